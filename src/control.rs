@@ -0,0 +1,196 @@
+use anyhow::{ bail, Context, Result };
+use serde::Serialize;
+use std::io::{ BufRead, BufReader, Write };
+use std::os::unix::net::UnixStream;
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use megamonic::system::System;
+use megamonic::system::bus::{ Bus, Event, Command, Toggle, Reply };
+
+use crate::output;
+
+// The main thread answers between two updates, this is in case it's stuck
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Processes in a dump without a number
+const TOP: usize = 10;
+
+// --control, a Unix socket for scripts. One command per line and one line back for each:
+// 'ok', the JSON that was asked for or 'error: ...'
+// The commands are sent on the Bus as Event::Command and done by whoever gets the events
+pub struct Control {
+    // Removed when megamonic exits
+    path: PathBuf,
+}
+
+impl Control {
+    // Start it after the System so the threads inherit the signal mask of the Event thread
+    pub fn start(path: &Path, bus: Bus, interactive: bool) -> Result<Self> {
+        let listener = output::serve::bind(path)?;
+
+        output::listen("Control", interactive, move || listener.accept().map(|(stream, _)| stream), move |stream| {
+            let bus = bus.clone();
+
+            // A script that keeps its connection open doesn't block the others
+            let _ = std::thread::Builder::new().name("Control client".to_string()).spawn(move || {
+                // A client that goes away isn't a problem
+                let _ = serve(&stream, &bus);
+            });
+        })?;
+
+        Ok(Self { path: path.to_path_buf() })
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn serve(stream: &UnixStream, bus: &Bus) -> Result<()> {
+    let mut writer = stream;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let answer = match parse(&line) {
+            Ok(command) => {
+                let (reply, answer) = Reply::new();
+
+                bus.send(Event::Command(command, reply));
+
+                answer.recv_timeout(TIMEOUT).unwrap_or_else(|_| Err(String::from("megamonic didn't answer")))
+            },
+            Err(err) => Err(format!("{:#}", err)),
+        };
+
+        match answer {
+            Ok(text) if text.is_empty() => writeln!(writer, "ok")?,
+            Ok(text) => writeln!(writer, "{}", text)?,
+            Err(err) => writeln!(writer, "error: {}", err)?,
+        }
+    }
+
+    Ok(())
+}
+
+pub fn parse(line: &str) -> Result<Command> {
+    let line = line.trim();
+    let (name, arg) = line.split_once(' ').map_or((line, ""), |(name, arg)| (name, arg.trim()));
+
+    let command = match (name, arg) {
+        ("filter", text) => Command::Filter(text.to_string()),
+
+        ("toggle", "smaps") => Command::Toggle(Toggle::Smaps),
+        ("toggle", "topmode") => Command::Toggle(Toggle::Topmode),
+        ("toggle", "all") => Command::Toggle(Toggle::All),
+        ("toggle", _) => bail!("toggle needs one of: smaps, topmode, all"),
+
+        ("sort", sort) => Command::Sort(sort.parse()?),
+
+        ("pause", "") => Command::Pause(true),
+        ("resume", "") => Command::Pause(false),
+
+        ("dump", "") => Command::Dump(TOP),
+        ("dump", top) => Command::Dump(top.parse().with_context(|| format!("'{}' isn't an amount of processes", top))?),
+
+        ("state", "") => Command::State,
+
+        _ => bail!("Unknown command '{}', expected one of: filter, toggle, sort, pause, resume, dump, state", line),
+    };
+
+    Ok(command)
+}
+
+#[derive(Serialize)]
+struct State {
+    // Only with the UI
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paused: Option::<bool>,
+
+    smaps: bool,
+    topmode: bool,
+    all: bool,
+    sort: &'static str,
+    filter: String,
+
+    // Milliseconds
+    frequency: u64,
+}
+
+// Do the parts of 'command' that only need the System. Returns what to send back
+// 'paused' is None without the UI, then Pause fails
+pub fn execute(system: &System, command: &Command, paused: Option::<bool>) -> Result<String> {
+    let config = system.config();
+
+    match command {
+        Command::Filter(text) => {
+            if let Ok(mut filter) = system.inputbuffer().lock() {
+                filter.clone_from(text);
+            }
+        },
+
+        Command::Toggle(toggle) => {
+            let toggle = match toggle {
+                Toggle::Smaps => &config.smaps,
+                Toggle::Topmode => &config.topmode,
+                Toggle::All => &config.all,
+            };
+
+            toggle.fetch_xor(true, Ordering::AcqRel);
+        },
+
+        Command::Sort(sort) => config.set_sort(*sort),
+
+        Command::Pause(_) => if paused.is_none() {
+            bail!("There's no UI to pause");
+        },
+
+        Command::Dump(top) => return output::json::to_string(&system.snapshot(), *top),
+
+        Command::State => {
+            let state = State {
+                paused,
+                smaps: config.smaps.load(Ordering::Relaxed),
+                topmode: config.topmode.load(Ordering::Relaxed),
+                all: config.all.load(Ordering::Relaxed),
+                sort: config.sort().name(),
+                filter: system.inputbuffer().lock().map(|filter| filter.clone()).unwrap_or_default(),
+                frequency: config.frequency.load(Ordering::Relaxed),
+            };
+
+            return Ok(serde_json::to_string(&state)?);
+        },
+    }
+
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use megamonic::Sort;
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse("filter  cargo build ").unwrap(), Command::Filter(String::from("cargo build")));
+        assert_eq!(parse("filter").unwrap(), Command::Filter(String::new()));
+        assert_eq!(parse("toggle smaps").unwrap(), Command::Toggle(Toggle::Smaps));
+        assert_eq!(parse("sort memory").unwrap(), Command::Sort(Sort::Memory));
+        assert_eq!(parse("resume").unwrap(), Command::Pause(false));
+        assert_eq!(parse("dump").unwrap(), Command::Dump(TOP));
+        assert_eq!(parse("dump 3").unwrap(), Command::Dump(3));
+
+        assert!(parse("toggle gpu").is_err());
+        assert!(parse("sort size").is_err());
+        assert!(parse("pause now").is_err());
+        assert!(parse("quit").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{ anyhow, bail, Result };
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use megamonic::system::{ System, Health };
+use megamonic::system::bus::{ Command, Event, Source };
+
+use crate::control;
+use crate::output::{ Outputs, Warmup };
+
+// Runs the collectors without a terminal until SIGINT or SIGTERM
+// Every Config.frequency the outputs get a snapshot, once every collector has something to show
+pub fn run(system: &System, events: Receiver<Event>, mut outputs: Outputs) -> Result<()> {
+    let mut log = Log::default();
+
+    loop {
+        let event = match outputs.recv(system, &events) {
+            Ok(Some(event)) => event,
+            Ok(None) => break,
+
+            // Whatever was reading stdout is gone, like 'head -n 1'
+            Err(err) if is_broken_pipe(&err) => break,
+            Err(err) => return Err(err),
+        };
+
+        if !log.event(system, event)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Wait until every collector has something to show, see Warmup
+// Returns false if it was told to quit first
+pub fn wait_for_data(system: &System, events: &Receiver<Event>, log: &mut Log) -> Result<bool> {
+    let mut warmup = Warmup::default();
+
+    while !warmup.ready(system) {
+        let Ok(event) = events.recv() else {
+            return Ok(false);
+        };
+
+        warmup.event(&event);
+
+        if !log.event(system, event)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+// Failing collectors and ones that recover are logged to stderr, that's where systemd looks
+#[derive(Default)]
+pub struct Log {
+    // Collectors that have failed and haven't worked since
+    failing: Vec::<Source>,
+}
+
+impl Log {
+    // Returns false if it's time to quit
+    pub fn event(&mut self, system: &System, event: Event) -> Result<bool> {
+        match event {
+            Event::Updated(source) => {
+                if let Some(idx) = self.failing.iter().position(|s| *s == source) {
+                    self.failing.swap_remove(idx);
+
+                    eprintln!("{} collector recovered", source.name());
+                }
+            },
+
+            // Without the Event thread SIGTERM can't be caught
+            Event::Error(Source::Input, err) => bail!("The Event thread stopped because of an error: {:?}", err),
+
+            // It's retried on its own with a backoff so this isn't too noisy
+            Event::Error(source, err) => {
+                if !self.failing.contains(&source) {
+                    self.failing.push(source);
+                }
+
+                eprintln!("{:#}, retrying in {:?}", err, retry(system, source));
+            },
+
+            Event::Quit => return Ok(false),
+
+            // From --control. The filter is only used by the UI
+            Event::Command(Command::Filter(_), reply) => reply.send(Err(anyhow!("There's no UI to filter"))),
+            Event::Command(command, reply) => reply.send(control::execute(system, &command, None)),
+
+            // No keys or terminal
+            Event::Key(_) | Event::Resize { .. } => (),
+        }
+
+        Ok(true)
+    }
+}
+
+fn retry(system: &System, source: Source) -> Duration {
+    match system.health(source) {
+        Health::Failed { retry, .. } => retry,
+        _ => Duration::ZERO,
+    }
+}
+
+fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|err| err.kind() == std::io::ErrorKind::BrokenPipe)
+}
@@ -0,0 +1,15 @@
+//! The collectors behind megamonic without the terminal UI.
+//!
+//! Start them with [`System::new`] and read the results with [`System::snapshot`]
+//! or one of the functions for a single collector.
+
+pub mod system;
+pub mod snapshot;
+
+// Used by the megamonic binary, not part of the library API
+#[doc(hidden)]
+pub mod syscall;
+
+pub use system::{ System, Config, IoBackend, Health, Sort, Filter };
+pub use system::bus::{ Bus, Event, Key, Source, Command, Toggle, Reply };
+pub use snapshot::*;
@@ -1,14 +1,21 @@
-#![feature(iter_intersperse)]
-
-use anyhow::{ bail, Context, Result };
+use anyhow::{ bail, ensure, Context, Result };
 use clap::{ Command, Arg, value_parser, ArgAction };
-use std::sync::atomic;
+use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::io::IsTerminal;
 
-mod system;
 mod ui;
 mod terminal;
-
+mod settings;
+mod headless;
+mod output;
+mod replay;
+mod remote;
+mod control;
+
+use megamonic::{ system, syscall };
 use system::System;
+use system::bus::{ self, Bus, Event, Source };
 use ui::Ui;
 
 // Customized version of https://github.com/sfackler/rust-log-panics
@@ -76,89 +83,396 @@ fn main() -> Result<()> {
         .arg(
             Arg::new("strftime")
                 .long("strftime")
-                .help("Strftime format string")
+                .help("Strftime format string. Default: %c")
                 .value_parser(clap::builder::NonEmptyStringValueParser::new())
-                .default_value("%c")
         )
         .arg(
             Arg::new("frequency")
                 .short('f')
                 .long("frequency")
-                .help("Sample frequency in milliseconds. Min: 1000, Max: 3000")
-                .value_parser(value_parser!(u64).range(1000..=3000))
-                .default_value("1000")
+                .help("Sample frequency in milliseconds. Min: 100, Max: 60000, Default: 1000. The config file can set one per collector")
+                .value_parser(value_parser!(u64).range(system::MIN_INTERVAL..=system::MAX_INTERVAL))
+        )
+        .arg(
+            Arg::new("io-backend")
+                .long("io-backend")
+                .help("How process information is read. 'auto' uses io_uring if it's available, otherwise pread. Default: auto")
+                .value_parser(["auto", "io_uring", "pread"])
+        )
+        .arg(
+            Arg::new("proc-root")
+                .long("proc-root")
+                .help("Where procfs is mounted. For example the host's /proc bind-mounted in a container. Default: /proc")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+        )
+        .arg(
+            Arg::new("sys-root")
+                .long("sys-root")
+                .help("Where sysfs is mounted. Sensors are always read from /sys by lm_sensors. Default: /sys")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .help("How the process list is sorted. Default: cpu")
+                .value_parser(["cpu", "memory", "pid", "name"])
         )
-        .after_help("\x1b[91mEnabling both smaps and all processes is ultra slow.\nEspecially if running as root.\x1b[0m\n\nThese buttons do things:\nq => exit.\na => toggle all processes.\ns => toggle smaps.\nt => toggle \"Top mode\"\nr => rebuild the UI incase its broken\nf => filter process list. [enter] or [esc] exits filter mode.\n[space] => pause the UI.")
+        .arg(
+            Arg::new("headless")
+                .long("headless")
+                .help("Run without a terminal, for systemd, cron or CI. SIGINT or SIGTERM stops it")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print one snapshot of everything as JSON and exit")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("json-stream")
+        )
+        .arg(
+            Arg::new("json-stream")
+                .long("json-stream")
+                .help("Print a snapshot as one line of JSON every sample. Runs headless")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("batch")
+                .long("batch")
+                .help("Print the overview, memory and process list as plain text every sample, like 'top -b'. Runs headless")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["json", "json-stream"])
+        )
+        .arg(
+            Arg::new("iterations")
+                .long("iterations")
+                .value_name("N")
+                .help("Exit after N frames of --batch")
+                .value_parser(value_parser!(u64).range(1..))
+                .requires("batch")
+        )
+        .arg(
+            Arg::new("top")
+                .long("top")
+                .help("Amount of processes in the JSON and --batch output. Default: 10")
+                .value_parser(value_parser!(usize))
+        )
+        .arg(
+            Arg::new("prometheus")
+                .long("prometheus")
+                .value_name("ADDR")
+                .help("Serve Prometheus metrics on http://ADDR/metrics, like 127.0.0.1:9188. Works with the UI and --headless")
+                .value_parser(value_parser!(SocketAddr))
+        )
+        .arg(
+            Arg::new("prometheus-processes")
+                .long("prometheus-processes")
+                .value_name("N")
+                .help("Export metrics for the N processes with the most CPU usage. Default: 0")
+                .value_parser(value_parser!(usize))
+        )
+        .arg(
+            Arg::new("influx")
+                .long("influx")
+                .value_name("ADDR")
+                .help("Send InfluxDB line protocol to ADDR every sample, like udp://127.0.0.1:8089 or tcp://telegraf:8094. Works with the UI and --headless")
+        )
+        .arg(
+            Arg::new("statsd")
+                .long("statsd")
+                .value_name("ADDR")
+                .help("Send StatsD gauges with tags to ADDR every sample, like udp://127.0.0.1:8125. Works with the UI and --headless")
+        )
+        .arg(
+            Arg::new("serve")
+                .long("serve")
+                .value_name("ADDR")
+                .help("Send every update to the clients that connect to ADDR, like 0.0.0.0:9189 or unix:/run/megamonic.sock. Works with the UI and --headless")
+                .value_parser(value_parser!(output::serve::Address))
+        )
+        .arg(
+            Arg::new("connect")
+                .long("connect")
+                .value_name("ADDR")
+                .help("Show a machine running --serve instead of this one. Give it more than once for a tab per machine")
+                .value_parser(value_parser!(output::serve::Address))
+                .action(ArgAction::Append)
+                .conflicts_with_all(["record", "replay", "serve", "headless", "json", "json-stream", "batch", "prometheus", "influx", "statsd"])
+        )
+        .arg(
+            Arg::new("control")
+                .long("control")
+                .value_name("PATH")
+                .help("Take commands like 'toggle smaps', 'sort memory' or 'dump' on a Unix socket at PATH. Works with the UI and --headless")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with("json")
+        )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .value_name("FILE")
+                .help("Write every update of every collector to FILE, to be shown with --replay later. Works with the UI and --headless")
+                .value_parser(value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .value_name("FILE")
+                .help("Show a file made with --record instead of this machine")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["record", "headless", "json", "json-stream", "batch", "prometheus", "influx", "statsd", "serve"])
+        )
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .help("Config file to use instead of $XDG_CONFIG_HOME/megamonic/config.toml. Options given here override it")
+                .value_parser(value_parser!(PathBuf))
+        )
+        .after_help("\x1b[91mEnabling both smaps and all processes is ultra slow.\nEspecially if running as root.\x1b[0m\n\nThese buttons do things:\nq => exit.\na => toggle all processes.\ns => toggle smaps.\nt => toggle \"Top mode\"\nr => rebuild the UI incase its broken\nf => filter process list. [enter] or [esc] exits filter mode.\no => next sort order.\n+ and - => sample twice as often or half as often.\nw => write the toggles, sort order and intervals to the config file.\n[space] => pause the UI.\n\nWith --connect:\nn and p => show the next or previous machine.\n\nWith --replay:\n[space] => pause or start over at the end.\n+ and - => play twice as fast or half as fast.\n, and . => jump a minute back or forward.\n\nThe keys can be changed in the config file.")
         .get_matches();
 
-    let freq: u64 = *options.get_one("frequency").unwrap();
+    let path = options.get_one::<PathBuf>("config").cloned().or_else(settings::path);
 
-    let config = system::Config {
-        smaps: atomic::AtomicBool::new(options.get_flag("smaps")),
-        topmode: atomic::AtomicBool::new(options.get_flag("topmode")),
-        all: atomic::AtomicBool::new(options.get_flag("all")),
-        frequency: atomic::AtomicU64::new(freq),
-        strftime_format: options.get_one::<String>("strftime").unwrap().clone()
+    // Without a home directory there's no config file
+    let file = match &path {
+        Some(path) => settings::Settings::load(path)?,
+        None => settings::Settings::default(),
     };
 
-    // Event channel
-    let (tx, rx) = std::sync::mpsc::channel();
+    // Commandline options override the file. The UI keeps 'file' so only the toggles are written back
+    let mut settings = file.clone();
+
+    // The flags can only turn things on, the keys can turn them off again
+    settings.processes.all |= options.get_flag("all");
+    settings.processes.smaps |= options.get_flag("smaps");
+    settings.processes.topmode |= options.get_flag("topmode");
+
+    if let Some(sort) = options.get_one::<String>("sort") {
+        settings.processes.sort.clone_from(sort);
+    }
+
+    if let Some(freq) = options.get_one::<u64>("frequency") {
+        settings.general.frequency = *freq;
+    }
+
+    if let Some(strftime) = options.get_one::<String>("strftime") {
+        settings.general.strftime.clone_from(strftime);
+    }
+
+    if let Some(io_backend) = options.get_one::<String>("io-backend") {
+        settings.general.io_backend.clone_from(io_backend);
+    }
+
+    if let Some(proc_root) = options.get_one::<String>("proc-root") {
+        settings.general.proc_root.clone_from(proc_root);
+    }
+
+    if let Some(sys_root) = options.get_one::<String>("sys-root") {
+        settings.general.sys_root.clone_from(sys_root);
+    }
+
+    let json = options.get_flag("json");
+    let top = options.get_one::<usize>("top").copied().unwrap_or(10);
+
+    // The JSON and batch frames go to stdout, the UI can't be there
+    let headless = options.get_flag("headless") || json || options.get_flag("json-stream") || options.get_flag("batch");
+
+    // A recording is shown instead of what the collectors see
+    let mut player = match options.get_one::<PathBuf>("replay") {
+        Some(path) => Some(replay::Player::open(path)?),
+        None => None,
+    };
+
+    let mut config = settings.config()?;
+    config.interactive = !headless;
+    config.signals = headless;
+
+    // The UI reads keys from stdin and draws on stdout
+    ensure!(headless || (std::io::stdin().is_terminal() && std::io::stdout().is_terminal()),
+        "stdin and stdout have to be a terminal, use --headless to run without one");
 
     // Initialize custom panic hook
     custom_panic_hook();
 
-    let system = System::new(config, tx)?;
+    // Subscribe before anything is started so no events are missed
+    let bus = Bus::default();
+    let events = bus.subscribe();
+
+    // Other machines are shown with --connect, each in its own System
+    let remote = match options.get_many::<output::serve::Address>("connect") {
+        Some(addresses) => Some(remote::Remote::connect(&addresses.cloned().collect::<Vec<_>>(), &settings, bus.clone())?),
+        None => None,
+    };
+
+    let local;
+    let system = match (&player, &remote) {
+        (Some(player), _) => {
+            local = player.system(config, bus)?;
+            &local
+        },
+        (None, Some(remote)) => remote.system(),
+        (None, None) => {
+            local = System::new(config, bus)?;
+            &local
+        },
+    };
+
+    // Made after the System so their threads inherit the signal mask of the Event thread
+    let _control = match options.get_one::<PathBuf>("control") {
+        Some(path) => Some(control::Control::start(path, system.bus().clone(), system.config().interactive)?),
+        None => None,
+    };
+
+    let mut outputs = Vec::<Box<dyn output::Output>>::new();
+
+    if options.get_flag("json-stream") {
+        outputs.push(Box::new(output::json::Stream::new(top)));
+    }
+
+    if options.get_flag("batch") {
+        let iterations = options.get_one::<u64>("iterations").copied();
+
+        outputs.push(Box::new(output::batch::Batch::new(system, top, iterations)));
+    }
+
+    if let Some(addr) = options.get_one::<SocketAddr>("prometheus") {
+        let processes = options.get_one::<usize>("prometheus-processes").copied().unwrap_or(0);
+
+        outputs.push(Box::new(output::prometheus::Prometheus::start(*addr, processes, system.config().interactive)?));
+    }
+
+    if let Some(addr) = options.get_one::<output::serve::Address>("serve") {
+        outputs.push(Box::new(output::serve::Server::start(addr, system.config().interactive)?));
+    }
 
-    // Check if there was any errors starting up
-    if !system.error.lock().unwrap().is_empty() {
-        for err in system.error.lock().expect("system.error lock couldn't be aquired!").iter() {
-            eprintln!("{:?}", err);
+    if let Some(path) = options.get_one::<PathBuf>("record") {
+        outputs.push(Box::new(output::record::Recorder::create(path)?));
+    }
+
+    // Tagged with the hostname so one server can take them from many machines
+    for (option, format) in [("influx", output::push::Format::Influx), ("statsd", output::push::Format::Statsd)] {
+        if let Some(target) = options.get_one::<String>(option) {
+            outputs.push(Box::new(output::push::Push::new(format, target, &system.hostinfo().hostname)?));
         }
+    }
+
+    // Alerts watch this machine, not a recording or other machines
+    let highlight = match (&player, &remote, settings.alerts.is_empty()) {
+        (None, None, false) => {
+            let alerts = output::alerts::Alerts::new(&settings.alerts, system)?;
+            let highlight = alerts.highlight();
+
+            outputs.push(Box::new(alerts));
+
+            Some(highlight)
+        },
+        _ => None,
+    };
 
-        bail!("An error occured while starting!");
+    let mut outputs = output::Outputs::new(outputs);
+
+    if json {
+        return output::json::once(system, &events, top);
+    }
+
+    if headless {
+        return headless::run(system, events, outputs);
+    }
+
+    let mut ui = Ui::new(system, terminal::gettermsize(), file, path)?;
+
+    if let Some(highlight) = highlight {
+        ui.set_highlight(highlight);
     }
 
-    let mut ui = Ui::new(&system, terminal::gettermsize())?;
+    if let Some(remote) = &remote {
+        ui.set_tabs(remote.tabs(), remote.current())?;
+    }
 
     // Main loop
-    for event in rx.iter() {
+    // Outputs like --prometheus are sampled while waiting
+    loop {
+        let event = match (&mut player, &remote) {
+            (Some(player), _) => player.recv(system, &events)?,
+            (None, Some(remote)) => remote.recv(&events),
+            (None, None) => outputs.recv(system, &events)?,
+        };
+
+        let Some(event) = event else {
+            break;
+        };
+
         match event {
             // Update UI element
-            1..=13 => {
-                if let Err(err) = ui.update(event).context("Error occured while updating UI") {
+            Event::Updated(source) => {
+                let mut result = ui.update(source);
+
+                // A host connecting or going away is shown with the clock
+                if let (Some(remote), Source::Time) = (&remote, source) {
+                    result = result.and_then(|_| ui.set_tabs(remote.tabs(), remote.current()));
+                }
+
+                if let Err(err) = result.context("Error occured while updating UI") {
                     ui.set_error(err);
 
                     break;
                 }
             },
 
-            // This is a error event incase one of the threads break.
-            99 => {
-                if let Some(err) = system.error.lock().expect("system.error lock couldn't be aquired!").pop() {
-                    ui.set_error(err);
+            Event::Key(key) => {
+                // The filter gets every key, otherwise some of them control the playback or pick the host
+                let playback = match &mut player {
+                    Some(player) if !ui.searching() => player.key(system, key, ui.keys()),
+                    _ => Ok(None),
+                };
+
+                let host = match &remote {
+                    Some(remote) if !ui.searching() => remote.key(key, ui.keys()).map(|host| (remote, host)),
+                    _ => None,
+                };
+
+                let result = playback.and_then(|status| match (status, host) {
+                    (Some(status), _) => ui.draw_status(&status).map(|_| false),
+                    (None, Some((remote, host))) => ui.switch(host)
+                        .and_then(|_| ui.set_tabs(remote.tabs(), remote.current()))
+                        .map(|_| false),
+                    (None, None) => ui.key(key),
+                });
+
+                match result.context("Error occured while handling a key press") {
+                    // Someone pressed Q
+                    Ok(true) => break,
+                    Ok(false) => (),
+                    Err(err) => {
+                        ui.set_error(err);
+
+                        break;
+                    },
                 }
-
-                bail!("Error event 99 occured!");
             },
 
-            // Pause
-            101 => ui.toggle_pause(),
+            // Without the Event thread there's no way to control anything
+            Event::Error(Source::Input, err) => {
+                ui.set_error(err);
 
-            // resize
-            105 => {
-                let (x, y) = terminal::gettermsize();
-                ui.terminal_size.x = x;
-                ui.terminal_size.y = y;
+                bail!("The Event thread stopped because of an error!");
+            },
 
-                if let Err(err) = ui.rebuild() {
+            // A collector failed, it's retried on its own. Show the error badge
+            Event::Error(source, _) => {
+                if let Err(err) = ui.update(source).context("Error occured while updating UI") {
                     ui.set_error(err);
 
                     break;
                 }
             },
 
-            // Rebuild UI if user pressed r
-            106 => {
+            // resize
+            Event::Resize { width, height } => {
+                ui.terminal_size.x = width;
+                ui.terminal_size.y = height;
+
                 if let Err(err) = ui.rebuild() {
                     ui.set_error(err);
 
@@ -166,11 +480,32 @@ fn main() -> Result<()> {
                 }
             },
 
-            // Exit - Someone pressed Q or a SIGINT was caught
-            255 => break,
+            // Exit - a SIGINT was caught
+            Event::Quit => break,
+
+            // From --control. What it changed is drawn after it's answered
+            Event::Command(command, reply) => {
+                let answer = control::execute(system, &command, Some(ui.paused()));
+
+                let result = match (&answer, command) {
+                    (Err(_), _) => Ok(()),
+                    (Ok(_), bus::Command::Pause(paused)) => {
+                        ui.set_paused(paused);
 
-            // If its something else we better exit just in case!
-            _ => break,
+                        if paused { Ok(()) } else { ui.rebuild() }
+                    },
+                    (Ok(_), bus::Command::Filter(_) | bus::Command::Toggle(_) | bus::Command::Sort(_)) => ui.rebuild(),
+                    (Ok(_), bus::Command::Dump(_) | bus::Command::State) => Ok(()),
+                };
+
+                reply.send(answer);
+
+                if let Err(err) = result.context("Error occured while handling a command") {
+                    ui.set_error(err);
+
+                    break;
+                }
+            },
         }
     }
 
@@ -0,0 +1,195 @@
+use anyhow::{ Context, Result };
+use std::collections::HashMap;
+use std::sync::{ atomic, mpsc::{ Receiver, RecvTimeoutError } };
+use std::time::{ Duration, Instant, SystemTime };
+
+use megamonic::Snapshot;
+use megamonic::system::{ System, Health, bus::{ Event, Source } };
+
+pub mod alerts;
+pub mod batch;
+pub mod json;
+pub mod prometheus;
+pub mod push;
+pub mod record;
+pub mod serve;
+
+// Slow clients and servers that are down can't hold up an output for longer than this
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long a listener waits before it accepts again after an error, like running out of fds
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(500);
+
+// Somewhere the data goes, next to the UI or when running headless
+// sample() is called every Config.frequency with the latest of every collector
+pub trait Output {
+    fn sample(&mut self, snapshot: &Snapshot) -> Result<()>;
+
+    // Called after every update of a collector, before the UI sees it
+    fn updated(&mut self, _system: &System, _source: Source) -> Result<()> {
+        Ok(())
+    }
+
+    // True once it doesn't want any more samples, then megamonic exits
+    fn finished(&self) -> bool {
+        false
+    }
+}
+
+// All the outputs and when they are sampled next
+pub struct Outputs {
+    outputs: Vec::<Box<dyn Output>>,
+    next: Instant,
+
+    // Nothing is sampled until it's ready so CPU usage and rates aren't sent out as zeroes
+    warmup: Option::<Warmup>,
+}
+
+impl Outputs {
+    pub fn new(outputs: Vec::<Box<dyn Output>>) -> Self {
+        Self {
+            outputs,
+            next: Instant::now(),
+            warmup: Some(Warmup::default()),
+        }
+    }
+
+    // Wait for the next Event, the outputs are sampled while waiting if it's time
+    // Returns None if every sender is gone or an output is finished
+    pub fn recv(&mut self, system: &System, events: &Receiver<Event>) -> Result<Option::<Event>> {
+        if self.outputs.is_empty() {
+            return Ok(events.recv().ok());
+        }
+
+        loop {
+            let result = match &self.warmup {
+                Some(_) => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                None => events.recv_timeout(self.next.saturating_duration_since(Instant::now())),
+            };
+
+            match result {
+                Ok(event) => {
+                    if let Some(warmup) = &mut self.warmup {
+                        warmup.event(&event);
+
+                        if warmup.ready(system) {
+                            self.warmup = None;
+                            self.next = Instant::now();
+                        }
+                    }
+
+                    if let Event::Updated(source) = event {
+                        for output in self.outputs.iter_mut() {
+                            output.updated(system, source)?;
+                        }
+                    }
+
+                    return Ok(Some(event));
+                },
+                Err(RecvTimeoutError::Timeout) => {
+                    self.sample(system)?;
+
+                    if self.outputs.iter().any(|output| output.finished()) {
+                        return Ok(None);
+                    }
+                },
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            }
+        }
+    }
+
+    fn sample(&mut self, system: &System) -> Result<()> {
+        let snapshot = system.snapshot();
+
+        for output in self.outputs.iter_mut() {
+            output.sample(&snapshot)?;
+        }
+
+        // The frequency can be changed while running
+        let frequency = Duration::from_millis(system.config().frequency.load(atomic::Ordering::Relaxed));
+
+        self.next += frequency;
+
+        // Don't try to catch up if it fell behind
+        if self.next < Instant::now() {
+            self.next = Instant::now() + frequency;
+        }
+
+        Ok(())
+    }
+}
+
+// Counts updates until every collector has something to show. The ones that report usage or rates
+// need two updates. Collectors that fail, aren't supported or are disabled aren't waited for
+#[derive(Default)]
+pub struct Warmup {
+    updates: HashMap::<Source, u32>,
+}
+
+impl Warmup {
+    pub fn event(&mut self, event: &Event) {
+        if let Event::Updated(source) = event {
+            *self.updates.entry(*source).or_default() += 1;
+        }
+    }
+
+    pub fn ready(&self, system: &System) -> bool {
+        Source::COLLECTORS.into_iter()
+            .filter(|source| *source != Source::Time)
+            .all(|source| {
+                let needed = match source {
+                    Source::Cpu | Source::Network | Source::Processes => 2,
+                    _ => 1,
+                };
+
+                match system.health(source) {
+                    Health::Starting | Health::Ok => self.updates.get(&source).copied().unwrap_or(0) >= needed,
+                    _ => true,
+                }
+            })
+    }
+}
+
+// Gives every connection 'accept' returns to 'handle', on a thread called 'name'
+// It's blocked in accept() so it's not joined. It stops when megamonic exits
+// Errors are printed unless it's 'interactive', then the UI is on the terminal
+pub fn listen<S, A, H>(name: &str, interactive: bool, mut accept: A, mut handle: H) -> Result<()>
+where
+    A: FnMut() -> std::io::Result<S> + Send + 'static,
+    H: FnMut(S) + Send + 'static,
+{
+    let thread = name.to_string();
+
+    std::thread::Builder::new().name(thread.clone()).spawn(move || {
+        // Only the first error in a row is printed
+        let mut failing = false;
+
+        loop {
+            match accept() {
+                Ok(stream) => {
+                    failing = false;
+                    handle(stream);
+                },
+                Err(err) => {
+                    if !failing && !interactive {
+                        eprintln!("{} can't accept connections: {}", thread, err);
+                    }
+
+                    failing = true;
+
+                    // The error is usually still there right away, don't spin on it
+                    std::thread::sleep(ACCEPT_BACKOFF);
+                },
+            }
+        }
+    }).with_context(|| format!("Couldn't spawn {} thread", name))?;
+
+    Ok(())
+}
+
+// Milliseconds since the Unix epoch
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64)
+}
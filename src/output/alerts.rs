@@ -0,0 +1,571 @@
+use anyhow::{ bail, ensure, Context, Result };
+use std::io::{ IsTerminal, Write };
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+use megamonic::Snapshot;
+use megamonic::system::{ self, System, Health, bus::Source };
+
+use super::Output;
+use crate::settings::{ Alert, Limit };
+
+// Sources of the alerts that are firing and want their panel highlighted, for the UI
+pub type Highlight = Arc<Mutex<Vec::<Source>>>;
+
+// What an alert can watch
+#[derive(Debug, Clone)]
+enum Metric {
+    // In %
+    Cpu,
+    Memory,
+    Swap,
+
+    Load1,
+    Load5,
+    Load15,
+
+    // In %, and degrees Celsius
+    Gpu,
+    GpuMemory,
+    GpuTemperature,
+
+    // Degrees Celsius of the sensors with a matching label, a trailing * matches anything
+    Sensor(system::Filter),
+
+    // Bytes of memory or CPU usage in % of the processes with this executable
+    ProcessMemory(String),
+    ProcessCpu(String),
+}
+
+impl std::str::FromStr for Metric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let metric = match s.split_once(':') {
+            Some((_, "")) => bail!("'{}' needs something after the ':'", s),
+            Some(("sensor", label)) => Metric::Sensor(system::Filter { include: vec![label.to_string()], exclude: Vec::new() }),
+            Some(("process", name)) => Metric::ProcessMemory(name.to_string()),
+            Some(("process_cpu", name)) => Metric::ProcessCpu(name.to_string()),
+
+            None if s == "cpu" => Metric::Cpu,
+            None if s == "memory" => Metric::Memory,
+            None if s == "swap" => Metric::Swap,
+            None if s == "load1" => Metric::Load1,
+            None if s == "load5" => Metric::Load5,
+            None if s == "load15" => Metric::Load15,
+            None if s == "gpu" => Metric::Gpu,
+            None if s == "gpu_memory" => Metric::GpuMemory,
+            None if s == "gpu_temperature" => Metric::GpuTemperature,
+
+            _ => bail!("Unknown metric '{}', expected one of: cpu, memory, swap, load1, load5, load15, gpu, gpu_memory, gpu_temperature, sensor:LABEL, process:NAME, process_cpu:NAME", s),
+        };
+
+        Ok(metric)
+    }
+}
+
+impl Metric {
+    // The panel that's highlighted
+    fn source(&self) -> Source {
+        match self {
+            Metric::Cpu => Source::Cpu,
+            Metric::Memory | Metric::Swap => Source::Memory,
+            Metric::Load1 | Metric::Load5 | Metric::Load15 => Source::Loadavg,
+            Metric::Gpu | Metric::GpuMemory | Metric::GpuTemperature => Source::Gpu,
+            Metric::Sensor(_) => Source::Sensors,
+            Metric::ProcessMemory(_) | Metric::ProcessCpu(_) => Source::Processes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Condition {
+    Above(f64),
+    Below(f64),
+
+    // A sensor is above its own critical temperature
+    AboveCrit,
+
+    // No process with the executable is running
+    Missing,
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Above(limit) => write!(f, "above {}", number(*limit)),
+            Condition::Below(limit) => write!(f, "below {}", number(*limit)),
+            Condition::AboveCrit => write!(f, "above crit"),
+            Condition::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Firing,
+    Resolved,
+}
+
+impl State {
+    fn name(self) -> &'static str {
+        match self {
+            State::Firing => "firing",
+            State::Resolved => "resolved",
+        }
+    }
+}
+
+// An [[alerts]] table that's been checked
+pub struct Rule {
+    name: String,
+    metric_name: String,
+    metric: Metric,
+    condition: Condition,
+    duration: Duration,
+    hysteresis: f64,
+
+    highlight: bool,
+    bell: bool,
+    log: Option::<PathBuf>,
+    command: Option::<String>,
+
+    // When the metric went past the limit, None if it isn't
+    since: Option::<Instant>,
+    firing: bool,
+
+    // From the last check, None if there was nothing to measure
+    value: Option::<f64>,
+}
+
+impl Rule {
+    pub fn new(alert: &Alert) -> Result<Self> {
+        let metric: Metric = alert.metric.parse()?;
+
+        let condition = match (&alert.above, &alert.below, alert.missing) {
+            (Some(Limit::Text(text)), None, false) if text == "crit" => match metric {
+                Metric::Sensor(_) => Condition::AboveCrit,
+                _ => bail!("only sensors have a crit value"),
+            },
+            (Some(limit), None, false) => Condition::Above(limit_value(limit)?),
+            (None, Some(limit), false) => Condition::Below(limit_value(limit)?),
+            (None, None, true) => match metric {
+                Metric::ProcessMemory(_) | Metric::ProcessCpu(_) => Condition::Missing,
+                _ => bail!("only processes can be missing"),
+            },
+            _ => bail!("it needs one of above, below or missing"),
+        };
+
+        ensure!(alert.hysteresis.is_finite() && alert.hysteresis >= 0.0, "hysteresis can't be negative");
+        ensure!(alert.highlight || alert.bell || alert.log.is_some() || alert.command.is_some(), "it needs at least one of highlight, bell, log or command");
+
+        Ok(Self {
+            name: if alert.name.is_empty() { alert.metric.clone() } else { alert.name.clone() },
+            metric_name: alert.metric.clone(),
+            metric,
+            condition,
+            duration: Duration::from_secs(alert.duration),
+            hysteresis: alert.hysteresis,
+            highlight: alert.highlight,
+            bell: alert.bell,
+            log: alert.log.clone(),
+            command: alert.command.clone(),
+            since: None,
+            firing: false,
+            value: None,
+        })
+    }
+
+    // The highest value of everything that matches, or the lowest with 'below'
+    fn value(&self, snapshot: &Snapshot) -> Option::<f64> {
+        let extreme = |a: f64, b: f64| match self.condition {
+            Condition::Below(_) => a.min(b),
+            _ => a.max(b),
+        };
+
+        let memory = &snapshot.memory;
+        let processes = &snapshot.processes.processes;
+
+        match &self.metric {
+            Metric::Cpu => Some(snapshot.cpu.usage as f64),
+            Metric::Memory => Some(percent(memory.used, memory.total)),
+            Metric::Swap => Some(percent(memory.swap_used, memory.swap_total)),
+            Metric::Load1 => Some(snapshot.loadavg.min1 as f64),
+            Metric::Load5 => Some(snapshot.loadavg.min5 as f64),
+            Metric::Load15 => Some(snapshot.loadavg.min15 as f64),
+            Metric::Gpu => snapshot.gpu.as_ref().map(|gpu| gpu.gpu_load as f64),
+            Metric::GpuMemory => snapshot.gpu.as_ref().map(|gpu| gpu.mem_load as f64),
+            Metric::GpuTemperature => snapshot.gpu.as_ref().map(|gpu| gpu.temperature as f64),
+
+            // Degrees above the critical temperature with 'crit'
+            Metric::Sensor(filter) => snapshot.sensors.sensors.iter()
+                .filter(|sensor| filter.matches(&sensor.label))
+                .filter_map(|sensor| match self.condition {
+                    Condition::AboveCrit => sensor.crit.map(|crit| sensor.temperature as f64 - crit as f64),
+                    _ => Some(sensor.temperature as f64),
+                })
+                .reduce(extreme),
+
+            Metric::ProcessMemory(name) => processes.iter()
+                .filter(|process| process.executable == *name)
+                .map(|process| process.pss.unwrap_or(process.rss) as f64)
+                .reduce(extreme),
+
+            Metric::ProcessCpu(name) => processes.iter()
+                .filter(|process| process.executable == *name)
+                .map(|process| process.cpu_usage as f64)
+                .reduce(extreme),
+        }
+    }
+
+    // Returns the new State if it fired or was resolved
+    pub fn check(&mut self, snapshot: &Snapshot, now: Instant) -> Option::<State> {
+        let value = self.value(snapshot);
+        self.value = value;
+
+        if self.firing {
+            // It has to come back past the hysteresis, so a value going up and down around the limit only fires once
+            let resolved = match (self.condition, value) {
+                (Condition::Missing, value) => value.is_some(),
+                (_, None) => true,
+                (Condition::Above(limit), Some(value)) => value <= limit - self.hysteresis,
+                (Condition::Below(limit), Some(value)) => value >= limit + self.hysteresis,
+                (Condition::AboveCrit, Some(value)) => value <= -self.hysteresis,
+            };
+
+            if resolved {
+                self.firing = false;
+                self.since = None;
+
+                return Some(State::Resolved);
+            }
+
+            return None;
+        }
+
+        let past = match (self.condition, value) {
+            (Condition::Missing, value) => value.is_none(),
+            (_, None) => false,
+            (Condition::Above(limit), Some(value)) => value > limit,
+            (Condition::Below(limit), Some(value)) => value < limit,
+            (Condition::AboveCrit, Some(value)) => value > 0.0,
+        };
+
+        if !past {
+            self.since = None;
+
+            return None;
+        }
+
+        let since = *self.since.get_or_insert(now);
+
+        if now.duration_since(since) >= self.duration {
+            self.firing = true;
+
+            return Some(State::Firing);
+        }
+
+        None
+    }
+
+    // Like 'cpu is 93.5, above 90'
+    fn describe(&self) -> String {
+        match (self.condition, self.value) {
+            (Condition::Missing, None) => format!("{} is missing", self.metric_name),
+            (Condition::AboveCrit, Some(value)) => format!("{} is {} from crit", self.metric_name, number(value)),
+            (_, Some(value)) => format!("{} is {}, {}", self.metric_name, number(value), self.condition),
+            (_, None) => format!("{} has no value", self.metric_name),
+        }
+    }
+}
+
+// The [[alerts]] in the config file, checked every sample
+pub struct Alerts {
+    rules: Vec::<Rule>,
+    highlight: Highlight,
+    hostname: String,
+    strftime_format: String,
+
+    // The UI is on the terminal, commands can't write to it
+    interactive: bool,
+
+    // The last update of the Processes collector worked. Until then an empty list
+    // doesn't mean a process is missing
+    processes: bool,
+}
+
+impl Alerts {
+    pub fn new(alerts: &[Alert], system: &System) -> Result<Self> {
+        let rules = alerts.iter()
+            .map(|alert| Rule::new(alert).with_context(|| format!("Invalid alert '{}'", alert.metric)))
+            .collect::<Result<Vec<_>>>()?;
+
+        if system.config().disabled.contains(&Source::Processes) {
+            if let Some(rule) = rules.iter().find(|rule| rule.condition == Condition::Missing) {
+                bail!("Invalid alert '{}': missing needs the processes panel, it's turned off", rule.metric_name);
+            }
+        }
+
+        // Set locale to whatever the environment is, for the time in the log
+        libc_strftime::tz_set();
+        libc_strftime::set_locale();
+
+        Ok(Self {
+            rules,
+            highlight: Highlight::default(),
+            hostname: system.hostinfo().hostname.clone(),
+            strftime_format: system.config().strftime_format.clone(),
+            interactive: system.config().interactive,
+            processes: false,
+        })
+    }
+
+    pub fn highlight(&self) -> Highlight {
+        Arc::clone(&self.highlight)
+    }
+
+    fn act(&self, rule: &Rule, state: State, time: u64) {
+        let description = rule.describe();
+
+        if rule.bell && state == State::Firing && std::io::stdout().is_terminal() {
+            let mut stdout = std::io::stdout().lock();
+            let _ = stdout.write_all(b"\x07");
+            let _ = stdout.flush();
+        }
+
+        // A log that can't be written isn't a reason to stop
+        if let Some(path) = &rule.log {
+            let time = libc_strftime::strftime_local(&self.strftime_format, time as i64);
+
+            if let Ok(mut file) = std::fs::OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{} {} {} {}: {}", time, self.hostname, rule.name, state.name(), description);
+            }
+        }
+
+        if let Some(command) = &rule.command {
+            let limit = match rule.condition {
+                Condition::Above(limit) | Condition::Below(limit) => number(limit),
+                Condition::AboveCrit => String::from("crit"),
+                Condition::Missing => String::new(),
+            };
+
+            let child = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("MEGAMONIC_ALERT", &rule.name)
+                .env("MEGAMONIC_STATE", state.name())
+                .env("MEGAMONIC_METRIC", &rule.metric_name)
+                .env("MEGAMONIC_VALUE", rule.value.map(number).unwrap_or_default())
+                .env("MEGAMONIC_CONDITION", rule.condition.to_string())
+                .env("MEGAMONIC_LIMIT", limit)
+                .env("MEGAMONIC_DESCRIPTION", &description)
+                .env("MEGAMONIC_HOST", &self.hostname)
+                .env("MEGAMONIC_TIME", time.to_string())
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(if self.interactive { Stdio::null() } else { Stdio::inherit() })
+                .spawn();
+
+            // Waited for on its own so a slow command doesn't hold anything up or become a zombie
+            if let Ok(mut child) = child {
+                let _ = std::thread::Builder::new().name("Alert".to_string()).spawn(move || {
+                    let _ = child.wait();
+                });
+            }
+        }
+    }
+
+    // The rules that fired or were resolved
+    fn check(&mut self, snapshot: &Snapshot, now: Instant) -> Vec::<(usize, State)> {
+        let mut changed = Vec::<(usize, State)>::new();
+
+        for (idx, rule) in self.rules.iter_mut().enumerate() {
+            // Without a working Processes collector it can't tell, it stays how it was
+            if rule.condition == Condition::Missing && !self.processes {
+                rule.since = None;
+                continue;
+            }
+
+            if let Some(state) = rule.check(snapshot, now) {
+                changed.push((idx, state));
+            }
+        }
+
+        changed
+    }
+}
+
+impl Output for Alerts {
+    fn sample(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let changed = self.check(snapshot, Instant::now());
+
+        for (idx, state) in &changed {
+            self.act(&self.rules[*idx], *state, snapshot.time);
+        }
+
+        if changed.iter().any(|(idx, _)| self.rules[*idx].highlight) {
+            let mut sources = Vec::<Source>::new();
+
+            for rule in self.rules.iter().filter(|rule| rule.firing && rule.highlight) {
+                if !sources.contains(&rule.metric.source()) {
+                    sources.push(rule.metric.source());
+                }
+            }
+
+            if let Ok(mut highlight) = self.highlight.lock() {
+                *highlight = sources;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn updated(&mut self, system: &System, source: Source) -> Result<()> {
+        if source == Source::Processes {
+            self.processes = matches!(system.health(Source::Processes), Health::Ok);
+        }
+
+        Ok(())
+    }
+}
+
+// A number or a size like 4G, 512M or 100Kb. Sizes are in bytes, a kilobyte is 1024 bytes
+fn limit_value(limit: &Limit) -> Result<f64> {
+    let text = match limit {
+        Limit::Number(number) if number.is_finite() => return Ok(*number),
+        Limit::Number(number) => bail!("{} isn't a limit", number),
+        Limit::Text(text) => text.trim(),
+    };
+
+    let split = text.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let number: f64 = number.trim().parse().with_context(|| format!("'{}' isn't a number or a size like \"4G\"", text))?;
+
+    let exponent = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" => 1,
+        "m" | "mb" => 2,
+        "g" | "gb" => 3,
+        "t" | "tb" => 4,
+        _ => bail!("Unknown unit '{}' in '{}', expected one of: K, M, G, T", unit, text),
+    };
+
+    Ok(number * 1024_f64.powi(exponent))
+}
+
+fn percent(used: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => used as f64 / total as f64 * 100.0,
+    }
+}
+
+// Without a fraction if there isn't one, like bytes
+fn number(value: f64) -> String {
+    match value.fract() == 0.0 {
+        true => format!("{:.0}", value),
+        false => format!("{:.1}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use megamonic::{ ProcessSnapshot, SensorSnapshot };
+
+    fn rule(text: &str) -> Rule {
+        Rule::new(&toml::from_str::<Alert>(text).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut rule = rule(r#"
+            metric = "cpu"
+            above = 90
+            for = 30
+            hysteresis = 5
+            highlight = true
+        "#);
+
+        let start = Instant::now();
+        let mut snapshot = Snapshot::default();
+        let mut check = |usage: f32, secs: u64| {
+            snapshot.cpu.usage = usage;
+            rule.check(&snapshot, start + Duration::from_secs(secs))
+        };
+
+        // Not for long enough, then it starts over
+        assert_eq!(check(95.0, 0), None);
+        assert_eq!(check(80.0, 20), None);
+        assert_eq!(check(95.0, 25), None);
+        assert_eq!(check(95.0, 55), Some(State::Firing));
+
+        // Inside the hysteresis it stays
+        assert_eq!(check(88.0, 60), None);
+        assert_eq!(check(85.0, 65), Some(State::Resolved));
+    }
+
+    #[test]
+    fn sensors_and_processes() {
+        let mut snapshot = Snapshot::default();
+
+        let mut sensor = SensorSnapshot::default();
+        sensor.label = String::from("Core 0");
+        sensor.temperature = 101;
+        sensor.crit = Some(100);
+        snapshot.sensors.sensors.push(sensor);
+
+        let mut process = ProcessSnapshot::default();
+        process.executable = String::from("postgres");
+        process.rss = 5 << 30;
+        snapshot.processes.processes.push(process);
+
+        let now = Instant::now();
+
+        let mut crit = rule("metric = \"sensor:Core*\"\nabove = \"crit\"\nbell = true");
+        assert_eq!(crit.check(&snapshot, now), Some(State::Firing));
+
+        let mut size = rule("metric = \"process:postgres\"\nabove = \"4G\"\nbell = true");
+        assert_eq!(size.check(&snapshot, now), Some(State::Firing));
+        assert_eq!(size.describe(), "process:postgres is 5368709120, above 4294967296");
+
+        let mut missing = rule("metric = \"process:redis\"\nmissing = true\nbell = true");
+        assert_eq!(missing.check(&snapshot, now), Some(State::Firing));
+        assert_eq!(missing.describe(), "process:redis is missing");
+    }
+
+    #[test]
+    fn missing_without_processes() {
+        let mut alerts = Alerts {
+            rules: vec![rule("metric = \"process:redis\"\nmissing = true\nbell = true")],
+            highlight: Highlight::default(),
+            hostname: String::new(),
+            strftime_format: String::new(),
+            interactive: false,
+            processes: false,
+        };
+
+        // A disabled or failing collector leaves the list empty, that doesn't mean it's gone
+        let snapshot = Snapshot::default();
+        let now = Instant::now();
+        assert!(alerts.check(&snapshot, now).is_empty());
+
+        alerts.processes = true;
+        assert_eq!(alerts.check(&snapshot, now), vec![(0, State::Firing)]);
+    }
+
+    #[test]
+    fn invalid() {
+        let invalid = |text: &str| Rule::new(&toml::from_str::<Alert>(text).unwrap()).is_err();
+
+        assert!(invalid("metric = \"cpu\"\nabove = 90"));
+        assert!(invalid("metric = \"cpu\"\nabove = \"crit\"\nbell = true"));
+        assert!(invalid("metric = \"cpu\"\nmissing = true\nbell = true"));
+        assert!(invalid("metric = \"cpu\"\nabove = 90\nbelow = 10\nbell = true"));
+        assert!(invalid("metric = \"disk\"\nabove = 90\nbell = true"));
+        assert!(invalid("metric = \"process:x\"\nabove = \"4Q\"\nbell = true"));
+    }
+}
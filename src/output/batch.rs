@@ -0,0 +1,159 @@
+use anyhow::{ Context, Result };
+use std::fmt::Write as fmtWrite;
+use std::io::Write as ioWrite;
+use std::sync::Arc;
+
+use megamonic::{ Snapshot, ProcessSnapshot };
+use megamonic::system::{ System, Config, Sort };
+
+use super::Output;
+use crate::ui::convert_with_padding;
+
+// --batch, a plain text frame on stdout every sample like 'top -b'
+// Nothing is colored or cursor addressed so it can go in a log file
+pub struct Batch {
+    config: Arc<Config>,
+    hostname: String,
+
+    // Amount of processes in every frame
+    top: usize,
+
+    // Frames left to print, None is until it's stopped
+    iterations: Option::<u64>,
+
+    text: String,
+}
+
+impl Batch {
+    pub fn new(system: &System, top: usize, iterations: Option::<u64>) -> Self {
+        // Set locale to whatever the environment is, for the time
+        libc_strftime::tz_set();
+        libc_strftime::set_locale();
+
+        Self {
+            config: Arc::clone(system.config()),
+            hostname: system.hostinfo().hostname.clone(),
+            top,
+            iterations,
+            text: String::with_capacity(4096),
+        }
+    }
+}
+
+impl Output for Batch {
+    fn sample(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.text.clear();
+
+        let time = libc_strftime::strftime_local(&self.config.strftime_format, snapshot.time as i64);
+
+        frame(&mut self.text, snapshot, &self.hostname, &time, self.config.sort(), self.top);
+
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(self.text.as_bytes()).context("Can't write to stdout")?;
+        stdout.flush().context("Can't write to stdout")?;
+
+        if let Some(iterations) = &mut self.iterations {
+            *iterations = iterations.saturating_sub(1);
+        }
+
+        Ok(())
+    }
+
+    fn finished(&self) -> bool {
+        self.iterations == Some(0)
+    }
+}
+
+fn frame(text: &mut String, snapshot: &Snapshot, hostname: &str, time: &str, sort: Sort, top: usize) {
+    let mut size = String::new();
+    let mut total = String::new();
+
+    let _ = writeln!(text, "megamonic - {} - {}", hostname, time);
+
+    let cpu = &snapshot.cpu;
+    let loadavg = &snapshot.loadavg;
+    let _ = writeln!(text, "CPU:  {:>5.1}%  {} cores  {}  Load: {:.2} {:.2} {:.2}",
+        cpu.usage, cpu.cores, cpu.governor, loadavg.min1, loadavg.min5, loadavg.min15);
+
+    // Same letters as top, 'gu' is guest and guest_nice
+    let b = &cpu.breakdown;
+    let _ = writeln!(text, "      {:.1} us, {:.1} ni, {:.1} sy, {:.1} id, {:.1} wa, {:.1} hi, {:.1} si, {:.1} st, {:.1} gu",
+        b.user, b.nice, b.system, b.idle, b.iowait, b.irq, b.softirq, b.steal, b.guest + b.guest_nice);
+
+    let memory = &snapshot.memory;
+    for (name, used, all) in [("Mem: ", memory.used, memory.total), ("Swap:", memory.swap_used, memory.swap_total)] {
+        convert_with_padding(&mut size, used);
+        convert_with_padding(&mut total, all);
+
+        let _ = writeln!(text, "{} {:>5.1}%  {} of {}", name, percent(used, all), size, total);
+    }
+
+    let _ = writeln!(text, "\n{:>7} {:>6} {:>8}  COMMAND", "PID", "CPU%", "MEMORY");
+
+    let mut processes: Vec::<&ProcessSnapshot> = snapshot.processes.processes.iter().collect();
+
+    // The snapshot is in the Cpu order
+    match sort {
+        Sort::Cpu => (),
+        Sort::Memory => processes.sort_by_key(|process| std::cmp::Reverse(process.pss.unwrap_or(process.rss))),
+        Sort::Pid => processes.sort_by_key(|process| process.pid),
+        Sort::Name => processes.sort_by(|a, b| a.executable.cmp(&b.executable).then(a.pid.cmp(&b.pid))),
+    }
+
+    for process in processes.into_iter().take(top) {
+        convert_with_padding(&mut size, process.pss.unwrap_or(process.rss));
+
+        let _ = write!(text, "{:>7} {:>6.1} {:>8}  {}", process.pid, process.cpu_usage, size, process.executable);
+
+        if !process.cmdline.is_empty() {
+            text.push(' ');
+
+            // One line per process, even if there are newlines in the arguments
+            text.extend(process.cmdline.trim_end().chars().map(|c| if c.is_control() { ' ' } else { c }));
+        }
+
+        text.push('\n');
+    }
+
+    // Between frames
+    text.push('\n');
+}
+
+fn percent(used: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => used as f64 / total as f64 * 100.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text() {
+        let mut snapshot = Snapshot::default();
+
+        for (pid, rss) in [(42, 2048), (7, 4096)] {
+            let mut process = ProcessSnapshot::default();
+            process.pid = pid;
+            process.executable = format!("proc{}", pid);
+            process.cmdline = String::from("--line\nbreak ");
+            process.rss = rss;
+
+            snapshot.processes.processes.push(process);
+        }
+
+        let mut text = String::new();
+        frame(&mut text, &snapshot, "box", "now", Sort::Memory, 10);
+
+        assert!(text.starts_with("megamonic - box - now\n"), "{}", text);
+        assert!(text.contains("Swap:   0.0%"), "{}", text);
+        assert!(!text.contains('\x1b'), "{}", text);
+
+        // Most memory first
+        let pids: Vec::<&str> = text.lines().skip(7).filter_map(|line| line.split_whitespace().next()).collect();
+        assert_eq!(pids, ["7", "42"]);
+        assert!(text.contains(" proc7 --line break\n"), "{}", text);
+    }
+}
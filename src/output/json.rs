@@ -0,0 +1,107 @@
+use anyhow::{ Context, Result };
+use serde::Serialize;
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+
+use megamonic::{ Snapshot, CpuSnapshot, MemorySnapshot, LoadavgSnapshot, NetworkSnapshot, SensorsSnapshot, ProcessSnapshot, GpuSnapshot };
+use megamonic::system::{ System, bus::Event };
+
+use super::{ Output, timestamp };
+use crate::headless;
+
+// One line of output. The names come from the snapshot types so they stay the same
+#[derive(Serialize)]
+struct Record<'a> {
+    // Milliseconds since the Unix epoch
+    timestamp: u64,
+
+    cpu: &'a CpuSnapshot,
+    memory: &'a MemorySnapshot,
+    loadavg: &'a LoadavgSnapshot,
+    network: &'a NetworkSnapshot,
+    sensors: &'a SensorsSnapshot,
+
+    // The first 'top' processes by CPU usage
+    processes: &'a [ProcessSnapshot],
+
+    // null without an Nvidia GPU
+    gpu: &'a Option::<GpuSnapshot>,
+}
+
+impl <'a> Record <'a> {
+    fn new(snapshot: &'a Snapshot, top: usize) -> Self {
+        let processes = &snapshot.processes.processes;
+
+        Self {
+            timestamp: timestamp(),
+            cpu: &snapshot.cpu,
+            memory: &snapshot.memory,
+            loadavg: &snapshot.loadavg,
+            network: &snapshot.network,
+            sensors: &snapshot.sensors,
+            processes: &processes[..top.min(processes.len())],
+            gpu: &snapshot.gpu,
+        }
+    }
+}
+
+// The same as one line of --json-stream, without the newline
+pub fn to_string(snapshot: &Snapshot, top: usize) -> Result<String> {
+    Ok(serde_json::to_string(&Record::new(snapshot, top))?)
+}
+
+fn write(snapshot: &Snapshot, top: usize) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+
+    serde_json::to_writer(&mut stdout, &Record::new(snapshot, top)).context("Can't write JSON to stdout")?;
+    writeln!(stdout).context("Can't write JSON to stdout")?;
+
+    Ok(())
+}
+
+// --json, print one snapshot once every collector has something to show
+pub fn once(system: &System, events: &Receiver<Event>, top: usize) -> Result<()> {
+    if headless::wait_for_data(system, events, &mut headless::Log::default())? {
+        write(&system.snapshot(), top)?;
+    }
+
+    Ok(())
+}
+
+// --json-stream, one line per sample
+pub struct Stream {
+    top: usize,
+}
+
+impl Stream {
+    pub fn new(top: usize) -> Self {
+        Self { top }
+    }
+}
+
+impl Output for Stream {
+    fn sample(&mut self, snapshot: &Snapshot) -> Result<()> {
+        write(snapshot, self.top)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_names() {
+        let snapshot = Snapshot::default();
+
+        let value = serde_json::to_value(Record::new(&snapshot, 10)).unwrap();
+        let object = value.as_object().unwrap();
+
+        let keys: Vec::<&str> = object.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["cpu", "gpu", "loadavg", "memory", "network", "processes", "sensors", "timestamp"]);
+
+        assert!(object["gpu"].is_null());
+        assert!(object["network"]["elapsed_ms"].is_u64());
+        assert!(object["memory"]["swap_used"].is_u64());
+        assert!(object["cpu"]["usage"].is_f64());
+    }
+}
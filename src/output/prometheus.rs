@@ -0,0 +1,259 @@
+use anyhow::{ Context, Result };
+use std::fmt::Write as fmtWrite;
+use std::io::{ BufRead, BufReader, Write as ioWrite };
+use std::net::{ SocketAddr, TcpListener, TcpStream };
+use std::sync::{ Arc, Mutex };
+
+use megamonic::Snapshot;
+use megamonic::system::CLK_TCK;
+
+use super::{ Output, TIMEOUT };
+
+// --prometheus, serves the last sample on /metrics in the text exposition format
+pub struct Prometheus {
+    // Amount of processes with their own metrics, the ones with the most CPU usage
+    processes: usize,
+
+    // What /metrics returns. Rendered in sample() so scrapes don't have to lock the collectors
+    metrics: Arc<Mutex<String>>,
+}
+
+impl Prometheus {
+    // Fails if 'addr' can't be listened on
+    pub fn start(addr: SocketAddr, processes: usize, interactive: bool) -> Result<Self> {
+        let listener = TcpListener::bind(addr).with_context(|| format!("Can't listen on {}", addr))?;
+
+        let metrics = Arc::new(Mutex::new(String::new()));
+        let internal = Arc::clone(&metrics);
+
+        super::listen("Prometheus", interactive, move || listener.accept().map(|(stream, _)| stream), move |stream| {
+            let metrics = Arc::clone(&internal);
+
+            // A client that sends its request slowly doesn't hold up the other scrapes
+            let _ = std::thread::Builder::new().name("Prometheus client".to_string()).spawn(move || {
+                // A client that goes away isn't a problem
+                let _ = serve(stream, &metrics);
+            });
+        })?;
+
+        Ok(Self { processes, metrics })
+    }
+}
+
+impl Output for Prometheus {
+    fn sample(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let text = render(snapshot, self.processes);
+
+        if let Ok(mut metrics) = self.metrics.lock() {
+            *metrics = text;
+        }
+
+        Ok(())
+    }
+}
+
+// One request per connection
+fn serve(stream: TcpStream, metrics: &Mutex<String>) -> Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+
+    // GET /metrics HTTP/1.1
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    // Skip the headers, nothing in them matters
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut parts = request.split_ascii_whitespace();
+
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.lock().map(|m| m.clone()).unwrap_or_default()),
+        (Some("GET"), Some("/")) => ("200 OK", String::from("megamonic exporter, the metrics are on /metrics\n")),
+        (Some("GET"), _) => ("404 Not Found", String::from("Not found\n")),
+        _ => ("405 Method Not Allowed", String::from("Only GET is supported\n")),
+    };
+
+    let mut stream = &stream;
+    write!(stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body
+    )?;
+
+    Ok(())
+}
+
+// Adds '# HELP' and '# TYPE' the first time a metric is written
+struct Writer {
+    text: String,
+    last: &'static str,
+}
+
+impl Writer {
+    fn metric(&mut self, name: &'static str, kind: &str, help: &str, labels: &[(&str, &str)], value: f64) {
+        if self.last != name {
+            let _ = writeln!(self.text, "# HELP megamonic_{} {}\n# TYPE megamonic_{} {}", name, help, name, kind);
+            self.last = name;
+        }
+
+        let _ = write!(self.text, "megamonic_{}", name);
+
+        for (idx, (label, value)) in labels.iter().enumerate() {
+            let _ = write!(self.text, "{}{}=\"", if idx == 0 { '{' } else { ',' }, label);
+
+            // Backslash, double quote and newline have to be escaped
+            for c in value.chars() {
+                match c {
+                    '\\' => self.text.push_str("\\\\"),
+                    '"' => self.text.push_str("\\\""),
+                    '\n' => self.text.push_str("\\n"),
+                    _ => self.text.push(c),
+                }
+            }
+
+            self.text.push('"');
+        }
+
+        if !labels.is_empty() {
+            self.text.push('}');
+        }
+
+        let _ = writeln!(self.text, " {}", value);
+    }
+}
+
+fn render(snapshot: &Snapshot, processes: usize) -> String {
+    let mut w = Writer { text: String::with_capacity(4096), last: "" };
+
+    let cpu = &snapshot.cpu;
+    w.metric("cpu_usage_percent", "gauge", "CPU usage of all cores since the last sample", &[], cpu.usage as f64);
+    w.metric("cpu_cores", "gauge", "Amount of cores", &[], cpu.cores as f64);
+
+    let times = &cpu.times;
+    for (mode, ticks) in [
+        ("user", times.user),
+        ("nice", times.nice),
+        ("system", times.system),
+        ("idle", times.idle),
+        ("iowait", times.iowait),
+        ("irq", times.irq),
+        ("softirq", times.softirq),
+        ("steal", times.steal),
+    ] {
+        w.metric("cpu_seconds_total", "counter", "Time all cores spent in each mode since boot", &[("mode", mode)], ticks as f64 / CLK_TCK);
+    }
+
+    // Like node_exporter it's separate since it's already in 'user' and 'nice'
+    for (mode, ticks) in [("user", times.guest), ("nice", times.guest_nice)] {
+        w.metric("cpu_guest_seconds_total", "counter", "Time all cores spent running virtual machines since boot", &[("mode", mode)], ticks as f64 / CLK_TCK);
+    }
+
+    // Always 1, for grouping the other per core metrics by socket, NUMA node or kind of core
+    for (core, id) in cpu.per_core.iter().zip(cpu.per_core.iter().map(|core| core.id.to_string())) {
+        w.metric("cpu_topology_info", "gauge", "Where the core is", &[
+            ("cpu", id.as_str()),
+            ("package", core.package.to_string().as_str()),
+            ("core", core.core_id.to_string().as_str()),
+            ("node", core.node.to_string().as_str()),
+            ("kind", core.kind.as_str()),
+        ], 1.0);
+    }
+
+    // Only the cores with cpufreq, it's in kHz
+    let cores: Vec::<_> = cpu.per_core.iter().filter(|core| core.frequency > 0).collect();
+    let ids: Vec::<String> = cores.iter().map(|core| core.id.to_string()).collect();
+
+    for (core, id) in cores.iter().zip(&ids) {
+        w.metric("cpu_frequency_hertz", "gauge", "Current frequency of the core", &[("cpu", id.as_str())], core.frequency as f64 * 1000.0);
+    }
+    for (core, id) in cores.iter().zip(&ids) {
+        w.metric("cpu_frequency_min_hertz", "gauge", "Lowest frequency the core is allowed to run at", &[("cpu", id.as_str())], core.min_frequency as f64 * 1000.0);
+    }
+    for (core, id) in cores.iter().zip(&ids) {
+        w.metric("cpu_frequency_max_hertz", "gauge", "Highest frequency the core is allowed to run at", &[("cpu", id.as_str())], core.max_frequency as f64 * 1000.0);
+    }
+
+    let memory = &snapshot.memory;
+    w.metric("memory_total_bytes", "gauge", "Total memory", &[], memory.total as f64);
+    w.metric("memory_available_bytes", "gauge", "Memory available without swapping", &[], memory.free as f64);
+    w.metric("memory_used_bytes", "gauge", "Memory in use", &[], memory.used as f64);
+    w.metric("swap_total_bytes", "gauge", "Total swap", &[], memory.swap_total as f64);
+    w.metric("swap_free_bytes", "gauge", "Unused swap", &[], memory.swap_free as f64);
+    w.metric("swap_used_bytes", "gauge", "Swap in use", &[], memory.swap_used as f64);
+
+    let loadavg = &snapshot.loadavg;
+    w.metric("load1", "gauge", "1 minute load average", &[], loadavg.min1 as f64);
+    w.metric("load5", "gauge", "5 minute load average", &[], loadavg.min5 as f64);
+    w.metric("load15", "gauge", "15 minute load average", &[], loadavg.min15 as f64);
+
+    let interfaces = &snapshot.network.interfaces;
+    for interface in interfaces {
+        let name = interface.name.trim_end_matches(':');
+        w.metric("network_receive_bytes_total", "counter", "Bytes received since the interface came up", &[("interface", name)], interface.total_recv as f64);
+    }
+    for interface in interfaces {
+        let name = interface.name.trim_end_matches(':');
+        w.metric("network_transmit_bytes_total", "counter", "Bytes sent since the interface came up", &[("interface", name)], interface.total_sent as f64);
+    }
+
+    for sensor in &snapshot.sensors.sensors {
+        w.metric("sensor_temperature_celsius", "gauge", "Temperature from lm_sensors", &[("sensor", sensor.label.as_str())], sensor.temperature as f64);
+    }
+
+    if let Some(gpu) = &snapshot.gpu {
+        w.metric("gpu_temperature_celsius", "gauge", "Temperature of the first Nvidia GPU", &[], gpu.temperature as f64);
+        w.metric("gpu_utilization_percent", "gauge", "GPU load", &[], gpu.gpu_load as f64);
+        w.metric("gpu_memory_utilization_percent", "gauge", "GPU memory controller load", &[], gpu.mem_load as f64);
+        w.metric("gpu_memory_used_percent", "gauge", "GPU memory in use", &[], gpu.mem_used as f64);
+    }
+
+    // Limited to the busiest ones so the amount of series stays the same
+    let top = &snapshot.processes.processes[..processes.min(snapshot.processes.processes.len())];
+    let pids: Vec::<String> = top.iter().map(|process| process.pid.to_string()).collect();
+
+    for (process, pid) in top.iter().zip(&pids) {
+        w.metric("process_cpu_usage_percent", "gauge", "CPU usage since the last sample", &[("pid", pid.as_str()), ("executable", process.executable.as_str())], process.cpu_usage as f64);
+    }
+    for (process, pid) in top.iter().zip(&pids) {
+        w.metric("process_cpu_seconds_total", "counter", "CPU time since the process started", &[("pid", pid.as_str()), ("executable", process.executable.as_str())], process.cpu_time as f64 / CLK_TCK);
+    }
+    for (process, pid) in top.iter().zip(&pids) {
+        w.metric("process_memory_bytes", "gauge", "PSS if smaps is enabled, otherwise RSS", &[("pid", pid.as_str()), ("executable", process.executable.as_str())], process.pss.unwrap_or(process.rss) as f64);
+    }
+
+    w.text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposition_format() {
+        let mut w = Writer { text: String::new(), last: "" };
+
+        w.metric("sensor_temperature_celsius", "gauge", "Temperature", &[("sensor", "a\"b\\c")], 45.0);
+        w.metric("sensor_temperature_celsius", "gauge", "Temperature", &[("sensor", "d")], 50.5);
+
+        assert_eq!(w.text, concat!(
+            "# HELP megamonic_sensor_temperature_celsius Temperature\n",
+            "# TYPE megamonic_sensor_temperature_celsius gauge\n",
+            "megamonic_sensor_temperature_celsius{sensor=\"a\\\"b\\\\c\"} 45\n",
+            "megamonic_sensor_temperature_celsius{sensor=\"d\"} 50.5\n",
+        ));
+    }
+
+    #[test]
+    fn every_metric_has_a_type() {
+        let text = render(&Snapshot::default(), 10);
+
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            assert!(text.contains(&format!("# TYPE {} ", name)), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use anyhow::{ bail, Context, Result };
+use std::fmt::Write as fmtWrite;
+use std::io::Write as ioWrite;
+use std::net::{ SocketAddr, TcpStream, ToSocketAddrs, UdpSocket };
+use std::sync::mpsc::{ self, SyncSender };
+
+use megamonic::Snapshot;
+
+use super::{ Output, TIMEOUT, timestamp };
+
+// Lines are put in datagrams of at most this many bytes so they aren't fragmented
+const MAX_DATAGRAM: usize = 1400;
+
+// Samples waiting to be sent. New ones are dropped while the server is down
+const QUEUE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // measurement,tag=value field=1i,field=2.5 timestamp
+    Influx,
+
+    // Telegraf's StatsD with tags: megamonic.measurement.field,tag=value:2.5|g
+    Statsd,
+}
+
+enum Transport {
+    Udp(UdpSocket),
+
+    // Connected when it's needed so the server can be restarted
+    Tcp { addr: SocketAddr, stream: Option::<TcpStream> },
+}
+
+// --influx and --statsd, sends every sample to something like Telegraf or InfluxDB
+// Connecting and sending is done by its own thread so a server that's down doesn't hold up the UI
+pub struct Push {
+    format: Format,
+
+    // Every metric is tagged with it
+    host: String,
+
+    // The rendered samples go to the thread
+    samples: SyncSender<String>,
+}
+
+impl Push {
+    // 'target' is udp://host:port, tcp://host:port or host:port for UDP
+    pub fn new(format: Format, target: &str, host: &str) -> Result<Self> {
+        let (tcp, addr) = match target.split_once("://") {
+            Some(("udp", addr)) => (false, addr),
+            Some(("tcp", addr)) => (true, addr),
+            Some((scheme, _)) => bail!("Unknown protocol '{}' in {}, use udp or tcp", scheme, target),
+            None => (false, target),
+        };
+
+        let addr = addr.to_socket_addrs()
+            .with_context(|| format!("Can't resolve {}", addr))?
+            .next()
+            .with_context(|| format!("{} has no addresses", addr))?;
+
+        let transport = if tcp {
+            Transport::Tcp { addr, stream: None }
+        } else {
+            let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+
+            let socket = UdpSocket::bind(local).context("Can't make a UDP socket")?;
+            socket.connect(addr).with_context(|| format!("Can't send to {}", addr))?;
+
+            Transport::Udp(socket)
+        };
+
+        let (samples, receiver) = mpsc::sync_channel::<String>(QUEUE);
+
+        // It stops once Push is dropped and the queue is empty, it's not joined so exiting doesn't wait for the server
+        std::thread::Builder::new().name("Push".to_string()).spawn(move || {
+            let mut transport = transport;
+
+            for text in receiver {
+                // The server being down isn't a reason to stop, the next sample tries again
+                let _ = transport.send(text.as_bytes());
+            }
+        }).context("Couldn't spawn Push thread")?;
+
+        Ok(Self {
+            format,
+            host: host.to_string(),
+            samples,
+        })
+    }
+}
+
+impl Transport {
+    fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            Transport::Udp(socket) => {
+                let mut rest = data;
+
+                while !rest.is_empty() {
+                    // Split after the last line that fits, a longer line goes on its own
+                    let end = match rest.len() <= MAX_DATAGRAM {
+                        true => rest.len(),
+                        false => rest[..MAX_DATAGRAM].iter().rposition(|b| *b == b'\n')
+                            .or_else(|| rest.iter().position(|b| *b == b'\n'))
+                            .map_or(rest.len(), |idx| idx + 1),
+                    };
+
+                    socket.send(&rest[..end])?;
+                    rest = &rest[end..];
+                }
+
+                Ok(())
+            },
+            Transport::Tcp { addr, stream } => {
+                if stream.is_none() {
+                    let connection = TcpStream::connect_timeout(addr, TIMEOUT)?;
+                    connection.set_write_timeout(Some(TIMEOUT))?;
+
+                    *stream = Some(connection);
+                }
+
+                let result = stream.as_mut().map_or(Ok(()), |stream| stream.write_all(data));
+
+                // Connect again next time
+                if result.is_err() {
+                    *stream = None;
+                }
+
+                result
+            },
+        }
+    }
+}
+
+impl Output for Push {
+    fn sample(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let mut text = String::with_capacity(4096);
+
+        render(&mut text, self.format, &self.host, timestamp(), snapshot);
+
+        // The queue is full if the thread is stuck on the server, this sample is dropped then
+        let _ = self.samples.try_send(text);
+
+        Ok(())
+    }
+}
+
+enum Value {
+    Int(u64),
+    Float(f64),
+}
+
+// Writes one point in either format
+struct Writer<'a> {
+    text: &'a mut String,
+    format: Format,
+    host: &'a str,
+
+    // Milliseconds since the Unix epoch
+    timestamp: u64,
+}
+
+impl Writer<'_> {
+    fn point(&mut self, measurement: &str, tags: &[(&str, &str)], fields: &[(&str, Value)]) {
+        match self.format {
+            Format::Influx => {
+                let _ = write!(self.text, "megamonic_{}", measurement);
+                self.tags(tags);
+
+                for (idx, (name, value)) in fields.iter().enumerate() {
+                    let _ = match value {
+                        Value::Int(value) => write!(self.text, "{}{}={}i", if idx == 0 { ' ' } else { ',' }, name, value),
+                        Value::Float(value) => write!(self.text, "{}{}={}", if idx == 0 { ' ' } else { ',' }, name, value),
+                    };
+                }
+
+                // Nanoseconds
+                let _ = writeln!(self.text, " {}", self.timestamp * 1_000_000);
+            },
+            Format::Statsd => {
+                for (name, value) in fields {
+                    let _ = write!(self.text, "megamonic.{}.{}", measurement, name);
+                    self.tags(tags);
+
+                    let _ = match value {
+                        Value::Int(value) => writeln!(self.text, ":{}|g", value),
+                        Value::Float(value) => writeln!(self.text, ":{}|g", value),
+                    };
+                }
+            },
+        }
+    }
+
+    fn tags(&mut self, tags: &[(&str, &str)]) {
+        for (name, value) in [("host", self.host)].iter().chain(tags) {
+            let _ = write!(self.text, ",{}=", name);
+
+            for c in value.chars() {
+                match (self.format, c) {
+                    // Line protocol escapes them
+                    (Format::Influx, ',' | '=' | ' ') => {
+                        self.text.push('\\');
+                        self.text.push(c);
+                    },
+
+                    // StatsD has no escaping, they would end the name or the value
+                    (Format::Statsd, ',' | '=' | ' ' | ':' | '|' | '@') => self.text.push('_'),
+
+                    _ => self.text.push(c),
+                }
+            }
+        }
+    }
+}
+
+fn render(text: &mut String, format: Format, host: &str, timestamp: u64, snapshot: &Snapshot) {
+    let mut w = Writer { text, format, host, timestamp };
+
+    let cpu = &snapshot.cpu;
+    let times = &cpu.times;
+    w.point("cpu", &[], &[
+        ("usage", Value::Float(cpu.usage as f64)),
+        ("cores", Value::Int(cpu.cores as u64)),
+        ("user", Value::Int(times.user)),
+        ("nice", Value::Int(times.nice)),
+        ("system", Value::Int(times.system)),
+        ("idle", Value::Int(times.idle)),
+        ("iowait", Value::Int(times.iowait)),
+        ("irq", Value::Int(times.irq)),
+        ("softirq", Value::Int(times.softirq)),
+        ("steal", Value::Int(times.steal)),
+        ("guest", Value::Int(times.guest)),
+        ("guest_nice", Value::Int(times.guest_nice)),
+    ]);
+
+    let memory = &snapshot.memory;
+    w.point("memory", &[], &[
+        ("total", Value::Int(memory.total)),
+        ("available", Value::Int(memory.free)),
+        ("used", Value::Int(memory.used)),
+        ("swap_total", Value::Int(memory.swap_total)),
+        ("swap_free", Value::Int(memory.swap_free)),
+        ("swap_used", Value::Int(memory.swap_used)),
+    ]);
+
+    let loadavg = &snapshot.loadavg;
+    w.point("load", &[], &[
+        ("load1", Value::Float(loadavg.min1 as f64)),
+        ("load5", Value::Float(loadavg.min5 as f64)),
+        ("load15", Value::Float(loadavg.min15 as f64)),
+    ]);
+
+    // Bytes per second over the last update
+    let elapsed = snapshot.network.elapsed.as_secs_f64();
+    let rate = |bytes: u64| if elapsed > 0.0 { bytes as f64 / elapsed } else { 0.0 };
+
+    for interface in &snapshot.network.interfaces {
+        w.point("network", &[("interface", interface.name.trim_end_matches(':'))], &[
+            ("recv_bytes", Value::Int(interface.total_recv)),
+            ("sent_bytes", Value::Int(interface.total_sent)),
+            ("recv_rate", Value::Float(rate(interface.recv))),
+            ("sent_rate", Value::Float(rate(interface.sent))),
+        ]);
+    }
+
+    for sensor in &snapshot.sensors.sensors {
+        w.point("sensor", &[("sensor", sensor.label.as_str())], &[
+            ("temperature", Value::Int(sensor.temperature as u64)),
+        ]);
+    }
+
+    if let Some(gpu) = &snapshot.gpu {
+        w.point("gpu", &[], &[
+            ("temperature", Value::Int(gpu.temperature as u64)),
+            ("gpu_load", Value::Int(gpu.gpu_load as u64)),
+            ("mem_load", Value::Int(gpu.mem_load as u64)),
+            ("mem_used", Value::Float(gpu.mem_used as f64)),
+        ]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use megamonic::SensorSnapshot;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn snapshot() -> Snapshot {
+        let mut snapshot = Snapshot::default();
+
+        let mut sensor = SensorSnapshot::default();
+        sensor.label = String::from("Core 0");
+        sensor.temperature = 45;
+
+        snapshot.sensors.sensors.push(sensor);
+
+        snapshot
+    }
+
+    #[test]
+    fn influx_over_udp() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        let target = format!("udp://{}", listener.local_addr().unwrap());
+        let mut push = Push::new(Format::Influx, &target, "build 1").unwrap();
+        push.sample(&snapshot()).unwrap();
+
+        let mut buffer = [0; MAX_DATAGRAM];
+        let length = listener.recv(&mut buffer).unwrap();
+        let text = std::str::from_utf8(&buffer[..length]).unwrap();
+
+        assert!(text.starts_with("megamonic_cpu,host=build\\ 1 usage=0,cores=0i,"), "{}", text);
+        assert!(text.lines().any(|line| line.starts_with("megamonic_sensor,host=build\\ 1,sensor=Core\\ 0 temperature=45i ")), "{}", text);
+    }
+
+    #[test]
+    fn statsd_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let target = format!("tcp://{}", listener.local_addr().unwrap());
+        let mut push = Push::new(Format::Statsd, &target, "build:1").unwrap();
+        push.sample(&snapshot()).unwrap();
+        drop(push);
+
+        let mut text = String::new();
+        listener.accept().unwrap().0.read_to_string(&mut text).unwrap();
+
+        assert!(text.lines().any(|line| line == "megamonic.load.load1,host=build_1:0|g"), "{}", text);
+        assert!(text.lines().any(|line| line == "megamonic.sensor.temperature,host=build_1,sensor=Core_0:45|g"), "{}", text);
+    }
+
+    #[test]
+    fn bad_target() {
+        assert!(Push::new(Format::Influx, "http://localhost:8086", "host").is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::{ Context, Result };
+use serde::{ Deserialize, Serialize };
+use serde::de::DeserializeOwned;
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use std::path::{ Path, PathBuf };
+
+use megamonic::Snapshot;
+use megamonic::system::{ System, bus::Source };
+
+use super::{ Output, timestamp };
+
+// Bumped when older versions can't read a recording anymore
+pub const VERSION: u32 = 1;
+
+// The first line of a recording
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+
+    // Hostinfo of the machine that was recorded
+    #[serde(default)]
+    pub hostname: String,
+    pub distname: String,
+    pub kernel: String,
+    pub ansi_color: String,
+}
+
+impl Header {
+    pub fn new(system: &System) -> Self {
+        Self {
+            version: VERSION,
+            hostname: system.hostinfo().hostname.clone(),
+            distname: system.hostinfo().distname.clone(),
+            kernel: system.hostinfo().kernel.clone(),
+            ansi_color: system.hostinfo().ansi_color.clone(),
+        }
+    }
+}
+
+// Every line after the header, one per collector update
+// 'data' is the part of the Snapshot that belongs to 'source'
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Line<T> {
+    // Milliseconds since the Unix epoch
+    pub timestamp: u64,
+
+    pub source: Source,
+    pub data: T,
+}
+
+// What's needed to know what a line is, 'data' is skipped
+#[derive(Deserialize)]
+pub struct Peek {
+    pub timestamp: u64,
+    pub source: Source,
+}
+
+// Write the part of 'system' that belongs to 'source' as a Line. Input has nothing to write
+pub fn write<W: Write>(mut writer: W, system: &System, source: Source) -> Result<()> {
+    let timestamp = timestamp();
+
+    match source {
+        Source::Time => serde_json::to_writer(&mut writer, &Line { timestamp, source, data: system.time() }),
+        Source::Loadavg => serde_json::to_writer(&mut writer, &Line { timestamp, source, data: system.loadavg() }),
+        Source::Cpu => serde_json::to_writer(&mut writer, &Line { timestamp, source, data: system.cpu() }),
+        Source::Memory => serde_json::to_writer(&mut writer, &Line { timestamp, source, data: system.memory() }),
+        Source::Sensors => serde_json::to_writer(&mut writer, &Line { timestamp, source, data: system.sensors() }),
+        Source::Network => serde_json::to_writer(&mut writer, &Line { timestamp, source, data: system.network() }),
+        Source::Processes => serde_json::to_writer(&mut writer, &Line { timestamp, source, data: system.processes() }),
+        Source::Gpu => serde_json::to_writer(&mut writer, &Line { timestamp, source, data: system.gpu() }),
+        Source::Input => return Ok(()),
+    }?;
+
+    writer.write_all(b"\n")?;
+
+    Ok(())
+}
+
+// Put the data of a line from 'source' in its part of 'state'
+// A line that can't be parsed is read as empty
+pub fn read(line: &str, source: Source, state: &mut Snapshot) {
+    match source {
+        Source::Time => state.time = data(line),
+        Source::Loadavg => state.loadavg = data(line),
+        Source::Cpu => state.cpu = data(line),
+        Source::Memory => state.memory = data(line),
+        Source::Sensors => state.sensors = data(line),
+        Source::Network => state.network = data(line),
+        Source::Processes => state.processes = data(line),
+        Source::Gpu => state.gpu = data(line),
+        Source::Input => (),
+    }
+}
+
+fn data<T: DeserializeOwned + Default>(line: &str) -> T {
+    serde_json::from_str::<Line<T>>(line).map(|line| line.data).unwrap_or_default()
+}
+
+// --record, every collector update as one line of JSON. See replay::Player
+pub struct Recorder {
+    file: BufWriter<File>,
+    path: PathBuf,
+
+    // The header needs the System so it's written with the first update
+    header: bool,
+}
+
+impl Recorder {
+    // The file is truncated if it exists
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Can't create {}", path.display()))?;
+
+        Ok(Self {
+            file: BufWriter::new(file),
+            path: path.to_path_buf(),
+            header: false,
+        })
+    }
+
+}
+
+impl Output for Recorder {
+    // Everything is written in updated()
+    fn sample(&mut self, _snapshot: &Snapshot) -> Result<()> {
+        Ok(())
+    }
+
+    fn updated(&mut self, system: &System, source: Source) -> Result<()> {
+        let context = || format!("Can't write to {}", self.path.display());
+
+        if !self.header {
+            self.header = true;
+
+            serde_json::to_writer(&mut self.file, &Header::new(system)).with_context(context)?;
+            self.file.write_all(b"\n").with_context(context)?;
+        }
+
+        write(&mut self.file, system, source).with_context(context)?;
+
+        // Every line is flushed so a crash doesn't lose more than the update it happened in
+        self.file.flush().with_context(context)
+    }
+}
@@ -0,0 +1,265 @@
+use anyhow::{ bail, Context, Result };
+use std::io::{ Read, Write };
+use std::net::{ TcpListener, TcpStream, ToSocketAddrs };
+use std::os::unix::net::{ UnixListener, UnixStream };
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Mutex, mpsc::{ self, SyncSender } };
+use std::time::Duration;
+
+use megamonic::Snapshot;
+use megamonic::system::{ System, bus::Source };
+
+use super::{ Output, TIMEOUT };
+use super::record::{ self, Header };
+
+// Updates a client can be behind before it's dropped
+const BACKLOG: usize = 64;
+
+// Where --serve listens and --connect connects to
+// unix:PATH is a Unix socket, anything else is host:port
+#[derive(Debug, Clone, PartialEq)]
+pub enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl std::str::FromStr for Address {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_prefix("unix:") {
+            Some("") => bail!("unix: needs a path, like unix:/run/megamonic.sock"),
+            Some(path) => Ok(Address::Unix(PathBuf::from(path))),
+            None if s.contains(':') => Ok(Address::Tcp(s.to_string())),
+            None => bail!("'{}' isn't host:port or unix:PATH", s),
+        }
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "{}", addr),
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl Address {
+    // Gives up after TIMEOUT so one host that's down doesn't hold up the others
+    pub fn connect(&self) -> std::io::Result<Stream> {
+        match self {
+            Address::Tcp(addr) => {
+                let mut last = std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses");
+
+                for addr in addr.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&addr, TIMEOUT) {
+                        Ok(stream) => return Ok(Stream::Tcp(stream)),
+                        Err(err) => last = err,
+                    }
+                }
+
+                Err(last)
+            },
+            Address::Unix(path) => UnixStream::connect(path).map(Stream::Unix),
+        }
+    }
+}
+
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn set_read_timeout(&self, timeout: Option::<Duration>) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option::<Duration>) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+// --serve, sends every collector update to the clients made with --connect
+// It's the same as a recording made with --record: a header and then one line of JSON per update
+pub struct Server {
+    // Every client has a thread that writes to it, so a slow one doesn't hold up the UI or the others
+    clients: Vec::<SyncSender<Arc<[u8]>>>,
+
+    // Accepted but haven't gotten the header yet. That needs the System so it's done in updated()
+    pending: Arc<Mutex<Vec::<Stream>>>,
+
+    // Removed when megamonic exits
+    socket: Option::<PathBuf>,
+
+    buffer: Vec::<u8>,
+}
+
+impl Server {
+    // Fails if 'addr' can't be listened on
+    pub fn start(addr: &Address, interactive: bool) -> Result<Self> {
+        let pending = Arc::new(Mutex::new(Vec::new()));
+        let internal = Arc::clone(&pending);
+
+        let accept: Box<dyn FnMut() -> std::io::Result<Stream> + Send> = match addr {
+            Address::Tcp(tcp) => {
+                let listener = TcpListener::bind(tcp.as_str()).with_context(|| format!("Can't listen on {}", addr))?;
+
+                Box::new(move || listener.accept().map(|(stream, _)| Stream::Tcp(stream)))
+            },
+            Address::Unix(path) => {
+                let listener = bind(path)?;
+
+                Box::new(move || listener.accept().map(|(stream, _)| Stream::Unix(stream)))
+            },
+        };
+
+        super::listen("Server", interactive, accept, move |stream: Stream| {
+            if stream.set_write_timeout(Some(TIMEOUT)).is_ok() {
+                if let Ok(mut pending) = internal.lock() {
+                    pending.push(stream);
+                }
+            }
+        })?;
+
+        Ok(Self {
+            clients: Vec::new(),
+            pending,
+            socket: match addr {
+                Address::Unix(path) => Some(path.clone()),
+                Address::Tcp(_) => None,
+            },
+            buffer: Vec::with_capacity(4096),
+        })
+    }
+}
+
+impl Output for Server {
+    // Everything is sent in updated()
+    fn sample(&mut self, _snapshot: &Snapshot) -> Result<()> {
+        Ok(())
+    }
+
+    fn updated(&mut self, system: &System, source: Source) -> Result<()> {
+        let pending = self.pending.lock().map(|mut pending| std::mem::take(&mut *pending)).unwrap_or_default();
+
+        // New clients get everything there is so they don't start out empty
+        for client in pending {
+            self.buffer.clear();
+
+            serde_json::to_writer(&mut self.buffer, &Header::new(system))?;
+            self.buffer.push(b'\n');
+
+            for source in Source::COLLECTORS {
+                record::write(&mut self.buffer, system, source)?;
+            }
+
+            if let Ok(sender) = writer(client) {
+                if sender.try_send(Arc::from(self.buffer.as_slice())).is_ok() {
+                    self.clients.push(sender);
+                }
+            }
+        }
+
+        if self.clients.is_empty() {
+            return Ok(());
+        }
+
+        self.buffer.clear();
+        record::write(&mut self.buffer, system, source)?;
+
+        // Clients that went away or are BACKLOG updates behind are dropped, they can connect again
+        let update: Arc<[u8]> = Arc::from(self.buffer.as_slice());
+        self.clients.retain(|client| client.try_send(Arc::clone(&update)).is_ok());
+
+        Ok(())
+    }
+}
+
+// Start a thread that writes what's sent to it to 'client'
+// It stops when the client goes away or is dropped from Server.clients
+fn writer(client: Stream) -> std::io::Result<SyncSender<Arc<[u8]>>> {
+    let (sender, receiver) = mpsc::sync_channel::<Arc<[u8]>>(BACKLOG);
+
+    std::thread::Builder::new().name("Server client".to_string()).spawn(move || {
+        let mut client = client;
+
+        for update in receiver {
+            // Dropping the receiver tells updated() it's gone
+            if client.write_all(&update).is_err() {
+                break;
+            }
+        }
+    })?;
+
+    Ok(sender)
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(path) = &self.socket {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Listen on a Unix socket. Remove it when done
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    // A socket nothing is listening on is left over from a megamonic that was killed
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            bail!("Something is already listening on {}", path.display());
+        }
+
+        std::fs::remove_file(path).with_context(|| format!("Can't remove {}", path.display()))?;
+    }
+
+    UnixListener::bind(path).with_context(|| format!("Can't listen on {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address() {
+        assert_eq!("unix:/run/megamonic.sock".parse::<Address>().unwrap(), Address::Unix(PathBuf::from("/run/megamonic.sock")));
+        assert_eq!("build1:9189".parse::<Address>().unwrap(), Address::Tcp(String::from("build1:9189")));
+        assert_eq!("[::1]:9189".parse::<Address>().unwrap(), Address::Tcp(String::from("[::1]:9189")));
+
+        assert!("unix:".parse::<Address>().is_err());
+        assert!("build1".parse::<Address>().is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use anyhow::{ anyhow, ensure, Context, Result };
+use std::cell::Cell;
+use std::io::{ BufRead, BufReader };
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use megamonic::Snapshot;
+use megamonic::system::System;
+use megamonic::system::bus::{ Bus, Event, Key, Source };
+
+use crate::output::record::{ self, Header, Peek, VERSION };
+use crate::output::serve::{ Address, Stream };
+use crate::settings::{ Settings, Keys };
+use crate::ui::Tab;
+
+// Nothing from a host for this long means it's gone. Time is sent every second
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Wait before connecting again, doubled every time it fails
+const MIN_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(30);
+
+// A machine running megamonic --serve, shown in a System made with System::external()
+struct Host {
+    system: System,
+    address: Address,
+
+    // Hostname from the header, or the address if it has never connected
+    name: Mutex<String>,
+    connected: AtomicBool,
+
+    // Updates that haven't been drawn. Every host sends Event::Updated on the same Bus
+    // but only the one that's shown is drawn
+    updated: Mutex<Vec::<Source>>,
+}
+
+// --connect, one tab per host
+pub struct Remote {
+    hosts: Vec::<Arc<Host>>,
+
+    // The host that's shown
+    current: Cell<usize>,
+}
+
+impl Remote {
+    // Fails if none of them can be connected to. The others are retried in the background
+    pub fn connect(addresses: &[Address], settings: &Settings, bus: Bus) -> Result<Self> {
+        let connections: Vec::<Result<(Header, BufReader<Stream>)>> = addresses.iter().map(handshake).collect();
+
+        // One that's down doesn't stop the others, but a typo in the only address should
+        if connections.iter().all(Result::is_err) {
+            return Err(connections.into_iter().find_map(Result::err).unwrap_or_else(|| anyhow!("Nothing to connect to")));
+        }
+
+        let mut hosts = Vec::<Arc<Host>>::new();
+
+        for (idx, (address, connection)) in addresses.iter().zip(&connections).enumerate() {
+            let mut config = settings.config()?;
+
+            // Only one of them reads the keys and signals
+            config.interactive = idx == 0;
+            config.signals = false;
+
+            let mut system = System::external(config, bus.clone())?;
+
+            let name = match connection {
+                Ok((header, _)) => {
+                    let hostinfo = system.hostinfo_mut();
+                    hostinfo.hostname.clone_from(&header.hostname);
+                    hostinfo.distname.clone_from(&header.distname);
+                    hostinfo.kernel.clone_from(&header.kernel);
+                    hostinfo.ansi_color.clone_from(&header.ansi_color);
+
+                    name(header, address)
+                },
+                Err(_) => address.to_string(),
+            };
+
+            hosts.push(Arc::new(Host {
+                system,
+                address: address.clone(),
+                name: Mutex::new(name),
+                connected: AtomicBool::new(connection.is_ok()),
+                updated: Mutex::new(Vec::new()),
+            }));
+        }
+
+        // Started after the Systems so they inherit the signal mask of the Event thread
+        // They are blocked reading so they aren't joined. They stop when megamonic exits
+        for (host, connection) in hosts.iter().zip(connections) {
+            let host = Arc::clone(host);
+            let reader = connection.ok().map(|(_, reader)| reader);
+
+            std::thread::Builder::new().name("Remote".to_string()).spawn(move || {
+                host.run(reader);
+            }).context("Couldn't spawn Remote thread")?;
+        }
+
+        Ok(Self { hosts, current: Cell::new(0) })
+    }
+
+    // The System of the host that's shown
+    pub fn system(&self) -> &System {
+        &self.hosts[self.current.get()].system
+    }
+
+    pub fn current(&self) -> usize {
+        self.current.get()
+    }
+
+    pub fn tabs(&self) -> Vec::<Tab> {
+        self.hosts.iter()
+            .map(|host| Tab {
+                name: host.name.lock().map(|name| name.clone()).unwrap_or_default(),
+                connected: host.connected.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    // Wait for the next Event. Updates from the hosts that aren't shown are left out
+    // Returns None if every sender is gone
+    pub fn recv(&self, events: &Receiver<Event>) -> Option::<Event> {
+        loop {
+            let event = events.recv().ok()?;
+
+            if let Event::Updated(source) = event {
+                if !self.hosts[self.current.get()].take(source) {
+                    continue;
+                }
+            }
+
+            return Some(event);
+        }
+    }
+
+    // Go to the next or previous host. Returns the System to show
+    // or None if 'key' isn't for picking a host
+    pub fn key(&self, key: Key, keys: Keys) -> Option::<&System> {
+        let Key::Char(c) = key else {
+            return None;
+        };
+
+        let count = self.hosts.len();
+
+        let current = if c == keys.next_host {
+            (self.current.get() + 1) % count
+        } else if c == keys.previous_host {
+            (self.current.get() + count - 1) % count
+        } else {
+            return None;
+        };
+
+        self.current.set(current);
+
+        // Everything is drawn when it's switched to
+        let host = &self.hosts[current];
+        if let Ok(mut updated) = host.updated.lock() {
+            updated.clear();
+        }
+
+        Some(&host.system)
+    }
+}
+
+impl Host {
+    // Read updates until the connection is lost, then connect again
+    fn run(&self, mut reader: Option::<BufReader<Stream>>) {
+        let mut state = Snapshot::default();
+        let mut line = String::new();
+        let mut retry = MIN_RETRY;
+
+        loop {
+            if let Some(mut reader) = reader.take() {
+                self.set_connected(true);
+                retry = MIN_RETRY;
+
+                // Until it's closed or times out
+                while matches!(reader.read_line(&mut line), Ok(length) if length > 0) {
+                    // A line cut short or from a newer version is skipped
+                    if let Ok(Peek { source, .. }) = serde_json::from_str::<Peek>(&line) {
+                        record::read(&line, source, &mut state);
+
+                        // Before restore() sends Event::Updated
+                        self.updated(source);
+                        self.system.restore(source, &state);
+                    }
+
+                    line.clear();
+                }
+
+                line.clear();
+                self.set_connected(false);
+            }
+
+            std::thread::sleep(retry);
+            retry = (retry * 2).min(MAX_RETRY);
+
+            if let Ok((header, connection)) = handshake(&self.address) {
+                if let Ok(mut name) = self.name.lock() {
+                    *name = self::name(&header, &self.address);
+                }
+
+                reader = Some(connection);
+            }
+        }
+    }
+
+    fn updated(&self, source: Source) {
+        if let Ok(mut updated) = self.updated.lock() {
+            if !updated.contains(&source) {
+                updated.push(source);
+            }
+        }
+    }
+
+    // Returns true if 'source' has been updated since it was drawn
+    fn take(&self, source: Source) -> bool {
+        let Ok(mut updated) = self.updated.lock() else {
+            return true;
+        };
+
+        match updated.iter().position(|s| *s == source) {
+            Some(idx) => {
+                updated.swap_remove(idx);
+                true
+            },
+            None => false,
+        }
+    }
+
+    // The tabs are drawn with the clock, so it's updated to show it
+    fn set_connected(&self, connected: bool) {
+        if self.connected.swap(connected, Ordering::Relaxed) != connected {
+            self.updated(Source::Time);
+            self.system.bus().send(Event::Updated(Source::Time));
+        }
+    }
+}
+
+fn handshake(address: &Address) -> Result<(Header, BufReader<Stream>)> {
+    let context = || format!("Can't connect to {}", address);
+
+    let stream = address.connect().with_context(context)?;
+    stream.set_read_timeout(Some(TIMEOUT)).with_context(context)?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+
+    reader.read_line(&mut line).with_context(context)?;
+
+    let header: Header = serde_json::from_str(&line).with_context(|| format!("{} isn't megamonic --serve", address))?;
+
+    ensure!(header.version <= VERSION, "{} runs a newer version of megamonic", address);
+
+    Ok((header, reader))
+}
+
+fn name(header: &Header, address: &Address) -> String {
+    match header.hostname.is_empty() {
+        true => address.to_string(),
+        false => header.hostname.clone(),
+    }
+}
@@ -0,0 +1,300 @@
+use anyhow::{ bail, ensure, Context, Result };
+use std::fs::File;
+use std::io::{ BufRead, BufReader, Seek, SeekFrom };
+use std::path::{ Path, PathBuf };
+use std::sync::mpsc::{ Receiver, RecvTimeoutError };
+use std::time::{ Duration, Instant };
+
+use megamonic::Snapshot;
+use megamonic::system::{ System, Config };
+use megamonic::system::bus::{ Bus, Event, Key, Source };
+
+use crate::output::record::{ self, Header, Peek, VERSION };
+use crate::settings::Keys;
+
+// How far the back and forward keys jump in the recording, in milliseconds
+const SEEK: u64 = 60_000;
+
+// Slowest and fastest playback
+const MIN_SPEED: f64 = 1.0 / 16.0;
+const MAX_SPEED: f64 = 256.0;
+
+// Where a line is in the file
+#[derive(Clone, Copy)]
+struct Entry {
+    timestamp: u64,
+    offset: u64,
+    source: Source,
+}
+
+// --replay, shows a file made with --record in a System made with System::external()
+// The lines are indexed when it's opened and read when they are shown so long recordings
+// don't have to fit in memory
+pub struct Player {
+    header: Header,
+
+    file: BufReader<File>,
+    path: PathBuf,
+
+    // Where 'file' is, so it isn't seeked for every line
+    offset: u64,
+
+    index: Vec::<Entry>,
+
+    // The next line to show
+    position: usize,
+
+    // The latest of every collector that has been shown
+    state: Snapshot,
+
+    // The time 'clock' in the recording was at 'started'. It moves 'speed' times as fast from there
+    clock: u64,
+    started: Instant,
+    speed: f64,
+    paused: bool,
+
+    buffer: String,
+}
+
+impl Player {
+    pub fn open(path: &Path) -> Result<Self> {
+        let context = || format!("Can't read {}", path.display());
+
+        let mut file = BufReader::new(File::open(path).with_context(context)?);
+        let mut buffer = String::new();
+
+        let mut offset = file.read_line(&mut buffer).with_context(context)? as u64;
+
+        let header: Header = serde_json::from_str(&buffer).with_context(|| format!("{} isn't a megamonic recording", path.display()))?;
+
+        ensure!(header.version <= VERSION, "{} was recorded by a newer version of megamonic", path.display());
+
+        let mut index = Vec::<Entry>::new();
+
+        loop {
+            buffer.clear();
+
+            let length = file.read_line(&mut buffer).with_context(context)?;
+
+            if length == 0 {
+                break;
+            }
+
+            let peek = match serde_json::from_str::<Peek>(&buffer) {
+                Ok(peek) => peek,
+
+                // The last line is cut short if megamonic was killed while writing it
+                Err(_) if !buffer.ends_with('\n') => break,
+                Err(err) => bail!("Line {} of {} is broken: {}", index.len() + 2, path.display(), err),
+            };
+
+            // Keep it sorted if the clock was set back while recording
+            let timestamp = index.last().map_or(peek.timestamp, |last| last.timestamp.max(peek.timestamp));
+
+            index.push(Entry { timestamp, offset, source: peek.source });
+
+            offset += length as u64;
+        }
+
+        ensure!(!index.is_empty(), "{} doesn't have anything in it", path.display());
+
+        file.rewind().with_context(context)?;
+
+        Ok(Self {
+            header,
+            file,
+            path: path.to_path_buf(),
+            offset: 0,
+            clock: index[0].timestamp,
+            index,
+            position: 0,
+            state: Snapshot::default(),
+            started: Instant::now(),
+            speed: 1.0,
+            paused: false,
+            buffer,
+        })
+    }
+
+    // The System to show the recording in. It looks like the recorded machine
+    pub fn system(&self, config: Config, bus: Bus) -> Result<System> {
+        let mut system = System::external(config, bus)?;
+
+        let hostinfo = system.hostinfo_mut();
+        hostinfo.hostname.clone_from(&self.header.hostname);
+        hostinfo.distname.clone_from(&self.header.distname);
+        hostinfo.kernel.clone_from(&self.header.kernel);
+        hostinfo.ansi_color.clone_from(&self.header.ansi_color);
+
+        Ok(system)
+    }
+
+    // Wait for the next Event, the recording is played while waiting
+    // The lines are sent to 'system' which sends Event::Updated like the collectors do
+    // Returns None if every sender is gone
+    pub fn recv(&mut self, system: &System, events: &Receiver<Event>) -> Result<Option::<Event>> {
+        loop {
+            let next = self.index.get(self.position).filter(|_| !self.paused);
+
+            let result = match next {
+                Some(entry) => events.recv_timeout(self.due(entry.timestamp).saturating_duration_since(Instant::now())),
+
+                // Paused or at the end, there's nothing to do until a key is pressed
+                None => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match result {
+                Ok(event) => return Ok(Some(event)),
+                Err(RecvTimeoutError::Timeout) => {
+                    let source = self.load(self.position)?;
+                    self.position += 1;
+
+                    system.restore(source, &self.state);
+                },
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            }
+        }
+    }
+
+    // Pause, speed and seek. Returns what to show in the status line
+    // or None if 'key' isn't for the playback
+    pub fn key(&mut self, system: &System, key: Key, keys: Keys) -> Result<Option::<String>> {
+        let Key::Char(c) = key else {
+            return Ok(None);
+        };
+
+        if c == keys.pause {
+            // Start over if it's at the end
+            if self.position == self.index.len() {
+                self.seek(system, 0)?;
+                self.paused = false;
+            } else {
+                self.clock = self.now();
+                self.started = Instant::now();
+                self.paused = !self.paused;
+            }
+        } else if c == keys.faster || c == keys.slower {
+            self.clock = self.now();
+            self.started = Instant::now();
+
+            let factor = if c == keys.faster { 2.0 } else { 0.5 };
+            self.speed = (self.speed * factor).clamp(MIN_SPEED, MAX_SPEED);
+        } else if c == keys.back {
+            self.seek(system, self.now().saturating_sub(SEEK))?;
+        } else if c == keys.forward {
+            self.seek(system, self.now() + SEEK)?;
+        } else {
+            return Ok(None);
+        }
+
+        Ok(Some(self.status()))
+    }
+
+    fn status(&self) -> String {
+        if self.position == self.index.len() {
+            String::from("end of recording")
+        } else if self.paused {
+            String::from("paused")
+        } else {
+            format!("{}x", self.speed)
+        }
+    }
+
+    // Where the playback is in the recording, milliseconds since the Unix epoch
+    fn now(&self) -> u64 {
+        if self.paused {
+            self.clock
+        } else {
+            self.clock + (self.started.elapsed().as_secs_f64() * self.speed * 1000.0) as u64
+        }
+    }
+
+    // When the line recorded at 'timestamp' should be shown
+    fn due(&self, timestamp: u64) -> Instant {
+        self.started + Duration::from_secs_f64(timestamp.saturating_sub(self.clock) as f64 / 1000.0 / self.speed)
+    }
+
+    // Jump to 'timestamp' and show the latest of every collector from before it
+    fn seek(&mut self, system: &System, timestamp: u64) -> Result<()> {
+        let first = self.index[0].timestamp;
+        let last = self.index[self.index.len() - 1].timestamp;
+        let timestamp = timestamp.clamp(first, last);
+
+        self.position = self.index.partition_point(|entry| entry.timestamp < timestamp);
+
+        let mut latest = Vec::<usize>::new();
+
+        for (idx, entry) in self.index[..self.position].iter().enumerate().rev() {
+            if !latest.iter().any(|l| self.index[*l].source == entry.source) {
+                latest.push(idx);
+
+                if latest.len() == Source::COLLECTORS.len() {
+                    break;
+                }
+            }
+        }
+
+        // In the order they were recorded so the file is read forward
+        for idx in latest.into_iter().rev() {
+            let source = self.load(idx)?;
+
+            system.restore(source, &self.state);
+        }
+
+        self.clock = timestamp;
+        self.started = Instant::now();
+
+        Ok(())
+    }
+
+    // Read line 'idx' into 'state'. Returns which part of it changed
+    fn load(&mut self, idx: usize) -> Result<Source> {
+        let Entry { offset, source, .. } = self.index[idx];
+        let context = || format!("Can't read {}", self.path.display());
+
+        if offset != self.offset {
+            self.file.seek(SeekFrom::Start(offset)).with_context(context)?;
+        }
+
+        self.buffer.clear();
+        self.offset = offset + self.file.read_line(&mut self.buffer).with_context(context)? as u64;
+
+        // It was parsed when it was indexed so it only fails if the file was changed since
+        record::read(&self.buffer, source, &mut self.state);
+
+        Ok(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index() {
+        let path = std::env::temp_dir().join(format!("megamonic-replay-{}.ndjson", std::process::id()));
+
+        std::fs::write(&path, concat!(
+            "{\"version\":1,\"distname\":\"Test\",\"kernel\":\"6.0\",\"ansi_color\":\"\"}\n",
+            "{\"timestamp\":2000,\"source\":\"time\",\"data\":2}\n",
+            "{\"timestamp\":1500,\"source\":\"loadavg\",\"data\":{\"min1\":0.5,\"min5\":0.25,\"min15\":0.125}}\n",
+            "{\"timestamp\":3000,\"source\":\"cpu\",\"data\":{\"usa",
+        )).unwrap();
+
+        let player = Player::open(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut player = player.unwrap();
+
+        // The cut off line is left out and the clock going back is evened out
+        let timestamps: Vec::<u64> = player.index.iter().map(|entry| entry.timestamp).collect();
+        assert_eq!(timestamps, [2000, 2000]);
+        assert_eq!(player.header.distname, "Test");
+
+        assert_eq!(player.load(1).unwrap(), Source::Loadavg);
+        assert_eq!(player.state.loadavg.min5, 0.25);
+
+        assert_eq!(player.load(0).unwrap(), Source::Time);
+        assert_eq!(player.state.time, 2);
+    }
+}
@@ -0,0 +1,676 @@
+use anyhow::{ bail, ensure, Context, Result };
+use serde::{ Deserialize, Serialize };
+use std::io::Write;
+use std::path::{ Path, PathBuf };
+use std::collections::HashMap;
+use std::sync::atomic;
+
+use megamonic::system::{ self, bus::Source };
+
+use crate::output::alerts::Rule;
+
+// The config file. Everything is optional, missing values are the defaults
+// Commandline options override what's in here
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub general: General,
+    pub intervals: Intervals,
+    pub processes: Processes,
+    pub panels: Panels,
+    pub colors: Colors,
+    pub keys: Keys,
+    pub network: Filter,
+    pub sensors: Filter,
+
+    // [[alerts]], checked every sample
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alerts: Vec::<Alert>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct General {
+    // Milliseconds
+    pub frequency: u64,
+    pub strftime: String,
+
+    // auto, io_uring or pread
+    pub io_backend: String,
+
+    pub proc_root: String,
+    pub sys_root: String,
+}
+
+impl Default for General {
+    fn default() -> Self {
+        Self {
+            frequency: 1000,
+            strftime: String::from("%c"),
+            io_backend: String::from("auto"),
+            proc_root: String::from("/proc"),
+            sys_root: String::from("/sys"),
+        }
+    }
+}
+
+// Milliseconds. Collectors that aren't set use general.frequency
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Intervals {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loadavg: Option::<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option::<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option::<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensors: Option::<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option::<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub processes: Option::<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu: Option::<u64>,
+
+    // Looking for new processes and reading their cmdlines. Every processes update if it's not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scan: Option::<u64>,
+}
+
+impl Intervals {
+    fn all(&self) -> [(Source, Option::<u64>); 7] {
+        [
+            (Source::Loadavg, self.loadavg),
+            (Source::Cpu, self.cpu),
+            (Source::Memory, self.memory),
+            (Source::Sensors, self.sensors),
+            (Source::Network, self.network),
+            (Source::Processes, self.processes),
+            (Source::Gpu, self.gpu),
+        ]
+    }
+
+    pub fn map(&self) -> HashMap<Source, atomic::AtomicU64> {
+        self.all().into_iter()
+            .filter_map(|(source, ms)| Some((source, atomic::AtomicU64::new(ms?))))
+            .collect()
+    }
+
+    // Copy the intervals that are set from 'config', they might have been scaled
+    fn store(&mut self, config: &system::Config, changes: &mut Vec::<Change>) {
+        let intervals = [
+            (&mut self.loadavg, Source::Loadavg, "loadavg"),
+            (&mut self.cpu, Source::Cpu, "cpu"),
+            (&mut self.memory, Source::Memory, "memory"),
+            (&mut self.sensors, Source::Sensors, "sensors"),
+            (&mut self.network, Source::Network, "network"),
+            (&mut self.processes, Source::Processes, "processes"),
+            (&mut self.gpu, Source::Gpu, "gpu"),
+        ];
+
+        for (interval, source, key) in intervals {
+            if let Some(ms) = config.intervals.get(&source).map(|ms| ms.load(atomic::Ordering::Relaxed)) {
+                if replace(interval, Some(ms)) {
+                    changes.push(Change::new("intervals", key, ms as i64));
+                }
+            }
+        }
+
+        if let Some(scan) = &mut self.scan {
+            let ms = config.scan_interval.load(atomic::Ordering::Relaxed);
+
+            if replace(scan, ms) {
+                changes.push(Change::new("intervals", "scan", ms as i64));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Processes {
+    pub all: bool,
+    pub smaps: bool,
+    pub topmode: bool,
+
+    // cpu, memory, pid or name
+    pub sort: String,
+}
+
+impl Default for Processes {
+    fn default() -> Self {
+        Self {
+            all: false,
+            smaps: false,
+            topmode: false,
+            sort: String::from("cpu"),
+        }
+    }
+}
+
+// Panels that are turned off aren't drawn and their collector isn't run
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Panels {
+    pub time: bool,
+    pub loadavg: bool,
+
+    // Overview and Cores
+    pub cpu: bool,
+
+    // Where the CPU time went and the usage of every core. Only hides the panel, the Cpu collector reads them anyway
+    pub cores: bool,
+
+    // Memory and Swap
+    pub memory: bool,
+
+    pub sensors: bool,
+    pub network: bool,
+    pub processes: bool,
+    pub gpu: bool,
+}
+
+impl Default for Panels {
+    fn default() -> Self {
+        Self {
+            time: true,
+            loadavg: true,
+            cpu: true,
+            cores: true,
+            memory: true,
+            sensors: true,
+            network: true,
+            processes: true,
+            gpu: true,
+        }
+    }
+}
+
+impl Panels {
+    pub fn disabled(&self) -> Vec::<Source> {
+        [
+            (self.time, Source::Time),
+            (self.loadavg, Source::Loadavg),
+            (self.cpu, Source::Cpu),
+            (self.memory, Source::Memory),
+            (self.sensors, Source::Sensors),
+            (self.network, Source::Network),
+            (self.processes, Source::Processes),
+            (self.gpu, Source::Gpu),
+        ].into_iter()
+            .filter_map(|(enabled, source)| (!enabled).then_some(source))
+            .collect()
+    }
+}
+
+// SGR parameters, like "95" or "38;5;208"
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    // Panel names
+    pub title: String,
+
+    // Labels like "CPU:"
+    pub text: String,
+
+    // Numbers
+    pub value: String,
+
+    // Brackets, errors and high values
+    pub accent: String,
+
+    // Things that don't matter much, like idle processes
+    pub dim: String,
+
+    // Process memory when it's PSS, and kernel threads
+    pub pss: String,
+
+    // Titles of panels with an alert that has 'highlight' set
+    pub highlight: String,
+
+    // The stacked bar of where the CPU time went, in the order of its legend: us sy wa st ni hi si gu
+    pub breakdown: Vec::<String>,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            title: String::from("95"),
+            text: String::from("37"),
+            value: String::from("92"),
+            accent: String::from("91"),
+            dim: String::from("38;5;244"),
+            pss: String::from("94"),
+            highlight: String::from("97;41"),
+            breakdown: ["92", "91", "38;5;208", "96", "94", "93", "95", "36"].map(String::from).to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keys {
+    pub quit: char,
+    pub pause: char,
+    pub filter: char,
+    pub topmode: char,
+    pub smaps: char,
+    pub all: char,
+    pub rebuild: char,
+
+    // Go to the next sort order
+    pub sort: char,
+
+    // Write the toggles, sort order and intervals to the config file
+    pub save: char,
+
+    // Halve or double every interval. With --replay they change the playback speed
+    pub faster: char,
+    pub slower: char,
+
+    // Jump a minute back or forward with --replay
+    pub back: char,
+    pub forward: char,
+
+    // Show the next or previous host with --connect
+    pub next_host: char,
+    pub previous_host: char,
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Self {
+            quit: 'q',
+            pause: ' ',
+            filter: 'f',
+            topmode: 't',
+            smaps: 's',
+            all: 'a',
+            rebuild: 'r',
+            sort: 'o',
+            save: 'w',
+            faster: '+',
+            slower: '-',
+            back: ',',
+            forward: '.',
+            next_host: 'n',
+            previous_host: 'p',
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filter {
+    pub include: Vec::<String>,
+    pub exclude: Vec::<String>,
+}
+
+impl From<&Filter> for system::Filter {
+    fn from(filter: &Filter) -> Self {
+        system::Filter {
+            include: filter.include.clone(),
+            exclude: filter.exclude.clone(),
+        }
+    }
+}
+
+// One [[alerts]] table. It fires when the metric has been past the limit for 'for' seconds
+// and is resolved once it's back by more than 'hysteresis'. See output::alerts for the metrics
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Alert {
+    // What it's called in the log and the command, the metric if it's empty
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub name: String,
+
+    // Like "cpu", "sensor:Tctl" or "process:postgres"
+    pub metric: String,
+
+    // One of these three
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub above: Option::<Limit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub below: Option::<Limit>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub missing: bool,
+
+    // Seconds
+    #[serde(rename = "for")]
+    pub duration: u64,
+
+    // In the unit of the metric
+    pub hysteresis: f64,
+
+    // What's done when it fires and when it's resolved. At least one is needed
+    pub highlight: bool,
+    pub bell: bool,
+
+    // Appended to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option::<PathBuf>,
+
+    // Run with sh -c, the details are in MEGAMONIC_* environment variables
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option::<String>,
+}
+
+// 90, 2.5, a size like "4G" or "crit" for the critical temperature of a sensor
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Limit {
+    Number(f64),
+    Text(String),
+}
+
+// $XDG_CONFIG_HOME/megamonic/config.toml or ~/.config/megamonic/config.toml
+pub fn path() -> Option::<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(dir.join("megamonic").join("config.toml"))
+}
+
+impl Settings {
+    // A file that doesn't exist is the same as an empty one
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err).with_context(|| format!("Can't read {}", path.display())),
+        };
+
+        let settings: Self = toml::from_str(&text).with_context(|| format!("Can't parse {}", path.display()))?;
+
+        settings.validate().with_context(|| format!("Invalid config file {}", path.display()))?;
+
+        Ok(settings)
+    }
+
+    // Writes what was changed with keys while running to the config file at 'path'
+    // Only those keys are touched, the rest of the file and its comments are left how they were
+    pub fn save(&mut self, path: &Path, config: &system::Config) -> Result<()> {
+        let mut saved = self.clone();
+        let changes = saved.store(config);
+
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err).with_context(|| format!("Can't read {}", path.display())),
+        };
+
+        let mut document: toml_edit::DocumentMut = text.parse().with_context(|| format!("Can't parse {}", path.display()))?;
+
+        for change in changes {
+            let Some(table) = document.entry(change.table).or_insert(toml_edit::table()).as_table_like_mut() else {
+                bail!("[{}] in {} isn't a table", change.table, path.display());
+            };
+
+            // Keep a comment after the value
+            match table.get_mut(change.key).and_then(toml_edit::Item::as_value_mut) {
+                Some(value) => {
+                    let decor = value.decor().clone();
+                    *value = change.value;
+                    *value.decor_mut() = decor;
+                },
+                None => {
+                    table.insert(change.key, toml_edit::Item::Value(change.value));
+                },
+            }
+        }
+
+        write(path, document.to_string().as_bytes())?;
+
+        *self = saved;
+
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let range = system::MIN_INTERVAL..=system::MAX_INTERVAL;
+
+        ensure!(range.contains(&self.general.frequency), "frequency must be between {} and {}, not {}", range.start(), range.end(), self.general.frequency);
+
+        for (source, ms) in self.intervals.all() {
+            if let Some(ms) = ms {
+                ensure!(range.contains(&ms), "the {} interval must be between {} and {}, not {}", source.name(), range.start(), range.end(), ms);
+            }
+        }
+
+        if let Some(ms) = self.intervals.scan {
+            ensure!(range.contains(&ms), "the scan interval must be between {} and {}, not {}", range.start(), range.end(), ms);
+        }
+
+        ensure!(!self.general.strftime.is_empty(), "strftime can't be empty");
+        ensure!(!self.general.proc_root.is_empty(), "proc_root can't be empty");
+        ensure!(!self.general.sys_root.is_empty(), "sys_root can't be empty");
+
+        io_backend(&self.general.io_backend)?;
+        self.processes.sort.parse::<system::Sort>()?;
+
+        let colors = &self.colors;
+        ensure!(colors.breakdown.len() == 8, "breakdown needs 8 colors, not {}", colors.breakdown.len());
+        for color in [&colors.title, &colors.text, &colors.value, &colors.accent, &colors.dim, &colors.pss, &colors.highlight].into_iter().chain(&colors.breakdown) {
+            ensure!(!color.is_empty() && color.bytes().all(|b| b.is_ascii_digit() || b == b';'), "'{}' isn't a color, use SGR parameters like \"95\" or \"38;5;208\"", color);
+        }
+
+        let keys = &self.keys;
+        let all = [keys.quit, keys.pause, keys.filter, keys.topmode, keys.smaps, keys.all, keys.rebuild, keys.sort, keys.save, keys.faster, keys.slower, keys.back, keys.forward, keys.next_host, keys.previous_host];
+        for (idx, key) in all.iter().enumerate() {
+            ensure!(!key.is_control(), "keys can't be control characters");
+            ensure!(!all[..idx].contains(key), "'{}' is bound to more than one key", key);
+        }
+
+        for alert in &self.alerts {
+            Rule::new(alert).with_context(|| format!("Invalid alert '{}'", alert.metric))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn config(&self) -> Result<system::Config> {
+        self.validate()?;
+
+        let config = system::Config {
+            smaps: atomic::AtomicBool::new(self.processes.smaps),
+            topmode: atomic::AtomicBool::new(self.processes.topmode),
+            all: atomic::AtomicBool::new(self.processes.all),
+            frequency: atomic::AtomicU64::new(self.general.frequency),
+            intervals: self.intervals.map(),
+            scan_interval: atomic::AtomicU64::new(self.intervals.scan.unwrap_or(0)),
+            strftime_format: self.general.strftime.clone(),
+            io_backend: io_backend(&self.general.io_backend)?,
+            disabled: self.panels.disabled(),
+            interfaces: (&self.network).into(),
+            sensors: (&self.sensors).into(),
+            interactive: true,
+            proc_root: self.general.proc_root.clone(),
+            sys_root: self.general.sys_root.clone(),
+            ..Default::default()
+        };
+
+        config.set_sort(self.processes.sort.parse()?);
+
+        Ok(config)
+    }
+
+    // Copy what can be changed with keys while running and return what's different
+    // 'self' already has the commandline options in it, so they're only saved if they were changed
+    fn store(&mut self, config: &system::Config) -> Vec::<Change> {
+        let mut changes = Vec::<Change>::new();
+
+        let toggles = [
+            (&mut self.processes.all, &config.all, "all"),
+            (&mut self.processes.smaps, &config.smaps, "smaps"),
+            (&mut self.processes.topmode, &config.topmode, "topmode"),
+        ];
+
+        for (toggle, current, key) in toggles {
+            let current = current.load(atomic::Ordering::Relaxed);
+
+            if replace(toggle, current) {
+                changes.push(Change::new("processes", key, current));
+            }
+        }
+
+        let sort = config.sort().name();
+        if replace(&mut self.processes.sort, sort.to_string()) {
+            changes.push(Change::new("processes", "sort", sort));
+        }
+
+        let frequency = config.frequency.load(atomic::Ordering::Relaxed);
+        if replace(&mut self.general.frequency, frequency) {
+            changes.push(Change::new("general", "frequency", frequency as i64));
+        }
+
+        self.intervals.store(config, &mut changes);
+
+        changes
+    }
+}
+
+// A key in the config file that was changed while running
+struct Change {
+    table: &'static str,
+    key: &'static str,
+    value: toml_edit::Value,
+}
+
+impl Change {
+    fn new(table: &'static str, key: &'static str, value: impl Into<toml_edit::Value>) -> Self {
+        Self { table, key, value: value.into() }
+    }
+}
+
+// Sets 'current' to 'new', true if it was different
+fn replace<T: PartialEq>(current: &mut T, new: T) -> bool {
+    if *current == new {
+        return false;
+    }
+
+    *current = new;
+
+    true
+}
+
+// Writes a temporary file next to 'path' and renames it over it, so a crash can't leave it half written
+fn write(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("Can't create {}", dir.display()))?;
+    }
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let result = std::fs::File::create(&temporary)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&temporary, path));
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+
+    result.with_context(|| format!("Can't write {}", path.display()))
+}
+
+fn io_backend(name: &str) -> Result<system::IoBackend> {
+    match name {
+        "auto" => Ok(system::IoBackend::Auto),
+        "io_uring" => Ok(system::IoBackend::Uring),
+        "pread" => Ok(system::IoBackend::Pread),
+        _ => bail!("Unknown io_backend '{}', expected one of: auto, io_uring, pread", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_file() {
+        let settings: Settings = toml::from_str(r#"
+            [processes]
+            sort = "memory"
+
+            [keys]
+            quit = "x"
+
+            [network]
+            exclude = ["docker*"]
+        "#).unwrap();
+
+        settings.validate().unwrap();
+
+        assert_eq!(settings.processes.sort, "memory");
+        assert_eq!(settings.keys.quit, 'x');
+        assert_eq!(settings.keys.pause, ' ');
+        assert_eq!(settings.general, General::default());
+
+        let config = settings.config().unwrap();
+        assert_eq!(config.sort(), system::Sort::Memory);
+        assert!(!config.interfaces.matches("docker0"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut settings = Settings::default();
+        settings.panels.gpu = false;
+        settings.colors.title = String::from("38;5;208");
+        settings.alerts.push(Alert {
+            metric: String::from("process:postgres"),
+            above: Some(Limit::Text(String::from("4G"))),
+            duration: 30,
+            highlight: true,
+            ..Default::default()
+        });
+
+        let text = toml::to_string_pretty(&settings).unwrap();
+        assert_eq!(toml::from_str::<Settings>(&text).unwrap(), settings);
+
+        assert_eq!(settings.panels.disabled(), [Source::Gpu]);
+    }
+
+    #[test]
+    fn save_changes_only() {
+        let path = std::env::temp_dir().join(format!("megamonic-save-{}.toml", std::process::id()));
+        std::fs::write(&path, "# Mine\n[processes]\nsort = \"cpu\"  # For now\n\n[keys]\nquit = \"x\"\n").unwrap();
+
+        // Like --all on the commandline
+        let mut settings = Settings::load(&path).unwrap();
+        settings.processes.all = true;
+        let config = settings.config().unwrap();
+
+        config.set_sort(system::Sort::Memory);
+        config.smaps.store(true, atomic::Ordering::Relaxed);
+        settings.save(&path, &config).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(text, "# Mine\n[processes]\nsort = \"memory\"  # For now\nsmaps = true\n\n[keys]\nquit = \"x\"\n");
+    }
+
+    #[test]
+    fn invalid() {
+        let mut settings = Settings::default();
+        settings.keys.save = 'q';
+        assert!(settings.validate().is_err());
+
+        let mut settings = Settings::default();
+        settings.colors.dim = String::from("grey");
+        assert!(settings.validate().is_err());
+
+        let mut settings = Settings::default();
+        settings.colors.breakdown.pop();
+        assert!(settings.validate().is_err());
+
+        assert!(toml::from_str::<Settings>("unknown = 1").is_err());
+    }
+}
@@ -0,0 +1,300 @@
+//! Owned copies of what the collectors have gathered, see System::snapshot()
+//! These are what library users should use. The collector structs behind System
+//! can change at any time, these only get new fields
+//! The field names are also what the JSON output and recordings use, don't rename them
+//! Missing fields are left at their default so older recordings can still be read
+
+use serde::{ Deserialize, Serialize };
+use std::time::Duration;
+
+/// /proc/stat and scaling_governor
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct CpuSnapshot {
+    /// Usage of all cores since the last update in %
+    pub usage: f32,
+
+    /// Amount of cores
+    pub cores: u32,
+
+    /// Governor of the first cpufreq policy, empty if there isn't one
+    pub governor: String,
+
+    /// Clock ticks of all cores together since boot
+    pub times: CpuTimesSnapshot,
+
+    /// Where the time since the last update went
+    pub breakdown: CpuBreakdownSnapshot,
+
+    /// The cores that are online, sorted by id
+    pub per_core: Vec::<CoreSnapshot>,
+
+    /// The cpufreq policies with a core that's online, sorted by id. Empty without cpufreq
+    pub policies: Vec::<PolicySnapshot>,
+}
+
+/// One cpuN line of /proc/stat
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct CoreSnapshot {
+    /// The N in cpuN
+    pub id: u32,
+
+    /// Usage since the last update in %
+    pub usage: f32,
+
+    /// From its cpufreq policy in kHz, 0 if it doesn't have one
+    pub frequency: u64,
+
+    /// The lowest and highest frequency its policy is set to, in kHz
+    pub min_frequency: u64,
+    pub max_frequency: u64,
+
+    /// Socket it's in, this and the next two are 0 if sysfs doesn't say
+    pub package: u32,
+
+    /// Die in the package
+    pub die: u32,
+
+    /// NUMA node
+    pub node: u32,
+
+    /// The physical core, only unique within a package. SMT siblings have the same one
+    pub core_id: u32,
+
+    /// The cores that share its physical core, itself included
+    pub siblings: Vec::<u32>,
+
+    /// 'performance' or 'efficiency' on hybrid CPUs, empty if all the cores are the same
+    pub kind: String,
+}
+
+/// /sys/devices/system/cpu/cpufreq/policyN, a group of cores that always run at the same frequency
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct PolicySnapshot {
+    /// The N in policyN
+    pub id: u32,
+
+    /// The cores in it that are online
+    pub cpus: Vec::<u32>,
+
+    pub governor: String,
+
+    /// energy_performance_preference, empty if the driver doesn't have it
+    pub epp: String,
+
+    /// In kHz
+    pub frequency: u64,
+
+    /// The lowest and highest frequency that are set, in kHz
+    pub min_frequency: u64,
+    pub max_frequency: u64,
+
+    /// What the hardware can do, in kHz
+    pub hardware_min: u64,
+    pub hardware_max: u64,
+}
+
+/// The 'cpu' line of /proc/stat, in clock ticks
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct CpuTimesSnapshot {
+    pub user: u64,
+    pub nice: u64,
+    pub system: u64,
+    pub idle: u64,
+    pub iowait: u64,
+    pub irq: u64,
+    pub softirq: u64,
+    pub steal: u64,
+
+    /// Time spent running virtual machines, it's also counted in 'user'
+    pub guest: u64,
+
+    /// Like 'guest' but counted in 'nice'
+    pub guest_nice: u64,
+}
+
+/// The 'cpu' line of /proc/stat since the last update, in % of all the time
+/// 'user' and 'nice' don't include 'guest' and 'guest_nice' here so they add up to 100
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct CpuBreakdownSnapshot {
+    pub user: f32,
+    pub nice: f32,
+    pub system: f32,
+    pub idle: f32,
+    pub iowait: f32,
+    pub irq: f32,
+    pub softirq: f32,
+    pub steal: f32,
+    pub guest: f32,
+    pub guest_nice: f32,
+}
+
+/// /proc/meminfo, all values are in bytes
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct MemorySnapshot {
+    pub total: u64,
+    pub free: u64,
+    pub used: u64,
+    pub swap_total: u64,
+    pub swap_free: u64,
+    pub swap_used: u64,
+}
+
+/// /proc/loadavg
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct LoadavgSnapshot {
+    pub min1: f32,
+    pub min5: f32,
+    pub min15: f32,
+}
+
+/// One network interface from /proc/net/dev
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct InterfaceSnapshot {
+    pub name: String,
+
+    /// Bytes recieved since the last update
+    pub recv: u64,
+
+    /// Bytes sent since the last update
+    pub sent: u64,
+
+    /// Bytes recieved since the interface came up
+    pub total_recv: u64,
+
+    /// Bytes sent since the interface came up
+    pub total_sent: u64,
+}
+
+/// Interfaces that haven't sent or recieved anything are left out
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct NetworkSnapshot {
+    /// Sorted by name
+    pub interfaces: Vec::<InterfaceSnapshot>,
+
+    /// Time between the last two updates, divide recv and sent by it to get a rate
+    #[serde(rename = "elapsed_ms", serialize_with = "millis", deserialize_with = "from_millis")]
+    pub elapsed: Duration,
+}
+
+/// One temperature sensor from lm_sensors
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct SensorSnapshot {
+    /// Truncated to 14 characters
+    pub label: String,
+
+    /// Degrees Celsius
+    pub temperature: u8,
+
+    /// Degrees Celsius, None if the sensor doesn't have a critical temperature
+    pub crit: Option::<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct SensorsSnapshot {
+    /// Sorted by label
+    pub sensors: Vec::<SensorSnapshot>,
+}
+
+/// One process from `/proc/[pid]`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct ProcessSnapshot {
+    pub pid: u32,
+    pub executable: String,
+    pub cmdline: String,
+
+    /// Kernel threads and other things without a cmdline
+    pub kernel_thread: bool,
+
+    /// CPU usage since the last update in %. Scaled by the amount of cores in top mode
+    pub cpu_usage: f32,
+
+    /// Clock ticks of CPU time used in total
+    pub cpu_time: u64,
+
+    /// Clock ticks of CPU time used since the last update
+    pub cpu_time_delta: u64,
+
+    /// Bytes
+    pub rss: u64,
+
+    /// Bytes, only there if smaps is enabled and smaps_rollup could be read
+    pub pss: Option::<u64>,
+}
+
+/// Processes that are shown depend on Config.all
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct ProcessesSnapshot {
+    /// Sorted by cpu_time_delta and then cpu_time, highest first
+    pub processes: Vec::<ProcessSnapshot>,
+}
+
+/// The first Nvidia GPU
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct GpuSnapshot {
+    /// Degrees Celsius
+    pub temperature: u32,
+
+    /// In %
+    pub gpu_load: u32,
+
+    /// Memory controller load in %
+    pub mem_load: u32,
+
+    /// Used memory in %
+    pub mem_used: f32,
+}
+
+/// Everything at once
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct Snapshot {
+    pub cpu: CpuSnapshot,
+    pub memory: MemorySnapshot,
+    pub loadavg: LoadavgSnapshot,
+    pub network: NetworkSnapshot,
+    pub sensors: SensorsSnapshot,
+    pub processes: ProcessesSnapshot,
+
+    /// None if there is no Nvidia GPU or NVML couldn't be loaded
+    pub gpu: Option::<GpuSnapshot>,
+
+    /// Seconds since the Unix epoch from the last Time update
+    pub time: u64,
+}
+
+fn millis<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+fn from_millis<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
@@ -0,0 +1,103 @@
+// Every system call megamonic makes goes through here.
+// On x86_64 they are made with inline asm. On other architectures, or if the
+// 'portable' feature is enabled, libc::syscall() is used instead.
+//
+// Everything returns what the kernel returns, so errors are negative errno values.
+
+use std::ffi::{ c_char, c_void };
+
+#[cfg(all(target_arch = "x86_64", not(feature = "portable")))]
+mod x86_64;
+#[cfg(all(target_arch = "x86_64", not(feature = "portable")))]
+use x86_64::{ syscall, nr };
+
+#[cfg(any(feature = "portable", not(target_arch = "x86_64")))]
+mod portable;
+#[cfg(any(feature = "portable", not(target_arch = "x86_64")))]
+use portable::{ syscall, nr };
+
+#[cfg(all(not(target_arch = "x86_64"), not(feature = "portable")))]
+compile_error!("The inline asm syscalls only work on x86_64, enable the 'portable' feature");
+
+// Use the current working directory with openat()
+const AT_FDCWD: isize = -100;
+
+// sigset_t is 64 bits for the kernel on every 64bit architecture
+const SIGSET_SIZE: usize = 8;
+
+pub unsafe fn open(path: *const c_char, flags: i32) -> i32 {
+    syscall(nr::OPENAT, AT_FDCWD as usize, path as usize, flags as usize, 0, 0, 0) as i32
+}
+
+pub fn close(fd: i32) -> i32 {
+    unsafe { syscall(nr::CLOSE, fd as usize, 0, 0, 0, 0, 0) as i32 }
+}
+
+pub unsafe fn read(fd: i32, buf: *mut u8, count: usize) -> isize {
+    syscall(nr::READ, fd as usize, buf as usize, count, 0, 0, 0)
+}
+
+pub unsafe fn write(fd: i32, buf: *const u8, count: usize) -> isize {
+    syscall(nr::WRITE, fd as usize, buf as usize, count, 0, 0, 0)
+}
+
+pub unsafe fn pread(fd: i32, buf: *mut u8, count: usize, offset: i64) -> isize {
+    syscall(nr::PREAD64, fd as usize, buf as usize, count, offset as usize, 0, 0)
+}
+
+pub fn lseek(fd: i32, offset: i64, whence: i32) -> i64 {
+    unsafe { syscall(nr::LSEEK, fd as usize, offset as usize, whence as usize, 0, 0, 0) as i64 }
+}
+
+pub unsafe fn getdents64(fd: i32, buf: *mut u8, count: usize) -> isize {
+    syscall(nr::GETDENTS64, fd as usize, buf as usize, count, 0, 0, 0)
+}
+
+pub unsafe fn ioctl(fd: i32, request: u32, arg: *mut c_void) -> i32 {
+    syscall(nr::IOCTL, fd as usize, request as usize, arg as usize, 0, 0, 0) as i32
+}
+
+pub fn kill(pid: u32, sig: i32) -> i32 {
+    unsafe { syscall(nr::KILL, pid as usize, sig as usize, 0, 0, 0, 0) as i32 }
+}
+
+pub unsafe fn rt_sigprocmask(how: i32, set: *const u64, oldset: *mut u64) -> i32 {
+    syscall(nr::RT_SIGPROCMASK, how as usize, set as usize, oldset as usize, SIGSET_SIZE, 0, 0) as i32
+}
+
+pub unsafe fn signalfd(fd: i32, mask: *const u64, flags: i32) -> i32 {
+    syscall(nr::SIGNALFD4, fd as usize, mask as usize, SIGSET_SIZE, flags as usize, 0, 0) as i32
+}
+
+pub fn epoll_create1(flags: i32) -> i32 {
+    unsafe { syscall(nr::EPOLL_CREATE1, flags as usize, 0, 0, 0, 0, 0) as i32 }
+}
+
+pub unsafe fn epoll_ctl(epfd: i32, op: i32, fd: i32, event: *const c_void) -> i32 {
+    syscall(nr::EPOLL_CTL, epfd as usize, op as usize, fd as usize, event as usize, 0, 0) as i32
+}
+
+// epoll_pwait without a sigmask is the same as epoll_wait, which doesn't exist on every architecture
+pub unsafe fn epoll_wait(epfd: i32, events: *mut c_void, maxevents: i32, timeout: i32) -> i32 {
+    syscall(nr::EPOLL_PWAIT, epfd as usize, events as usize, maxevents as usize, timeout as usize, 0, SIGSET_SIZE) as i32
+}
+
+pub unsafe fn mmap(addr: usize, length: usize, prot: u32, flags: u32, fd: i32, offset: u64) -> isize {
+    syscall(nr::MMAP, addr, length, prot as usize, flags as usize, fd as usize, offset as usize)
+}
+
+pub unsafe fn prlimit64(pid: u32, resource: u32, new_limit: *const u64, old_limit: *mut u64) -> i32 {
+    syscall(nr::PRLIMIT64, pid as usize, resource as usize, new_limit as usize, old_limit as usize, 0, 0) as i32
+}
+
+pub unsafe fn io_uring_setup(entries: usize, params: *mut c_void) -> i32 {
+    syscall(nr::IO_URING_SETUP, entries, params as usize, 0, 0, 0, 0) as i32
+}
+
+pub unsafe fn io_uring_enter(fd: i32, to_submit: u32, min_complete: u32, flags: u32) -> i32 {
+    syscall(nr::IO_URING_ENTER, fd as usize, to_submit as usize, min_complete as usize, flags as usize, 0, 0) as i32
+}
+
+pub unsafe fn io_uring_register(fd: i32, opcode: u32, arg: *const c_void, nr_args: u32) -> i32 {
+    syscall(nr::IO_URING_REGISTER, fd as usize, opcode as usize, arg as usize, nr_args as usize, 0, 0) as i32
+}
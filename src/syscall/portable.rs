@@ -0,0 +1,34 @@
+// System call numbers for whatever architecture libc is built for
+pub mod nr {
+    pub const READ: usize = libc::SYS_read as usize;
+    pub const WRITE: usize = libc::SYS_write as usize;
+    pub const CLOSE: usize = libc::SYS_close as usize;
+    pub const LSEEK: usize = libc::SYS_lseek as usize;
+    pub const MMAP: usize = libc::SYS_mmap as usize;
+    pub const RT_SIGPROCMASK: usize = libc::SYS_rt_sigprocmask as usize;
+    pub const IOCTL: usize = libc::SYS_ioctl as usize;
+    pub const PREAD64: usize = libc::SYS_pread64 as usize;
+    pub const KILL: usize = libc::SYS_kill as usize;
+    pub const GETDENTS64: usize = libc::SYS_getdents64 as usize;
+    pub const EPOLL_CTL: usize = libc::SYS_epoll_ctl as usize;
+    pub const OPENAT: usize = libc::SYS_openat as usize;
+    pub const EPOLL_PWAIT: usize = libc::SYS_epoll_pwait as usize;
+    pub const SIGNALFD4: usize = libc::SYS_signalfd4 as usize;
+    pub const EPOLL_CREATE1: usize = libc::SYS_epoll_create1 as usize;
+    pub const PRLIMIT64: usize = libc::SYS_prlimit64 as usize;
+    pub const IO_URING_SETUP: usize = libc::SYS_io_uring_setup as usize;
+    pub const IO_URING_ENTER: usize = libc::SYS_io_uring_enter as usize;
+    pub const IO_URING_REGISTER: usize = libc::SYS_io_uring_register as usize;
+}
+
+// libc returns -1 and sets errno, turn it into -errno like the kernel does
+#[inline(always)]
+pub unsafe fn syscall(n: usize, a1: usize, a2: usize, a3: usize, a4: usize, a5: usize, a6: usize) -> isize {
+    let ret = libc::syscall(n as libc::c_long, a1, a2, a3, a4, a5, a6);
+
+    if ret == -1 {
+        -(std::io::Error::last_os_error().raw_os_error().unwrap_or(0) as isize)
+    } else {
+        ret as isize
+    }
+}
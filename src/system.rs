@@ -1,220 +1,474 @@
-use std::arch::asm;
-
-use std::sync::{Arc, Mutex, atomic, Condvar};
+use std::sync::{Arc, Mutex, MutexGuard, atomic};
+use std::collections::HashMap;
+use std::time::Duration;
 use std::thread;
+use std::ffi::CString;
 use anyhow::{ Result, Context, bail };
 
+use crate::syscall;
+use crate::snapshot::*;
+
+mod collector;
+pub mod bus;
+
+pub use collector::Health;
+pub use cpu::Kind;
 mod cpu;
 mod loadavg;
 mod memory;
 mod sensors;
 mod network;
+// Process is used by the megamonic UI, not part of the library API
+#[doc(hidden)]
 pub mod processes;
 mod gpu;
 mod hostinfo;
 mod time;
-pub mod events;
+mod events;
 mod uring;
 
+// Which I/O backend is used to read the process files
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum IoBackend {
+    // Use io_uring if it can be set up, otherwise pread
+    #[default]
+    Auto,
+    Uring,
+    Pread,
+}
+
+// How the process list is sorted
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Sort {
+    // Work since the last update, then total work. Highest first
+    #[default]
+    Cpu,
+
+    // PSS if smaps is enabled and it could be read, otherwise RSS. Highest first
+    Memory,
+
+    Pid,
+
+    // Executable name, then pid
+    Name,
+}
+
+impl Sort {
+    pub const ALL: [Sort; 4] = [Sort::Cpu, Sort::Memory, Sort::Pid, Sort::Name];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Sort::Cpu => "cpu",
+            Sort::Memory => "memory",
+            Sort::Pid => "pid",
+            Sort::Name => "name",
+        }
+    }
+
+    // The one after this, for cycling through them
+    pub fn next(self) -> Self {
+        Sort::ALL[(self as usize + 1) % Sort::ALL.len()]
+    }
+}
+
+impl std::str::FromStr for Sort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Sort::ALL.into_iter()
+            .find(|sort| sort.name() == s)
+            .with_context(|| format!("Unknown sort order '{}', expected one of: cpu, memory, pid, name", s))
+    }
+}
+
+// Which names are shown. Patterns ending in '*' match everything starting with the rest
+// An empty include list includes everything
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Filter {
+    pub include: Vec::<String>,
+    pub exclude: Vec::<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, name: &str) -> bool {
+        let hit = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        };
+
+        (self.include.is_empty() || self.include.iter().any(hit)) && !self.exclude.iter().any(hit)
+    }
+}
+
+// Limits of every interval in milliseconds
+pub const MIN_INTERVAL: u64 = 100;
+pub const MAX_INTERVAL: u64 = 60_000;
+
+/// Clock ticks per second, the unit of the CPU times in the snapshots. USER_HZ is 100 on every architecture
+pub const CLK_TCK: f64 = 100.0;
+
 // Holds all the commandline options.
-#[derive(Default)]
 pub struct Config {
     pub smaps: atomic::AtomicBool,
     pub topmode: atomic::AtomicBool,
     pub all: atomic::AtomicBool,
+
+    // Milliseconds between updates of the collectors that aren't in 'intervals'
     pub frequency: atomic::AtomicU64,
+
+    // Collectors with their own interval in milliseconds. Use interval() to get the one in use
+    pub intervals: HashMap<bus::Source, atomic::AtomicU64>,
+
+    // Milliseconds between looking for new processes and reading their cmdlines
+    // The known processes are updated every Processes interval. 0 is every update
+    pub scan_interval: atomic::AtomicU64,
+
     pub strftime_format: String,
+    pub io_backend: IoBackend,
+
+    // A Sort as u8 so it can be changed while running, use sort() and set_sort()
+    pub sort: atomic::AtomicU8,
+
+    // Collectors that aren't started. Cpu is still run if Processes isn't in here
+    // since it needs the amount of cores
+    pub disabled: Vec::<bus::Source>,
+
+    // Network interfaces without the ':' and sensor labels
+    pub interfaces: Filter,
+    pub sensors: Filter,
+
+    // Read keys from stdin and catch SIGINT, SIGTERM and SIGWINCH. Only the terminal UI wants this
+    pub interactive: bool,
+
+    // Catch SIGINT and SIGTERM and send Event::Quit, without touching stdin. For running headless
+    pub signals: bool,
+
+    // Where procfs and sysfs are mounted, for example the host's /proc bind-mounted in a container
+    pub proc_root: String,
+    pub sys_root: String,
 }
 
-//#[derive(Default)]
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            smaps: atomic::AtomicBool::new(false),
+            topmode: atomic::AtomicBool::new(false),
+            all: atomic::AtomicBool::new(false),
+            frequency: atomic::AtomicU64::new(1000),
+            intervals: HashMap::new(),
+            scan_interval: atomic::AtomicU64::new(0),
+            strftime_format: String::from("%c"),
+            io_backend: IoBackend::Auto,
+            sort: atomic::AtomicU8::new(Sort::Cpu as u8),
+            disabled: Vec::new(),
+            interfaces: Filter::default(),
+            sensors: Filter::default(),
+            interactive: false,
+            signals: false,
+            proc_root: String::from("/proc"),
+            sys_root: String::from("/sys"),
+        }
+    }
+}
+
+impl Config {
+    pub fn sort(&self) -> Sort {
+        Sort::ALL.get(self.sort.load(atomic::Ordering::Relaxed) as usize).copied().unwrap_or_default()
+    }
+
+    pub fn set_sort(&self, sort: Sort) {
+        self.sort.store(sort as u8, atomic::Ordering::Relaxed);
+    }
+
+    // How long 'source' waits between updates
+    pub fn interval(&self, source: bus::Source) -> Duration {
+        let ms = self.intervals.get(&source).unwrap_or(&self.frequency).load(atomic::Ordering::Relaxed);
+
+        Duration::from_millis(ms)
+    }
+
+    // Multiply every interval by 'factor', within MIN_INTERVAL and MAX_INTERVAL
+    // Use System::scale_intervals() so the collectors notice right away
+    pub fn scale_intervals(&self, factor: f64) {
+        let scale = |interval: &atomic::AtomicU64| {
+            let _ = interval.fetch_update(atomic::Ordering::Relaxed, atomic::Ordering::Relaxed, |ms| {
+                // 0 means it's not used
+                (ms != 0).then(|| ((ms as f64 * factor).round() as u64).clamp(MIN_INTERVAL, MAX_INTERVAL))
+            });
+        };
+
+        scale(&self.frequency);
+        scale(&self.scan_interval);
+        self.intervals.values().for_each(scale);
+    }
+}
+
+/// The handle to all the collector threads. They are started by new() and stopped when it's dropped
+///
+/// Use [`System::snapshot`] or the functions for a single collector to get the data
 pub struct System {
     // Info gathering structs
-    pub cpuinfo: Arc<Mutex<cpu::Cpuinfo>>,
-    pub loadavg: Arc<Mutex<loadavg::Loadavg>>,
-    pub memoryinfo: Arc<Mutex<memory::Memory>>,
-    pub sensorinfo: Arc<Mutex<sensors::Sensors>>,
-    pub networkinfo: Arc<Mutex<network::Network>>,
-    pub processinfo: Arc<Mutex<processes::Processes>>,
-    pub gpuinfo: Arc<Mutex<gpu::Gpu>>,
-    pub hostinfo: hostinfo::Hostinfo,
-    pub time: Arc<time::Time>,
-
-    // Used to notify threads that they should exit
-    pub exit: Arc<(Mutex<bool>, Condvar)>,
+    cpuinfo: Arc<Mutex<cpu::Cpuinfo>>,
+    loadavg: Arc<Mutex<loadavg::Loadavg>>,
+    memoryinfo: Arc<Mutex<memory::Memory>>,
+    sensorinfo: Arc<Mutex<sensors::Sensors>>,
+    networkinfo: Arc<Mutex<network::Network>>,
+    processinfo: Arc<Mutex<processes::Processes>>,
+    gpuinfo: Arc<Mutex<gpu::Gpu>>,
+    hostinfo: hostinfo::Hostinfo,
+    time: Arc<Mutex<time::Time>>,
 
     // Options
-    pub config: Arc<Config>,
+    config: Arc<Config>,
 
-    pub inputbuffer: Arc<Mutex<String>>,
+    inputbuffer: Arc<Mutex<String>>,
 
-    // List of all thread handles
-    pub threads: Vec<thread::JoinHandle<()>>,
+    // Every update, key press and error is sent here
+    bus: bus::Bus,
 
-    // Cancer that should be refactored, holds any potential errors so it can be propagated
-    pub error: Arc<Mutex<Vec::<anyhow::Error>>>,
+    // The Event thread, the collectors are run by the scheduler
+    threads: Vec<thread::JoinHandle<()>>,
+    scheduler: collector::Scheduler,
 }
 
 impl System {
-    pub fn new(config: Config, mtx: std::sync::mpsc::Sender<u8>) -> Result<Self> {
-        let processes = processes::Processes::new().context("Can't initialize Procesess")?;
-        let loadavg = loadavg::Loadavg::new().context("Can't initialize Loadavg")?;
-        let network = network::Network::new().context("Can't initialize Network")?;
-        let cpu = cpu::Cpuinfo::new().context("Can't initialize Cpu")?;
-        let memory = memory::Memory::new().context("Can't initialize Memory")?;
-
-        let mut system = Self {
-            cpuinfo: Arc::new(Mutex::new(cpu)),
-            loadavg: Arc::new(Mutex::new(loadavg)),
-            memoryinfo: Arc::new(Mutex::new(memory)),
-            sensorinfo: Arc::new(Mutex::new(sensors::Sensors::default())),
-            networkinfo: Arc::new(Mutex::new(network)),
+    /// Subscribe to 'bus' before calling this to get every event from the start
+    pub fn new(config: Config, bus: bus::Bus) -> Result<Self> {
+        // Processes keep their files open so we want as many fds as we can get
+        let fd_limit = raise_fd_limit();
+
+        let mut system = Self::build(config, bus, fd_limit)?;
+
+        system.start();
+
+        Ok(system)
+    }
+
+    /// A System that doesn't run any collectors, the data is given to it with restore()
+    /// For showing a recording. Subscribe to 'bus' first like with new()
+    pub fn external(mut config: Config, bus: bus::Bus) -> Result<Self> {
+        // Nothing is read so io_uring isn't needed
+        config.io_backend = IoBackend::Pread;
+
+        let mut system = Self::build(config, bus, 0)?;
+
+        system.start_events();
+
+        Ok(system)
+    }
+
+    fn build(config: Config, bus: bus::Bus, fd_limit: u64) -> Result<Self> {
+        let config = Arc::new(config);
+
+        // Collectors that can't read their files fail in their first update and are retried
+        let cpu = Arc::new(Mutex::new(cpu::Cpuinfo::new(&config)));
+        let processes = processes::Processes::new(Arc::clone(&cpu), Arc::clone(&config), fd_limit).context("Can't initialize Procesess")?;
+
+        // Processes needs the amount of cores from Cpu so it has to run
+        let mut disabled = config.disabled.clone();
+        if !disabled.contains(&bus::Source::Processes) {
+            disabled.retain(|source| *source != bus::Source::Cpu);
+        }
+
+        let scheduler = collector::Scheduler::new(bus.clone(), Arc::clone(&config), disabled);
+
+        Ok(Self {
+            cpuinfo: cpu,
+            loadavg: Arc::new(Mutex::new(loadavg::Loadavg::new(&config))),
+            memoryinfo: Arc::new(Mutex::new(memory::Memory::new(&config))),
+            sensorinfo: Arc::new(Mutex::new(sensors::Sensors::new(&config))),
+            networkinfo: Arc::new(Mutex::new(network::Network::new(&config))),
             processinfo: Arc::new(Mutex::new(processes)),
             gpuinfo: Arc::new(Mutex::new(gpu::Gpu::default())),
-            hostinfo: hostinfo::Hostinfo::default(),
+            hostinfo: hostinfo::Hostinfo::new(&config),
 
-            time: Arc::new(time::Time::default()),
+            time: Arc::new(Mutex::new(time::Time::default())),
 
-            exit: Arc::new((Mutex::new(false), Condvar::new())),
-
-            config: Arc::new(config),
+            config,
             inputbuffer: Arc::new(Mutex::new(String::new())),
 
             threads: Vec::new(),
-            error: Arc::new(Mutex::new(Vec::new())),
-        };
+            scheduler,
+            bus,
+        })
+    }
 
-        system.start(mtx);
+    pub fn cpu(&self) -> CpuSnapshot {
+        lock(&self.cpuinfo).snapshot()
+    }
 
-        Ok(system)
+    pub fn memory(&self) -> MemorySnapshot {
+        lock(&self.memoryinfo).snapshot()
+    }
+
+    pub fn loadavg(&self) -> LoadavgSnapshot {
+        lock(&self.loadavg).snapshot()
+    }
+
+    pub fn network(&self) -> NetworkSnapshot {
+        lock(&self.networkinfo).snapshot()
+    }
+
+    pub fn sensors(&self) -> SensorsSnapshot {
+        lock(&self.sensorinfo).snapshot()
+    }
+
+    pub fn processes(&self) -> ProcessesSnapshot {
+        lock(&self.processinfo).snapshot()
+    }
+
+    pub fn gpu(&self) -> Option::<GpuSnapshot> {
+        lock(&self.gpuinfo).snapshot()
+    }
+
+    /// Seconds since the Unix epoch
+    pub fn time(&self) -> u64 {
+        lock(&self.time).time
+    }
+
+    /// Whether 'source' is updating, failing or not supported
+    pub fn health(&self, source: bus::Source) -> Health {
+        self.scheduler.health(source)
+    }
+
+    /// Speed up (< 1.0) or slow down (> 1.0) every collector. They are updated right away
+    /// so the new intervals are used from now on
+    pub fn scale_intervals(&self, factor: f64) {
+        self.config.scale_intervals(factor);
+        self.scheduler.wake();
+    }
+
+    /// Everything at once. Each collector is locked on its own so the parts
+    /// can be from different updates
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            cpu: self.cpu(),
+            memory: self.memory(),
+            loadavg: self.loadavg(),
+            network: self.network(),
+            sensors: self.sensors(),
+            processes: self.processes(),
+            gpu: self.gpu(),
+            time: self.time(),
+        }
+    }
+
+    /// Replace what 'source' has with its part of 'snapshot' and send Event::Updated(source)
+    /// The collectors overwrite it on their next update, so this is for a System made with external()
+    pub fn restore(&self, source: bus::Source, snapshot: &Snapshot) {
+        match source {
+            bus::Source::Time => lock(&self.time).time = snapshot.time,
+            bus::Source::Loadavg => lock(&self.loadavg).restore(&snapshot.loadavg),
+            bus::Source::Cpu => lock(&self.cpuinfo).restore(&snapshot.cpu),
+            bus::Source::Memory => lock(&self.memoryinfo).restore(&snapshot.memory),
+            bus::Source::Sensors => lock(&self.sensorinfo).restore(&snapshot.sensors),
+            bus::Source::Network => lock(&self.networkinfo).restore(&snapshot.network),
+            bus::Source::Processes => lock(&self.processinfo).restore(&snapshot.processes),
+            bus::Source::Gpu => lock(&self.gpuinfo).restore(snapshot.gpu.as_ref()),
+            bus::Source::Input => return,
+        }
+
+        self.bus.send(bus::Event::Updated(source));
     }
 
     // This function starts all the monitoring threads
-    fn start(&mut self, mtx: std::sync::mpsc::Sender<u8>) {
-        // Event thread
-        // This needs to be done BEFORE any other child threads are spawned
-        // so the rules for signal handling are inherited to all child threads
-        self.threads.push(
-            events::start_thread(
-                Arc::clone(&self.inputbuffer),
-                Arc::clone(&self.config),
-                mtx.clone(),
-            )
-        );
-
-        // Update frequency
-        let sleepy = std::time::Duration::from_millis(self.config.frequency.load(atomic::Ordering::Relaxed));
-
-        // Read /proc/stat
-        self.threads.push(
-            cpu::start_thread(
-                Arc::clone(&self.cpuinfo),
-                 mtx.clone(),
-                Arc::clone(&self.exit),
-                Arc::clone(&self.error),
-                sleepy
-            )
-        );
-
-        // Processes
-        self.threads.push(
-            processes::start_thread(
-                Arc::clone(&self.processinfo),
-                Arc::clone(&self.cpuinfo),
-                Arc::clone(&self.config),
-                mtx.clone(),
-                Arc::clone(&self.exit),
-                Arc::clone(&self.error),
-                sleepy
-            )
-        );
-
-        // Time loop
-        self.threads.push(
-            time::start_thread(
-                Arc::clone(&self.time),
-                mtx.clone(),
-                Arc::clone(&self.exit)
-            )
-        );
-
-        // Read /proc/loadavg
-        self.threads.push(
-            loadavg::start_thread(
-                Arc::clone(&self.loadavg),
-                mtx.clone(),
-                Arc::clone(&self.exit),
-                Arc::clone(&self.error),
-                sleepy
-            )
-        );
-
-        // Read /proc/meminfo
-        self.threads.push(
-            memory::start_thread(
-                Arc::clone(&self.memoryinfo),
-                mtx.clone(),
-                Arc::clone(&self.exit),
-                Arc::clone(&self.error),
-                sleepy
-            )
-        );
-
-        // Sensors
-        self.threads.push(
-            sensors::start_thread(
-                Arc::clone(&self.sensorinfo),
-                mtx.clone(),
-                Arc::clone(&self.exit),
-                sleepy
-            )
-        );
-
-        // Network
-        self.threads.push(
-            network::start_thread(
-                Arc::clone(&self.networkinfo),
-                mtx.clone(),
-                Arc::clone(&self.exit),
-                Arc::clone(&self.error),
-                sleepy
-            )
-        );
-
-        // GPU
-        self.threads.push(
-            gpu::start_thread(
-                Arc::clone(&self.gpuinfo),
-                mtx,
-                Arc::clone(&self.exit),
-                sleepy
-            )
-        );
+    fn start(&mut self) {
+        self.start_events();
+
+        if let Ok(mut gpu) = self.gpuinfo.lock() {
+            gpu.init();
+        }
+
+        self.scheduler.add(&self.cpuinfo);
+        self.scheduler.add(&self.processinfo);
+        self.scheduler.add(&self.time);
+        self.scheduler.add(&self.loadavg);
+        self.scheduler.add(&self.memoryinfo);
+        self.scheduler.add(&self.sensorinfo);
+        self.scheduler.add(&self.networkinfo);
+        self.scheduler.add(&self.gpuinfo);
+    }
+
+    // Event thread
+    // This needs to be done BEFORE any other child threads are spawned
+    // so the rules for signal handling are inherited to all child threads
+    fn start_events(&mut self) {
+        if self.config.interactive || self.config.signals {
+            self.threads.push(
+                events::start_thread(self.bus.clone(), self.config.interactive)
+            );
+        }
+    }
+}
+
+// The collectors themselves for the megamonic binary. They change whenever the collectors do,
+// library users get snapshots instead
+#[doc(hidden)]
+impl System {
+    pub fn cpuinfo(&self) -> &Mutex<cpu::Cpuinfo> {
+        &self.cpuinfo
+    }
+
+    pub fn loadavginfo(&self) -> &Mutex<loadavg::Loadavg> {
+        &self.loadavg
+    }
+
+    pub fn memoryinfo(&self) -> &Mutex<memory::Memory> {
+        &self.memoryinfo
+    }
+
+    pub fn sensorinfo(&self) -> &Mutex<sensors::Sensors> {
+        &self.sensorinfo
+    }
+
+    pub fn networkinfo(&self) -> &Mutex<network::Network> {
+        &self.networkinfo
+    }
+
+    pub fn processinfo(&self) -> &Mutex<processes::Processes> {
+        &self.processinfo
+    }
+
+    pub fn gpuinfo(&self) -> &Mutex<gpu::Gpu> {
+        &self.gpuinfo
+    }
+
+    pub fn timeinfo(&self) -> &Mutex<time::Time> {
+        &self.time
+    }
+
+    pub fn hostinfo(&self) -> &hostinfo::Hostinfo {
+        &self.hostinfo
+    }
+
+    // For --connect and --replay, to look like the other machine
+    pub fn hostinfo_mut(&mut self) -> &mut hostinfo::Hostinfo {
+        &mut self.hostinfo
+    }
+
+    pub fn config(&self) -> &Arc<Config> {
+        &self.config
+    }
+
+    // The processes filter the UI and control socket share
+    pub fn inputbuffer(&self) -> &Mutex<String> {
+        &self.inputbuffer
+    }
+
+    pub fn bus(&self) -> &bus::Bus {
+        &self.bus
     }
 }
 
 impl Drop for System {
     fn drop(&mut self) {
-        // Notify all threads that they should exit
-        let (lock, cvar) = &*self.exit;
-        if let Ok(mut exitvar) = lock.lock() {
-            *exitvar = true;
-            cvar.notify_all();
-        }
-
         // This signals the Event thread that it should exit
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 62, // SYS_KILL
-                in("rdi") std::process::id(),
-                in("rsi") 10, // SIG_USR1
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
+        // Without it SIGUSR1 isn't blocked and would kill the process
+        if self.config.interactive || self.config.signals {
+            let ret = syscall::kill(std::process::id(), 10); // SIG_USR1
 
-        assert!(!ret.is_negative(), "SYS_KILL returned: {}", ret);
+            assert!(!ret.is_negative(), "SYS_KILL returned: {}", ret);
+        }
 
         while !self.threads.is_empty() {
             if let Some(val) = self.threads.pop() {
@@ -222,25 +476,27 @@ impl Drop for System {
                 let _ = val.join();
             }
         }
+
+        // The collector threads are stopped when the scheduler is dropped
     }
 }
 
+// A collector that panicked still has usable data from its last update
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// 'path' relative to 'root', for use with open_file()
+fn root_path(root: &str, path: &str) -> CString {
+    // A path with a null in it can't be opened anyway
+    CString::new(format!("{}{}", root.trim_end_matches('/'), path)).unwrap_or_default()
+}
+
 // 'path' is a pointer to a null terminated string
 // Returns the fd of the open file
-fn open_file(path: *const i8) -> Result<i32> {
-    // Open file
-    let ret: i32;
-    unsafe {
-        asm!("syscall",
-            in("rax") 2, // SYS_OPEN
-            in("rdi") path,
-            in("rsi") 0, // O_RDONLY
-            //in("rdx") 0, // This is the mode. It is not used in this case
-            out("rcx") _,
-            out("r11") _,
-            lateout("rax") ret,
-        );
-    }
+fn open_file(path: *const std::ffi::c_char) -> Result<i32> {
+    // Open file, O_RDONLY
+    let ret = unsafe { syscall::open(path, 0) };
 
     // If there's an error it's 99.999% certain it's because the process has terminated
     if ret.is_negative() {
@@ -256,19 +512,7 @@ fn read_fd(fd: i32, buffer: &mut Vec::<u8>) -> Result<()> {
     buffer.clear();
 
     // Read file from position 0
-    let n_read: i32;
-    unsafe {
-        asm!("syscall",
-            in("rax") 17, // SYS_PREAD64
-            in("rdi") fd,
-            in("rsi") buffer.as_mut_ptr(),
-            in("rdx") buffer.capacity(),
-            in("r10") 0, // offset
-            out("rcx") _,
-            out("r11") _,
-            lateout("rax") n_read,
-        );
-    }
+    let n_read = unsafe { syscall::pread(fd, buffer.as_mut_ptr(), buffer.capacity(), 0) };
 
     if n_read.is_negative()  {
         bail!("Read error: {}", n_read);
@@ -281,3 +525,249 @@ fn read_fd(fd: i32, buffer: &mut Vec::<u8>) -> Result<()> {
 
     Ok(())
 }
+
+// Close 'fd', errors are ignored
+fn close_fd(fd: i32) {
+    syscall::close(fd);
+}
+
+// Raise the soft RLIMIT_NOFILE to the hard limit
+// Returns the new soft limit
+fn raise_fd_limit() -> u64 {
+    // struct rlimit64 { rlim_cur, rlim_max }
+    let mut limit: [u64; 2] = [0; 2];
+
+    // pid 0 == this process, 7 == RLIMIT_NOFILE
+    let ret = unsafe { syscall::prlimit64(0, 7, std::ptr::null(), limit.as_mut_ptr()) };
+
+    // Assume the usual default if it can't be read
+    if ret.is_negative() {
+        return 1024;
+    }
+
+    if limit[0] < limit[1] {
+        let new_limit: [u64; 2] = [limit[1], limit[1]];
+
+        let ret = unsafe { syscall::prlimit64(0, 7, new_limit.as_ptr(), std::ptr::null_mut()) };
+
+        if !ret.is_negative() {
+            limit[0] = limit[1];
+        }
+    }
+
+    limit[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collector::Collector;
+
+    // tests/fixtures has a recorded /proc and /sys
+    fn fixture_config() -> Config {
+        Config {
+            proc_root: concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/proc").to_string(),
+            sys_root: concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sys/").to_string(),
+            io_backend: IoBackend::Pread,
+            ..Default::default()
+        }
+    }
+
+    // Cpuinfo after one update of the fixture
+    fn fixture_cpu() -> CpuSnapshot {
+        let mut cpu = cpu::Cpuinfo::new(&fixture_config());
+        cpu.update().unwrap();
+
+        cpu.snapshot()
+    }
+
+    #[test]
+    fn cpu_usage() {
+        let mut cpu = cpu::Cpuinfo::new(&fixture_config());
+        cpu.update().unwrap();
+
+        let snapshot = cpu.snapshot();
+        assert_eq!(snapshot.cores, 2);
+        assert_eq!(snapshot.usage, 25.0);
+
+        let per_core: Vec::<(u32, f32)> = snapshot.per_core.iter().map(|core| (core.id, core.usage)).collect();
+        assert_eq!(per_core, [(0, 43.75), (1, 12.5)]);
+
+        // No clock ticks since the last update, that's 0% and not NaN
+        cpu.update().unwrap();
+        assert_eq!(cpu.snapshot().usage, 0.0);
+    }
+
+    #[test]
+    fn cpu_breakdown() {
+        let snapshot = fixture_cpu();
+
+        // The guest time is part of 'user' in /proc/stat
+        let breakdown = &snapshot.breakdown;
+        assert_eq!((breakdown.user, breakdown.guest), (6.0, 4.0));
+        assert_eq!((breakdown.system, breakdown.idle, breakdown.iowait, breakdown.steal), (10.0, 70.0, 5.0, 5.0));
+    }
+
+    #[test]
+    fn cpufreq() {
+        let snapshot = fixture_cpu();
+        assert_eq!(snapshot.governor, "schedutil");
+
+        // policy2 only has offline cores
+        let policies: Vec::<(u32, &[u32], &str, &str)> = snapshot.policies.iter()
+            .map(|policy| (policy.id, policy.cpus.as_slice(), policy.governor.as_str(), policy.epp.as_str()))
+            .collect();
+        assert_eq!(policies, [(0, &[0][..], "schedutil", "balance_performance"), (1, &[1][..], "performance", "")]);
+
+        let frequencies: Vec::<(u64, u64, u64)> = snapshot.per_core.iter().map(|core| (core.frequency, core.min_frequency, core.max_frequency)).collect();
+        assert_eq!(frequencies, [(3400000, 800000, 4700000), (800000, 800000, 2000000)]);
+        assert_eq!(snapshot.policies[1].hardware_max, 4700000);
+
+        // Lots of VMs don't have cpufreq
+        let mut cpu = cpu::Cpuinfo::new(&Config { sys_root: String::from("/nonexistent"), ..fixture_config() });
+        cpu.update().unwrap();
+
+        let snapshot = cpu.snapshot();
+        assert!(snapshot.policies.is_empty());
+        assert_eq!(snapshot.governor, "");
+        assert!(snapshot.per_core.iter().all(|core| core.frequency == 0));
+    }
+
+    #[test]
+    fn cpufreq_refresh() {
+        let sys_root = std::env::temp_dir().join(format!("megamonic-cpufreq-{}", std::process::id()));
+        let policy = sys_root.join("devices/system/cpu/cpufreq/policy0");
+        std::fs::create_dir_all(&policy).unwrap();
+
+        let write = |file: &str, value: &str| std::fs::write(policy.join(file), value).unwrap();
+        write("affected_cpus", "0 1\n");
+        write("scaling_cur_freq", "1000000\n");
+        write("scaling_max_freq", "4000000\n");
+        write("scaling_governor", "powersave\n");
+
+        let mut cpu = cpu::Cpuinfo::new(&Config { sys_root: sys_root.to_string_lossy().into_owned(), ..fixture_config() });
+        cpu.update().unwrap();
+
+        // Only the current frequency is read on every update, the rest when they're refreshed
+        write("scaling_cur_freq", "2000000\n");
+        write("scaling_governor", "performance\n");
+        cpu.update().unwrap();
+
+        let snapshot = cpu.snapshot();
+        std::fs::remove_dir_all(&sys_root).unwrap();
+
+        assert_eq!(snapshot.policies[0].frequency, 2000000);
+        assert_eq!(snapshot.policies[0].max_frequency, 4000000);
+        assert_eq!(snapshot.governor, "powersave");
+    }
+
+    #[test]
+    fn topology() {
+        let snapshot = fixture_cpu();
+
+        let topology: Vec::<(u32, u32, u32, &[u32], &str)> = snapshot.per_core.iter()
+            .map(|core| (core.package, core.core_id, core.node, core.siblings.as_slice(), core.kind.as_str()))
+            .collect();
+        assert_eq!(topology, [(0, 0, 0, &[0][..], "performance"), (0, 8, 1, &[1][..], "efficiency")]);
+    }
+
+    #[test]
+    fn memory() {
+        let mut memory = memory::Memory::new(&fixture_config());
+        memory.update().unwrap();
+
+        let snapshot = memory.snapshot();
+        assert_eq!(snapshot.total, 8000000 * 1024);
+        assert_eq!(snapshot.used, 2000000 * 1024);
+        assert_eq!(snapshot.swap_total, 2000000 * 1024);
+        assert_eq!(snapshot.swap_used, 500000 * 1024);
+    }
+
+    #[test]
+    fn loadavg() {
+        let mut loadavg = loadavg::Loadavg::new(&fixture_config());
+        loadavg.update().unwrap();
+
+        let snapshot = loadavg.snapshot();
+        assert_eq!((snapshot.min1, snapshot.min5, snapshot.min15), (0.52, 0.58, 0.59));
+    }
+
+    #[test]
+    fn network() {
+        let mut network = network::Network::new(&fixture_config());
+        network.update().unwrap();
+
+        // ifb0 hasn't done anything so it's left out
+        let snapshot = network.snapshot();
+        let names: Vec::<&str> = snapshot.interfaces.iter().map(|interface| interface.name.as_str()).collect();
+        assert_eq!(names, ["eth0:", "lo:"]);
+        assert_eq!(snapshot.interfaces[0].total_recv, 6500466);
+        assert_eq!(snapshot.interfaces[0].total_sent, 48648);
+    }
+
+    #[test]
+    fn processes() {
+        let config = Arc::new(fixture_config());
+        let cpu = Arc::new(Mutex::new(cpu::Cpuinfo::new(&config)));
+
+        let mut processes = processes::Processes::new(cpu, config, 1024).unwrap();
+        processes.update().unwrap();
+
+        let snapshot = processes.snapshot();
+        assert_eq!(snapshot.processes.len(), 1);
+
+        let process = &snapshot.processes[0];
+        assert_eq!(process.pid, 42);
+        assert_eq!(process.executable, "fixture");
+        assert_eq!(process.cmdline, "--flag value");
+        assert_eq!(process.rss, 25 * 4096);
+        assert_eq!(process.pss, None);
+    }
+
+    #[test]
+    fn filter() {
+        let filter = Filter {
+            include: vec![String::from("eth*"), String::from("lo")],
+            exclude: vec![String::from("eth1")],
+        };
+
+        assert!(filter.matches("eth0"));
+        assert!(filter.matches("lo"));
+        assert!(!filter.matches("eth1"));
+        assert!(!filter.matches("lo0"));
+        assert!(!filter.matches("wlan0"));
+        assert!(Filter::default().matches("anything"));
+    }
+
+    #[test]
+    fn scale_intervals() {
+        let config = Config {
+            intervals: HashMap::from([(bus::Source::Sensors, atomic::AtomicU64::new(40_000))]),
+            ..Default::default()
+        };
+
+        config.scale_intervals(0.5);
+        assert_eq!(config.interval(bus::Source::Cpu), Duration::from_millis(500));
+        assert_eq!(config.interval(bus::Source::Sensors), Duration::from_millis(20_000));
+
+        // Clamped, and 0 stays unused
+        config.scale_intervals(0.01);
+        assert_eq!(config.interval(bus::Source::Cpu), Duration::from_millis(MIN_INTERVAL));
+        assert_eq!(config.scan_interval.load(atomic::Ordering::Relaxed), 0);
+
+        config.scale_intervals(1000.0);
+        assert_eq!(config.interval(bus::Source::Sensors), Duration::from_millis(MAX_INTERVAL));
+    }
+
+    #[test]
+    fn missing_root() {
+        let config = Config {
+            proc_root: String::from("/nonexistent"),
+            ..Default::default()
+        };
+
+        // It fails in update() so it can be retried, not when it's made
+        let mut loadavg = loadavg::Loadavg::new(&config);
+        assert!(loadavg.update().is_err());
+    }
+}
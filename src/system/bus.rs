@@ -0,0 +1,166 @@
+use serde::{ Deserialize, Serialize };
+use std::sync::{ Arc, Mutex, mpsc };
+
+use super::Sort;
+
+// Where an Event comes from. Serialized in lowercase, like "loadavg"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Time,
+    Loadavg,
+    Cpu,
+    Memory,
+    Sensors,
+    Network,
+    Processes,
+    Gpu,
+
+    // The Event thread, stdin and signals
+    Input,
+}
+
+impl Source {
+    // Every collector, in the order the UI draws them
+    pub const COLLECTORS: [Source; 8] = [
+        Source::Time,
+        Source::Loadavg,
+        Source::Cpu,
+        Source::Memory,
+        Source::Sensors,
+        Source::Network,
+        Source::Processes,
+        Source::Gpu,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Time => "Time",
+            Source::Loadavg => "Load Average",
+            Source::Cpu => "Cpu",
+            Source::Memory => "Memory",
+            Source::Sensors => "Sensors",
+            Source::Network => "Network",
+            Source::Processes => "Processes",
+            Source::Gpu => "Gpu",
+            Source::Input => "Input",
+        }
+    }
+}
+
+// A key read from stdin. Only the first character of each read is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Escape,
+    Backspace,
+}
+
+impl Key {
+    // Returns None for anything that isn't handled
+    pub fn parse(buf: &[u8]) -> Option::<Self> {
+        match buf.first()? {
+            b'\r' | b'\n' => Some(Key::Enter),
+            27 => Some(Key::Escape),
+            127 => Some(Key::Backspace),
+            _ => std::str::from_utf8(buf).ok()?
+                .chars()
+                .next()
+                .filter(|c| !c.is_control())
+                .map(Key::Char),
+        }
+    }
+}
+
+// One of the Config toggles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Toggle {
+    Smaps,
+    Topmode,
+    All,
+}
+
+// Something to do that didn't come from a key, like a line from the control socket of the megamonic binary
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    // Only show processes with this in their name. Empty shows all of them
+    Filter(String),
+
+    Toggle(Toggle),
+    Sort(Sort),
+
+    // Stop or start drawing the UI
+    Pause(bool),
+
+    // Everything as JSON, with this many processes
+    Dump(usize),
+
+    // The toggles, sort order and filter as JSON
+    State,
+}
+
+// Where the answer to a Command goes. The first one is used if there's more than one subscriber
+#[derive(Debug, Clone)]
+pub struct Reply(mpsc::Sender<Result<String, String>>);
+
+impl Reply {
+    pub fn new() -> (Self, mpsc::Receiver<Result<String, String>>) {
+        let (tx, rx) = mpsc::channel();
+
+        (Self(tx), rx)
+    }
+
+    // Nothing happens if whoever sent the Command stopped waiting
+    pub fn send(&self, result: anyhow::Result<String>) {
+        let _ = self.0.send(result.map_err(|err| format!("{:#}", err)));
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    // A collector has new data
+    Updated(Source),
+
+    // Someone pressed a key
+    Key(Key),
+
+    // The collector's update failed. It keeps running and is retried with a backoff, see Health
+    // Source::Input is the exception, the Event thread has stopped
+    Error(Source, Arc<anyhow::Error>),
+
+    // The terminal was resized, in columns and rows
+    Resize { width: u16, height: u16 },
+
+    // SIGINT or SIGTERM was caught
+    Quit,
+
+    // Do something and send the answer to the Reply
+    Command(Command, Reply),
+}
+
+// Sends every Event to all subscribers. Clones share the same subscribers
+#[derive(Clone, Default)]
+pub struct Bus {
+    subscribers: Arc<Mutex<Vec::<mpsc::Sender<Event>>>>,
+}
+
+impl Bus {
+    // Get every Event sent from now on
+    pub fn subscribe(&self) -> mpsc::Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(tx);
+        }
+
+        rx
+    }
+
+    // Subscribers that have dropped their Receiver are removed
+    pub fn send(&self, event: Event) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::Result;
+use std::sync::{ Arc, Mutex, Condvar, atomic };
+use std::collections::HashMap;
+use std::time::Duration;
+use std::thread::JoinHandle;
+
+use super::Config;
+use super::bus::{ Bus, Event, Source };
+
+// Failed collectors are retried after interval * 2^failures, but never less often than this
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// How a collector is doing
+#[derive(Debug, Clone, Default)]
+pub enum Health {
+    // Hasn't been updated yet
+    #[default]
+    Starting,
+
+    Ok,
+
+    // The last update failed, it's tried again after 'retry'
+    Failed { error: Arc<anyhow::Error>, retry: Duration },
+
+    // Not available on this system, it's never updated
+    Unsupported,
+
+    // Turned off in Config.disabled, it's never updated
+    Disabled,
+}
+
+impl Health {
+    // True if the data can be shown
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Health::Starting | Health::Ok)
+    }
+}
+
+// A source of data that the Scheduler updates on a timer in its own thread
+pub trait Collector: Send + 'static {
+    // Name of the thread
+    const NAME: &'static str;
+
+    // Event::Updated(SOURCE) is sent after every update
+    const SOURCE: Source;
+
+    fn update(&mut self) -> Result<()>;
+
+    // How long to wait after an update. 'frequency' is Config.interval(SOURCE)
+    fn interval(&self, frequency: Duration) -> Duration {
+        frequency
+    }
+
+    // If it's false no thread is started. For things like a GPU that might not be there
+    fn enabled(&self) -> bool {
+        true
+    }
+}
+
+// Runs every collector in its own thread until it's dropped
+pub struct Scheduler {
+    bus: Bus,
+
+    // Used to notify threads that they should exit
+    exit: Arc<(Mutex<bool>, Condvar)>,
+
+    // Bumped by wake(). Threads that see it change stop waiting and update
+    generation: Arc<atomic::AtomicU64>,
+
+    // The intervals are read before every wait so they can be changed while running
+    config: Arc<Config>,
+
+    // Sources that aren't in here are Health::Starting
+    health: Arc<Mutex<HashMap<Source, Health>>>,
+
+    // Collectors that aren't started
+    disabled: Vec::<Source>,
+
+    threads: Vec::<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    pub fn new(bus: Bus, config: Arc<Config>, disabled: Vec::<Source>) -> Self {
+        Self {
+            bus,
+            exit: Arc::new((Mutex::new(false), Condvar::new())),
+            generation: Arc::new(atomic::AtomicU64::new(0)),
+            config,
+            health: Arc::new(Mutex::new(HashMap::new())),
+            disabled,
+            threads: Vec::new(),
+        }
+    }
+
+    pub fn health(&self, source: Source) -> Health {
+        self.health.lock()
+            .map(|health| health.get(&source).cloned().unwrap_or_default())
+            .unwrap_or_default()
+    }
+
+    // Update every collector now instead of when their current wait is over
+    pub fn wake(&self) {
+        let (lock, cvar) = &*self.exit;
+
+        // Bumped with the lock held so a thread can't miss it between reading it and waiting
+        if let Ok(_exitvar) = lock.lock() {
+            self.generation.fetch_add(1, atomic::Ordering::Relaxed);
+            cvar.notify_all();
+        }
+    }
+
+    pub fn add<C: Collector>(&mut self, collector: &Arc<Mutex<C>>) {
+        if self.disabled.contains(&C::SOURCE) {
+            set_health(&self.health, C::SOURCE, Health::Disabled);
+
+            return;
+        }
+
+        match collector.lock() {
+            Ok(val) if val.enabled() => (),
+            _ => {
+                set_health(&self.health, C::SOURCE, Health::Unsupported);
+                self.bus.send(Event::Updated(C::SOURCE));
+
+                return;
+            },
+        }
+
+        let internal = Arc::clone(collector);
+        let bus = self.bus.clone();
+        let exit = Arc::clone(&self.exit);
+        let health = Arc::clone(&self.health);
+        let generation = Arc::clone(&self.generation);
+        let config = Arc::clone(&self.config);
+
+        self.threads.push(std::thread::Builder::new().name(C::NAME.to_string()).spawn(move || {
+            let (lock, cvar) = &*exit;
+
+            // Amount of updates in a row that have failed
+            let mut failures: u32 = 0;
+
+            'outer: loop {
+                let frequency = config.interval(C::SOURCE);
+
+                let result = match internal.lock() {
+                    Ok(mut val) => val.update().map(|_| val.interval(frequency)),
+                    Err(_) => break,
+                };
+
+                // Errors don't stop the collector, it's retried with a backoff
+                let sleepy = match result {
+                    Ok(sleepy) => {
+                        failures = 0;
+                        set_health(&health, C::SOURCE, Health::Ok);
+
+                        bus.send(Event::Updated(C::SOURCE));
+
+                        sleepy
+                    },
+                    Err(err) => {
+                        failures += 1;
+                        let retry = frequency.saturating_mul(1u32 << failures.min(16)).min(MAX_BACKOFF);
+
+                        let error = Arc::new(err.context(format!("{} collector failed", C::NAME)));
+                        set_health(&health, C::SOURCE, Health::Failed { error: Arc::clone(&error), retry });
+
+                        bus.send(Event::Error(C::SOURCE, error));
+
+                        retry
+                    },
+                };
+
+                if let Ok(mut exitvar) = lock.lock() {
+                    let woken = generation.load(atomic::Ordering::Relaxed);
+
+                    loop {
+                        if let Ok(result) = cvar.wait_timeout(exitvar, sleepy) {
+                            exitvar = result.0;
+
+                            if *exitvar {
+                                break 'outer;
+                            }
+
+                            // The intervals were changed, update now
+                            if result.1.timed_out() || generation.load(atomic::Ordering::Relaxed) != woken {
+                                break;
+                            }
+                        } else {
+                            break 'outer;
+                        }
+                    }
+                } else {
+                    break;
+                }
+            }
+        }).unwrap_or_else(|_| panic!("Couldn't spawn {} thread", C::NAME)));
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        // Notify all threads that they should exit
+        let (lock, cvar) = &*self.exit;
+        if let Ok(mut exitvar) = lock.lock() {
+            *exitvar = true;
+            cvar.notify_all();
+        }
+
+        while let Some(thread) = self.threads.pop() {
+            // If the thread is broken just go to the next one
+            let _ = thread.join();
+        }
+    }
+}
+
+fn set_health(health: &Mutex<HashMap<Source, Health>>, source: Source, state: Health) {
+    if let Ok(mut health) = health.lock() {
+        health.insert(source, state);
+    }
+}
@@ -1,11 +1,20 @@
-use std::arch::asm;
-
 use anyhow::{ anyhow, Context, Result };
-use std::sync::{ Arc, Mutex, mpsc };
+use std::ffi::CString;
+use std::time::{ Duration, Instant };
+
+use super::{ read_fd, open_file, root_path, Config, collector::Collector, bus::Source };
+use crate::syscall;
+use crate::snapshot::{ CpuSnapshot, CpuTimesSnapshot, CpuBreakdownSnapshot, CoreSnapshot, PolicySnapshot };
 
-use super::{ read_fd, open_file };
+mod cpufreq;
+mod topology;
+pub use cpufreq::Policy;
+pub use topology::{ Kind, Topology };
 
-#[derive(Default)]
+// How often the cpufreq limits, governors and EPP are read again, besides after a rescan
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default, Clone)]
 struct Cpustats {
     user: u64,
     nice: u64,
@@ -15,103 +24,339 @@ struct Cpustats {
     irq: u64,
     softirq: u64,
     steal: u64,
+
+    // Already counted in 'user' and 'nice'
+    guest: u64,
+    guest_nice: u64,
+}
+
+impl Cpustats {
+    // The numbers after 'cpu' or 'cpuN' on a line of /proc/stat
+    fn parse(line: &str) -> Result<Self> {
+        let mut stats = Self::default();
+
+        for (i, s) in line.split_ascii_whitespace().enumerate() {
+            match i {
+                0 => stats.user = s.parse::<u64>().context("Can't parse 'user'")?,
+                1 => stats.nice = s.parse::<u64>().context("Can't parse 'nice'")?,
+                2 => stats.system = s.parse::<u64>().context("Can't parse 'system'")?,
+                3 => stats.idle = s.parse::<u64>().context("Can't parse 'idle'")?,
+                4 => stats.iowait = s.parse::<u64>().context("Can't parse 'iowait'")?,
+                5 => stats.irq = s.parse::<u64>().context("Can't parse 'irq'")?,
+                6 => stats.softirq = s.parse::<u64>().context("Can't parse 'softirq'")?,
+                7 => stats.steal = s.parse::<u64>().context("Can't parse 'steal'")?,
+                8 => stats.guest = s.parse::<u64>().context("Can't parse 'guest'")?,
+                9 => stats.guest_nice = s.parse::<u64>().context("Can't parse 'guest_nice'")?,
+                _ => break,
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn idle(&self) -> u64 {
+        self.idle + self.iowait
+    }
+
+    fn non_idle(&self) -> u64 {
+        self.user
+            + self.nice
+            + self.system
+            + self.irq
+            + self.softirq
+            + self.steal
+    }
+}
+
+// Where the time since the last update went, in % of all of it
+// Guest time is taken out of 'user' and 'nice' so it adds up to 100
+#[derive(Default, Clone, Copy)]
+pub struct Breakdown {
+    pub user: f32,
+    pub nice: f32,
+    pub system: f32,
+    pub idle: f32,
+    pub iowait: f32,
+    pub irq: f32,
+    pub softirq: f32,
+    pub steal: f32,
+    pub guest: f32,
+    pub guest_nice: f32,
+}
+
+impl Breakdown {
+    fn between(prev: &Cpustats, now: &Cpustats) -> Self {
+        let total = (now.idle() + now.non_idle()).saturating_sub(prev.idle() + prev.non_idle());
+
+        if total == 0 {
+            return Self::default();
+        }
+
+        let percent = |now: u64, prev: u64| now.saturating_sub(prev) as f32 / total as f32 * 100.0;
+
+        Self {
+            user: percent(now.user.saturating_sub(now.guest), prev.user.saturating_sub(prev.guest)),
+            nice: percent(now.nice.saturating_sub(now.guest_nice), prev.nice.saturating_sub(prev.guest_nice)),
+            system: percent(now.system, prev.system),
+            idle: percent(now.idle, prev.idle),
+            iowait: percent(now.iowait, prev.iowait),
+            irq: percent(now.irq, prev.irq),
+            softirq: percent(now.softirq, prev.softirq),
+            steal: percent(now.steal, prev.steal),
+            guest: percent(now.guest, prev.guest),
+            guest_nice: percent(now.guest_nice, prev.guest_nice),
+        }
+    }
+}
+
+// One of the cpuN lines of /proc/stat
+pub struct Core {
+    // The N in cpuN. CPUs that are offline are left out so they don't have to be in order
+    pub id: u32,
+
+    // Since the last update in %
+    pub usage: f32,
+
+    // From its cpufreq policy in kHz, 0 if it doesn't have one
+    pub frequency: u64,
+    pub min_frequency: u64,
+    pub max_frequency: u64,
+
+    pub topology: Topology,
+
+    stats: Cpustats,
+}
+
+impl Core {
+    fn update(&mut self, stats: Cpustats) {
+        // The counters can go backwards when a CPU comes back online
+        let busy = stats.non_idle().saturating_sub(self.stats.non_idle());
+        let total = busy + stats.idle().saturating_sub(self.stats.idle());
+
+        self.usage = match total {
+            0 => 0.0,
+            _ => busy as f32 / total as f32 * 100.0,
+        };
+
+        self.stats = stats;
+    }
 }
 
 pub struct Cpuinfo {
     pub cpu_avg: f32,
     pub totald: u64,
-    pub cpu_count: u8,
+    pub cpu_count: u32,
+
+    // Of the first policy
     pub governor: String,
+    pub breakdown: Breakdown,
+
+    // Sorted by id
+    pub cores: Vec::<Core>,
+
+    // Sorted by id, empty without cpufreq
+    pub policies: Vec::<Policy>,
+
     buffer: String,
+    freq_buffer: String,
     idle: u64,
     non_idle: u64,
     stats: Cpustats,
     cpu_fd: i32,
-    gov_fd: i32,
-}
+    stat_path: CString,
+    sys_root: String,
 
-impl Cpuinfo {
-    pub fn new() -> Result<Self> {
-        let cpu_fd = open_file(c"/proc/stat".as_ptr()).context("Can't open /proc/stat")?;
-
-        let mut buffer = String::with_capacity(5000);
-
-        unsafe {
-            read_fd(cpu_fd, buffer.as_mut_vec()).context("Can't read /proc/stat")?;
-        }
-
-        let mut cpu_count = 0;
-        for line in buffer.lines().skip(1) {
-                if line.starts_with("cpu") {
-                    cpu_count += 1;
-                } else {
-                    break;
-                }
-        }
+    // The policies and topology are read again when a core goes offline or comes online
+    rescan: bool,
 
-        let gov_fd = open_file(c"/sys/devices/system/cpu/cpufreq/policy0/scaling_governor".as_ptr()).context("Can't open /sys/devices/system/cpu/cpufreq/policy0/scaling_governor")?;
+    // When Policy::refresh() was last done, None until the first update
+    refreshed: Option::<Instant>,
+}
 
-        Ok(Self {
+impl Cpuinfo {
+    // The files are opened by update() so a failure can be retried
+    pub fn new(config: &Config) -> Self {
+        Self {
             cpu_avg: 0.0,
             totald: 0,
-            cpu_count,
+            cpu_count: 0,
             governor: String::with_capacity(100),
-            buffer,
+            breakdown: Breakdown::default(),
+            cores: Vec::new(),
+            policies: Vec::new(),
+            buffer: String::with_capacity(5000),
+            freq_buffer: String::with_capacity(100),
             idle: 0,
             non_idle: 0,
             stats: Cpustats::default(),
-            cpu_fd,
-            gov_fd
-        })
-    }
-    pub fn update(&mut self) -> Result<()> {
-        unsafe {
-            read_fd(self.gov_fd, self.governor.as_mut_vec()).context("Can't read /sys/devices/system/cpu/cpufreq/policy0/scaling_governor")?;
+            cpu_fd: -1,
+            stat_path: root_path(&config.proc_root, "/stat"),
+            sys_root: config.sys_root.clone(),
+            rescan: true,
+            refreshed: None,
         }
+    }
 
-        unsafe {
-            read_fd(self.cpu_fd, self.buffer.as_mut_vec()).context("Can't read /proc/stat")?;
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            usage: self.cpu_avg,
+            cores: self.cpu_count,
+            governor: self.governor.trim().to_string(),
+            per_core: self.cores.iter()
+                .map(|core| CoreSnapshot {
+                    id: core.id,
+                    usage: core.usage,
+                    frequency: core.frequency,
+                    min_frequency: core.min_frequency,
+                    max_frequency: core.max_frequency,
+                    package: core.topology.package,
+                    die: core.topology.die,
+                    core_id: core.topology.core,
+                    node: core.topology.node,
+                    siblings: core.topology.siblings.clone(),
+                    kind: core.topology.kind.name().to_string(),
+                })
+                .collect(),
+            policies: self.policies.iter()
+                .map(|policy| PolicySnapshot {
+                    id: policy.id,
+                    cpus: policy.cpus.clone(),
+                    governor: policy.governor.clone(),
+                    epp: policy.epp.clone(),
+                    frequency: policy.frequency,
+                    min_frequency: policy.min_frequency,
+                    max_frequency: policy.max_frequency,
+                    hardware_min: policy.hardware_min,
+                    hardware_max: policy.hardware_max,
+                })
+                .collect(),
+            times: CpuTimesSnapshot {
+                user: self.stats.user,
+                nice: self.stats.nice,
+                system: self.stats.system,
+                idle: self.stats.idle,
+                iowait: self.stats.iowait,
+                irq: self.stats.irq,
+                softirq: self.stats.softirq,
+                steal: self.stats.steal,
+                guest: self.stats.guest,
+                guest_nice: self.stats.guest_nice,
+            },
+            breakdown: CpuBreakdownSnapshot {
+                user: self.breakdown.user,
+                nice: self.breakdown.nice,
+                system: self.breakdown.system,
+                idle: self.breakdown.idle,
+                iowait: self.breakdown.iowait,
+                irq: self.breakdown.irq,
+                softirq: self.breakdown.softirq,
+                steal: self.breakdown.steal,
+                guest: self.breakdown.guest,
+                guest_nice: self.breakdown.guest_nice,
+            },
         }
+    }
 
-        let line = self.buffer.lines().next().ok_or_else(||anyhow!("Can't parse /proc/stat"))?;
+    // Show 'snapshot' instead of what was read, see System::restore()
+    pub fn restore(&mut self, snapshot: &CpuSnapshot) {
+        self.cpu_avg = snapshot.usage;
+        self.cpu_count = snapshot.cores;
 
-        // Save previous stats
-        let prev_idle = self.stats.idle + self.stats.iowait;
-        let prev_non_idle = self.stats.user
-            + self.stats.nice
-            + self.stats.system
-            + self.stats.irq
-            + self.stats.softirq
-            + self.stats.steal;
-
-        for (i, s) in line.split_ascii_whitespace().skip(1).enumerate() {
-            match i {
-                0 => self.stats.user = s.parse::<u64>().context("Can't parse 'user'")?,
-                1 => self.stats.nice = s.parse::<u64>().context("Can't parse 'nice'")?,
-                2 => self.stats.system = s.parse::<u64>().context("Can't parse 'system'")?,
-                3 => self.stats.idle = s.parse::<u64>().context("Can't parse 'idle'")?,
-                4 => self.stats.iowait = s.parse::<u64>().context("Can't parse 'iowait'")?,
-                5 => self.stats.irq = s.parse::<u64>().context("Can't parse 'irq'")?,
-                6 => self.stats.softirq = s.parse::<u64>().context("Can't parse 'softirq'")?,
-                7 => self.stats.steal = s.parse::<u64>().context("Can't parse 'steal'")?,
-                _ => break,
-            }
+        self.cores = snapshot.per_core.iter()
+            .map(|core| Core {
+                id: core.id,
+                usage: core.usage,
+                frequency: core.frequency,
+                min_frequency: core.min_frequency,
+                max_frequency: core.max_frequency,
+                topology: Topology {
+                    package: core.package,
+                    die: core.die,
+                    core: core.core_id,
+                    node: core.node,
+                    siblings: core.siblings.clone(),
+                    kind: Kind::from_name(&core.kind),
+                },
+                stats: Cpustats::default(),
+            })
+            .collect();
+
+        self.policies = snapshot.policies.iter()
+            .map(|snapshot| {
+                let mut policy = Policy::empty(snapshot.id);
+                policy.cpus = snapshot.cpus.clone();
+                policy.governor = snapshot.governor.clone();
+                policy.epp = snapshot.epp.clone();
+                policy.frequency = snapshot.frequency;
+                policy.min_frequency = snapshot.min_frequency;
+                policy.max_frequency = snapshot.max_frequency;
+                policy.hardware_min = snapshot.hardware_min;
+                policy.hardware_max = snapshot.hardware_max;
+
+                policy
+            })
+            .collect();
+
+        self.governor.clear();
+        self.governor.push_str(&snapshot.governor);
+
+        let times = &snapshot.times;
+        self.stats = Cpustats {
+            user: times.user,
+            nice: times.nice,
+            system: times.system,
+            idle: times.idle,
+            iowait: times.iowait,
+            irq: times.irq,
+            softirq: times.softirq,
+            steal: times.steal,
+            guest: times.guest,
+            guest_nice: times.guest_nice,
+        };
+
+        let breakdown = &snapshot.breakdown;
+        self.breakdown = Breakdown {
+            user: breakdown.user,
+            nice: breakdown.nice,
+            system: breakdown.system,
+            idle: breakdown.idle,
+            iowait: breakdown.iowait,
+            irq: breakdown.irq,
+            softirq: breakdown.softirq,
+            steal: breakdown.steal,
+            guest: breakdown.guest,
+            guest_nice: breakdown.guest_nice,
+        };
+    }
+}
+
+impl Cpuinfo {
+    // Missing cpufreq files aren't an error, the frequencies are 0 then
+    fn update_frequencies(&mut self) {
+        for core in self.cores.iter_mut() {
+            core.frequency = 0;
+            core.min_frequency = 0;
+            core.max_frequency = 0;
         }
 
-        self.idle = self.stats.idle + self.stats.iowait;
-        self.non_idle = self.stats.user
-            + self.stats.nice
-            + self.stats.system
-            + self.stats.irq
-            + self.stats.softirq
-            + self.stats.steal;
+        for policy in self.policies.iter_mut() {
+            policy.update(&mut self.freq_buffer);
 
-        // This is saved for use in process.rs to calculate cpu usage
-        self.totald = self.idle + self.non_idle - prev_idle - prev_non_idle;
+            for cpu in policy.cpus.iter() {
+                if let Ok(idx) = self.cores.binary_search_by_key(cpu, |core| core.id) {
+                    let core = &mut self.cores[idx];
+                    core.frequency = policy.frequency;
+                    core.min_frequency = policy.min_frequency;
+                    core.max_frequency = policy.max_frequency;
+                }
+            }
+        }
 
-        self.cpu_avg = ((self.non_idle - prev_non_idle) as f32 / self.totald as f32) * 100.0;
+        self.governor.clear();
 
-        Ok(())
+        if let Some(policy) = self.policies.first() {
+            self.governor.push_str(&policy.governor);
+        }
     }
 }
 
@@ -119,73 +364,123 @@ impl Drop for Cpuinfo {
     fn drop(&mut self) {
         // Close any open FDs when it's dropped
         if self.cpu_fd > 0 {
-            unsafe {
-                asm!("syscall",
-                    in("rax") 3, // SYS_CLOSE
-                    in("rdi") self.cpu_fd,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") _,
-                );
-            }
+            syscall::close(self.cpu_fd);
+        }
+    }
+}
+
+impl Collector for Cpuinfo {
+    const NAME: &'static str = "Cpu";
+    const SOURCE: Source = Source::Cpu;
+
+    fn update(&mut self) -> Result<()> {
+        if self.cpu_fd < 0 {
+            self.cpu_fd = open_file(self.stat_path.as_ptr()).with_context(|| format!("Can't open {}", self.stat_path.to_string_lossy()))?;
         }
 
-        if self.gov_fd > 0 {
+        loop {
             unsafe {
-                asm!("syscall",
-                    in("rax") 3, // SYS_CLOSE
-                    in("rdi") self.gov_fd,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") _,
-                );
+                read_fd(self.cpu_fd, self.buffer.as_mut_vec()).with_context(|| format!("Can't read {}", self.stat_path.to_string_lossy()))?;
             }
+
+            // Only the cpu lines at the start are used. It's about 70 bytes per core,
+            // so read it again with a bigger buffer until there's a whole line after them
+            let cpu_lines_done = self.buffer.rfind('\n')
+                .is_some_and(|end| self.buffer[..end].lines().any(|line| !line.starts_with("cpu")));
+
+            if cpu_lines_done || self.buffer.len() < self.buffer.capacity() {
+                break;
+            }
+
+            self.buffer.reserve(self.buffer.capacity() * 2);
         }
-    }
-}
 
-pub fn start_thread(internal: Arc<Mutex<Cpuinfo>>, tx: mpsc::Sender::<u8>, exit: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>, error: Arc<Mutex<Vec::<anyhow::Error>>>, sleepy: std::time::Duration) -> std::thread::JoinHandle<()> {
-    std::thread::Builder::new().name("Cpu".to_string()).spawn(move || {
-        let (lock, cvar) = &*exit;
-        'outer: loop {
-            match internal.lock() {
-                Ok(mut val) => {
-                    if let Err(err) = val.update() {
-                        let mut errvec = error.lock().expect("Error lock couldn't be aquired!");
+        let mut lines = self.buffer.lines();
 
-                        errvec.push(err);
-                        let _ = tx.send(99);
+        let line = lines.next()
+            .and_then(|line| line.strip_prefix("cpu "))
+            .ok_or_else(|| anyhow!("Can't parse /proc/stat"))?;
 
-                        break;
-                    }
-                },
-                Err(_) => break
+        // Save previous stats
+        let prev = std::mem::replace(&mut self.stats, Cpustats::parse(line)?);
+        let prev_idle = prev.idle();
+        let prev_non_idle = prev.non_idle();
+
+        self.breakdown = Breakdown::between(&prev, &self.stats);
+
+        self.idle = self.stats.idle();
+        self.non_idle = self.stats.non_idle();
+
+        // They come right after the 'cpu' line
+        let mut idx = 0;
+
+        for line in lines {
+            let Some((id, line)) = line.strip_prefix("cpu").and_then(|line| line.split_once(' ')) else {
+                break;
+            };
+
+            let id = id.parse::<u32>().with_context(|| format!("Can't parse 'cpu{}'", id))?;
+            let stats = Cpustats::parse(line)?;
+
+            // CPUs that went offline are gone from /proc/stat
+            while self.cores.get(idx).is_some_and(|core| core.id < id) {
+                self.cores.remove(idx);
+                self.rescan = true;
             }
 
-            match tx.send(3) {
-                Ok(_) => (),
-                Err(_) => break
+            if self.cores.get(idx).is_none_or(|core| core.id != id) {
+                self.cores.insert(idx, Core {
+                    id,
+                    usage: 0.0,
+                    frequency: 0,
+                    min_frequency: 0,
+                    max_frequency: 0,
+                    topology: Topology::default(),
+                    stats: Cpustats::default(),
+                });
+
+                self.rescan = true;
             }
 
-            if let Ok(mut exitvar) = lock.lock() {
-                loop {
-                    if let Ok(result) = cvar.wait_timeout(exitvar, sleepy) {
-                        exitvar = result.0;
-
-                        if *exitvar {
-                            break 'outer;
-                        }
-
-                        if result.1.timed_out() {
-                            break;
-                        }
-                    } else {
-                        break 'outer;
-                    }
-                }
-            } else {
-                break;
+            self.cores[idx].update(stats);
+
+            idx += 1;
+        }
+
+        if self.cores.len() > idx {
+            self.cores.truncate(idx);
+            self.rescan = true;
+        }
+
+        self.cpu_count = self.cores.len() as u32;
+
+        if self.rescan {
+            self.policies = cpufreq::policies(&self.sys_root);
+            topology::read(&self.sys_root, &mut self.cores);
+            self.refreshed = None;
+            self.rescan = false;
+        }
+
+        if self.refreshed.is_none_or(|refreshed| refreshed.elapsed() >= REFRESH_INTERVAL) {
+            for policy in self.policies.iter_mut() {
+                policy.refresh(&mut self.freq_buffer);
             }
+
+            self.refreshed = Some(Instant::now());
         }
-    }).expect("Couldn't spawn Cpu thread")
+
+        self.update_frequencies();
+
+        // Clock ticks of all cores since the last update. Like Core::update() the counters can
+        // go backwards and two updates can be less than a clock tick apart
+        let busy = self.non_idle.saturating_sub(prev_non_idle);
+        self.totald = busy + self.idle.saturating_sub(prev_idle);
+
+        self.cpu_avg = match self.totald {
+            0 => 0.0,
+            _ => busy as f32 / self.totald as f32 * 100.0,
+        };
+
+        Ok(())
+    }
 }
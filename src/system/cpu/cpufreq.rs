@@ -0,0 +1,184 @@
+use std::ffi::CString;
+
+use crate::system::{ read_fd, open_file, root_path };
+use crate::syscall;
+
+// Files that are read by refresh()
+const MIN: usize = 0;
+const MAX: usize = 1;
+const GOVERNOR: usize = 2;
+const EPP: usize = 3;
+
+const FILES: [&str; 4] = [
+    "scaling_min_freq",
+    "scaling_max_freq",
+    "scaling_governor",
+    "energy_performance_preference",
+];
+
+// /sys/devices/system/cpu/cpufreq/policyN, the cores in it always run at the same frequency
+pub struct Policy {
+    // The N in policyN
+    pub id: u32,
+
+    // The cores in it that are online, sorted
+    pub cpus: Vec::<u32>,
+
+    pub governor: String,
+
+    // Energy performance preference, empty if the driver doesn't have one
+    pub epp: String,
+
+    // In kHz like cpufreq. 'min' and 'max' are the limits that are set, which can be
+    // lower than what the hardware can do
+    pub frequency: u64,
+    pub min_frequency: u64,
+    pub max_frequency: u64,
+    pub hardware_min: u64,
+    pub hardware_max: u64,
+
+    // scaling_cur_freq, -1 if it couldn't be opened. It's the only one that's kept open,
+    // the rest are opened by refresh() so a policy only takes one fd
+    fd: i32,
+
+    // In the order of FILES, None if the driver doesn't have it
+    paths: [Option::<CString>; 4],
+}
+
+impl Policy {
+    // Without files, for System::restore()
+    pub fn empty(id: u32) -> Self {
+        Self {
+            id,
+            cpus: Vec::new(),
+            governor: String::new(),
+            epp: String::new(),
+            frequency: 0,
+            min_frequency: 0,
+            max_frequency: 0,
+            hardware_min: 0,
+            hardware_max: 0,
+            fd: -1,
+            paths: Default::default(),
+        }
+    }
+
+    // Reads the current frequency, if it can't be read anymore it's closed and left at 0
+    pub fn update(&mut self, buffer: &mut String) {
+        if self.fd < 0 {
+            return;
+        }
+
+        if read(self.fd, buffer) {
+            self.frequency = buffer.trim().parse::<u64>().unwrap_or_default();
+        } else {
+            syscall::close(self.fd);
+            self.fd = -1;
+            self.frequency = 0;
+        }
+    }
+
+    // Reads the limits, governor and EPP. They only change when they're set so this is done
+    // now and then instead of every update. A file that can't be read keeps its last value
+    pub fn refresh(&mut self, buffer: &mut String) {
+        for (idx, path) in self.paths.iter().enumerate() {
+            let Some(path) = path else {
+                continue;
+            };
+
+            let Ok(fd) = open_file(path.as_ptr()) else {
+                continue;
+            };
+
+            let ok = read(fd, buffer);
+            syscall::close(fd);
+
+            if !ok {
+                continue;
+            }
+
+            let value = buffer.trim();
+
+            match idx {
+                MIN => self.min_frequency = value.parse::<u64>().unwrap_or_default(),
+                MAX => self.max_frequency = value.parse::<u64>().unwrap_or_default(),
+                GOVERNOR => {
+                    self.governor.clear();
+                    self.governor.push_str(value);
+                },
+                EPP => {
+                    self.epp.clear();
+                    self.epp.push_str(value);
+                },
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+impl Drop for Policy {
+    fn drop(&mut self) {
+        if self.fd >= 0 {
+            syscall::close(self.fd);
+        }
+    }
+}
+
+// Reads 'fd' into 'buffer', false if it couldn't be read or isn't text
+fn read(fd: i32, buffer: &mut String) -> bool {
+    unsafe { read_fd(fd, buffer.as_mut_vec()).is_ok() && std::str::from_utf8(buffer.as_bytes()).is_ok() }
+}
+
+// Every policy with a core that's online, sorted by id. Empty if there's no cpufreq, which is common in VMs
+pub fn policies(sys_root: &str) -> Vec::<Policy> {
+    let dir = format!("{}/devices/system/cpu/cpufreq", sys_root.trim_end_matches('/'));
+
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+
+    let mut policies = Vec::<Policy>::new();
+
+    for entry in entries.flatten() {
+        let Some(id) = entry.file_name().to_str()
+            .and_then(|name| name.strip_prefix("policy"))
+            .and_then(|id| id.parse::<u32>().ok()) else {
+            continue;
+        };
+
+        let read = |file: &str| std::fs::read_to_string(format!("{}/policy{}/{}", dir, id, file)).unwrap_or_default();
+
+        let mut cpus: Vec::<u32> = read("affected_cpus")
+            .split_ascii_whitespace()
+            .filter_map(|cpu| cpu.parse::<u32>().ok())
+            .collect();
+
+        // All of its cores are offline
+        if cpus.is_empty() {
+            continue;
+        }
+
+        cpus.sort_unstable();
+
+        let mut policy = Policy::empty(id);
+        policy.cpus = cpus;
+        policy.hardware_min = read("cpuinfo_min_freq").trim().parse::<u64>().unwrap_or_default();
+        policy.hardware_max = read("cpuinfo_max_freq").trim().parse::<u64>().unwrap_or_default();
+
+        let path = |file: &str| root_path(sys_root, &format!("/devices/system/cpu/cpufreq/policy{}/{}", id, file));
+
+        policy.fd = open_file(path("scaling_cur_freq").as_ptr()).unwrap_or(-1);
+
+        // energy_performance_preference only exists with some drivers
+        for (slot, file) in policy.paths.iter_mut().zip(FILES) {
+            let path = path(file);
+            *slot = std::fs::metadata(path.to_str().unwrap_or_default()).is_ok().then_some(path);
+        }
+
+        policies.push(policy);
+    }
+
+    policies.sort_unstable_by_key(|policy| policy.id);
+
+    policies
+}
@@ -0,0 +1,152 @@
+use super::Core;
+
+// Performance or efficiency core on hybrid CPUs like Alder Lake or big.LITTLE
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    // All the cores are the same
+    #[default]
+    Unknown,
+    Performance,
+    Efficiency,
+}
+
+impl Kind {
+    // What the snapshot uses
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Unknown => "",
+            Kind::Performance => "performance",
+            Kind::Efficiency => "efficiency",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "performance" => Kind::Performance,
+            "efficiency" => Kind::Efficiency,
+            _ => Kind::Unknown,
+        }
+    }
+}
+
+// Where a core is. Everything is 0 if the files aren't there
+#[derive(Debug, Default, Clone)]
+pub struct Topology {
+    // Socket
+    pub package: u32,
+    pub die: u32,
+
+    // Only unique within a package. SMT siblings have the same one
+    pub core: u32,
+
+    // NUMA node
+    pub node: u32,
+
+    // Cores that share this physical core, itself included
+    pub siblings: Vec::<u32>,
+
+    pub kind: Kind,
+}
+
+// Reads it for every core in 'cores'
+pub fn read(sys_root: &str, cores: &mut [Core]) {
+    let root = sys_root.trim_end_matches('/');
+    let read = |path: String| std::fs::read_to_string(path).unwrap_or_default();
+    let number = |path: String| read(path).trim().parse::<u32>().unwrap_or_default();
+
+    for core in cores.iter_mut() {
+        let dir = format!("{}/devices/system/cpu/cpu{}/topology", root, core.id);
+
+        core.topology = Topology {
+            package: number(format!("{}/physical_package_id", dir)),
+            die: number(format!("{}/die_id", dir)),
+            core: number(format!("{}/core_id", dir)),
+            node: 0,
+            siblings: parse_list(&read(format!("{}/thread_siblings_list", dir))),
+            kind: Kind::Unknown,
+        };
+    }
+
+    // node0, node1, ... with the cores in each one
+    if let Ok(entries) = std::fs::read_dir(format!("{}/devices/system/node", root)) {
+        for entry in entries.flatten() {
+            let Some(node) = entry.file_name().to_str()
+                .and_then(|name| name.strip_prefix("node"))
+                .and_then(|node| node.parse::<u32>().ok()) else {
+                continue;
+            };
+
+            for id in parse_list(&read(format!("{}/devices/system/node/node{}/cpulist", root, node))) {
+                if let Ok(idx) = cores.binary_search_by_key(&id, |core| core.id) {
+                    cores[idx].topology.node = node;
+                }
+            }
+        }
+    }
+
+    // Intel hybrid CPUs have a PMU for each kind of core
+    let performance = parse_list(&read(format!("{}/devices/cpu_core/cpus", root)));
+    let efficiency = parse_list(&read(format!("{}/devices/cpu_atom/cpus", root)));
+
+    if !performance.is_empty() && !efficiency.is_empty() {
+        for core in cores.iter_mut() {
+            if performance.contains(&core.id) {
+                core.topology.kind = Kind::Performance;
+            } else if efficiency.contains(&core.id) {
+                core.topology.kind = Kind::Efficiency;
+            }
+        }
+
+        return;
+    }
+
+    // On ARM the big cores have the highest capacity
+    let capacities: Vec::<u32> = cores.iter()
+        .map(|core| number(format!("{}/devices/system/cpu/cpu{}/cpu_capacity", root, core.id)))
+        .collect();
+
+    let biggest = capacities.iter().copied().max().unwrap_or_default();
+
+    if capacities.iter().any(|&capacity| capacity != biggest) {
+        for (core, capacity) in cores.iter_mut().zip(capacities) {
+            core.topology.kind = match capacity == biggest {
+                true => Kind::Performance,
+                false => Kind::Efficiency,
+            };
+        }
+    }
+}
+
+// Like '0-3,8,10-11'
+fn parse_list(list: &str) -> Vec::<u32> {
+    let mut ids = Vec::<u32>::new();
+
+    for part in list.trim().split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                if let (Ok(first), Ok(last)) = (first.parse::<u32>(), last.parse::<u32>()) {
+                    ids.extend(first..=last);
+                }
+            },
+            None => {
+                if let Ok(id) = part.parse::<u32>() {
+                    ids.push(id);
+                }
+            },
+        }
+    }
+
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists() {
+        assert_eq!(parse_list("0-3,8,10-11\n"), [0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_list("5"), [5]);
+        assert!(parse_list("").is_empty());
+    }
+}
@@ -1,13 +1,27 @@
-use std::arch::asm;
+use std::sync::Arc;
+use std::ffi::c_void;
+use anyhow::anyhow;
 
-use std::sync::{ Arc, mpsc, atomic, Mutex };
-use super::Config;
+use super::bus::{ Bus, Event, Key, Source };
+use crate::syscall;
 
 mod epoll;
 
-// pub static mut InputBuffer: String = String::new();
+// ioctl command to get the terminal size
+const TIOCGWINSZ: u32 = 0x5413;
+
+// Used with the TIOCGWINSZ ioctl command to hold the terminal size info
+#[repr(C)]
+#[derive(Default)]
+struct Winsize {
+    ws_row: u16,
+    ws_col: u16,
+    ws_xpixel: u16,   /* unused */
+    ws_ypixel: u16,   /* unused */
+}
 
-pub fn start_thread(inputbuffer: Arc<Mutex<String>>, config: Arc<Config>, tx: mpsc::Sender::<u8>) -> std::thread::JoinHandle<()> {
+// Keys are only read from stdin if 'keys' is true. Without a terminal it might not be something epoll can use
+pub fn start_thread(bus: Bus, keys: bool) -> std::thread::JoinHandle<()> {
     // Set up the signals for the Event thread
     // This needs to be done in the MAIN thread BEFORE any child threads are spawned
     // so the rules are inherited to all child threads
@@ -17,13 +31,13 @@ pub fn start_thread(inputbuffer: Arc<Mutex<String>>, config: Arc<Config>, tx: mp
         // Buffer is 10 to make sure stuff fits
         let mut buf = Vec::<u8>::with_capacity(10);
 
-        let mut search = false;
-
         // Initialize epoll
         let mut epoll = epoll::Epoll::new();
 
         // Add stdin
-        epoll.add(0);
+        if keys {
+            epoll.add(0);
+        }
 
         // Add singalfd
         epoll.add(signalfd.fd);
@@ -42,18 +56,7 @@ pub fn start_thread(inputbuffer: Arc<Mutex<String>>, config: Arc<Config>, tx: mp
                 buf.clear();
 
                 // Read what's in stdin
-                let ret: i32;
-                unsafe {
-                    asm!("syscall",
-                        in("rax") 0, // SYS_READ
-                        in("rdi") 0, // STDIN
-                        in("rsi") buf.as_mut_ptr(),
-                        in("rdx") buf.capacity(),
-                        out("rcx") _,
-                        out("r11") _,
-                        lateout("rax") ret,
-                    );
-                }
+                let ret = unsafe { syscall::read(0, buf.as_mut_ptr(), buf.capacity()) };
 
                 assert!(!ret.is_negative());
 
@@ -62,118 +65,9 @@ pub fn start_thread(inputbuffer: Arc<Mutex<String>>, config: Arc<Config>, tx: mp
                     buf.set_len(ret as usize);
                 }
 
-
-                if search {
-                    let mut in_buf_lock = inputbuffer.lock().unwrap();
-                    // Disable Search
-                    if buf[0] == b'\r' || buf[0] == 27 {
-                        search = false;
-
-                        in_buf_lock.clear();
-
-                        // Notify UI that string has been updated
-                        match tx.send(106) {
-                            Ok(_) => (),
-                            Err(_) => break,
-                        }
-                    } else {
-                        // Delete last char if you press backspace
-                        if buf[0] == 127 {
-                            let _ = in_buf_lock.pop();
-                        } else {
-                            in_buf_lock.push(buf[0] as char);
-                        }
-
-                        // Notify UI that string has been updated
-                        match tx.send(106) {
-                            Ok(_) => (),
-                            Err(_) => break,
-                        }
-                    }
-                } else {
-                    // Do stuff depending on what button was pressed
-                    // I only care about the first byte
-                    match buf[0] {
-                        // Enable Search
-                        b'f' => {
-                            search = true;
-
-//                             match tx.send(106) {
-//                                 Ok(_) => (),
-//                                 Err(_) => break,
-//                             }
-                        }
-
-                        // Quit
-                        b'q' => {
-                            match tx.send(255) {
-                                Ok(_) => (),
-                                Err(_) => break,
-                            }
-
-                            break;
-                        },
-
-                        // Pause UI
-                        b' ' => {
-                            match tx.send(101) {
-                                Ok(_) => (),
-                                Err(_) => break,
-                            }
-                        },
-
-                        // Toggle Topmode
-                        b't' => {
-                            if config.topmode.load(atomic::Ordering::Acquire) {
-                                config.topmode.store(false, atomic::Ordering::Release);
-                            } else {
-                                config.topmode.store(true, atomic::Ordering::Release);
-                            }
-
-                            match tx.send(10) {
-                                Ok(_) => (),
-                                Err(_) => break,
-                            }
-                        },
-
-                        // Toggle smaps
-                        b's' => {
-                            if config.smaps.load(atomic::Ordering::Acquire) {
-                                config.smaps.store(false, atomic::Ordering::Release);
-                            } else {
-                                config.smaps.store(true, atomic::Ordering::Release);
-                            }
-
-                            match tx.send(11) {
-                                Ok(_) => (),
-                                Err(_) => break,
-                            }
-                        },
-
-                        // Toggle All Processes
-                        b'a' => {
-                            if config.all.load(atomic::Ordering::Acquire) {
-                                config.all.store(false, atomic::Ordering::Release);
-                            } else {
-                                config.all.store(true, atomic::Ordering::Release);
-                            }
-
-                            match tx.send(12) {
-                                Ok(_) => (),
-                                Err(_) => break,
-                            }
-                        },
-
-                        // Rebuild UI cache
-                        b'r' => {
-                            match tx.send(106) {
-                                Ok(_) => (),
-                                Err(_) => break,
-                            }
-                        },
-
-                        _ => (),
-                    }
+                // What the keys do is up to the subscribers
+                if let Some(key) = Key::parse(&buf) {
+                    bus.send(Event::Key(key));
                 }
             } else if fd == signalfd.fd {
                 // Get what signal was recieved
@@ -183,20 +77,13 @@ pub fn start_thread(inputbuffer: Arc<Mutex<String>>, config: Arc<Config>, tx: mp
                     // SIGWINCH aka terminal resize signal
                     28 => {
                         // Notify main thread about resize
-                        match tx.send(105) {
-                            Ok(_) => (),
-                            Err(_) => break,
+                        if let Some((width, height)) = terminal_size() {
+                            bus.send(Event::Resize { width, height });
                         }
                     },
 
-                    // SIGINT
-                    2 => {
-                        // Notify main thread about SIGINT
-                        match tx.send(255) {
-                            Ok(_) => (),
-                            Err(_) => break,
-                        }
-                    },
+                    // SIGINT and SIGTERM
+                    2 | 15 => bus.send(Event::Quit),
 
                     // SIGUSR1 - Used to exit the thread in case of errors
                     10 => break,
@@ -204,15 +91,19 @@ pub fn start_thread(inputbuffer: Arc<Mutex<String>>, config: Arc<Config>, tx: mp
                 }
             } else {
                 // Something has gone horrible wrong!
-
-                // Error event
-                match tx.send(99) {
-                    Ok(_) => (),
-                    Err(_) => break,
-                }
+                bus.send(Event::Error(Source::Input, Arc::new(anyhow!("epoll returned an unknown fd: {}", fd))));
 
                 break;
             }
         }
     }).expect("Couldn't spawn Events thread")
 }
+
+// Size of the terminal on stdin in columns and rows
+fn terminal_size() -> Option::<(u16, u16)> {
+    let mut winsize = Winsize::default();
+
+    let ret = unsafe { syscall::ioctl(0, TIOCGWINSZ, &mut winsize as *mut Winsize as *mut c_void) };
+
+    (!ret.is_negative()).then_some((winsize.ws_col, winsize.ws_row))
+}
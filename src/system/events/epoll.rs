@@ -1,4 +1,6 @@
-use std::arch::asm;
+use std::ffi::c_void;
+
+use crate::syscall;
 
 #[repr(C)]
 #[derive(Default)]
@@ -43,37 +45,17 @@ impl SignalFD {
         set |= 1u64 << 27; // SIGWINCH == 28 - 1 = 27
         set |= 1u64 << 1; // SIGINT == 2 - 1 = 1
         set |= 1u64 << 9; // SIGUSR1 == 10 - 1 = 9
+        set |= 1u64 << 14; // SIGTERM == 15 - 1 = 14
 
         // Block the signals we want to handle ourselves
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 14, // SYS_RT_SIGPROCMASK
-                in("rdi") 2, // SIG_SETMASK
-                in("rsi") &set as *const u64, // sigset_t __user * nset
-                in("rdx") 0, // sigset_t __user * oset
-                in("r10") 8, // size_t sigsetsize aka how many bytes is the set
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
+        // 2 == SIG_SETMASK
+        let ret = unsafe { syscall::rt_sigprocmask(2, &set as *const u64, std::ptr::null_mut()) };
 
         assert!(ret == 0);
 
         // Make a signalfd file
-        let fd: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 282, // SYS_SIGNALFD4
-                in("rdi") -1, // -1 == create a new signalfd
-                in("rsi") &set as *const u64, //&sigset as *const libc::sigset_t, // user_mask
-                in("rdx") 8, // sizemask aka how many bytes is the set
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") fd,
-            );
-        }
+        // -1 == create a new signalfd
+        let fd = unsafe { syscall::signalfd(-1, &set as *const u64, 0) };
 
         assert!(!fd.is_negative());
 
@@ -87,18 +69,7 @@ impl SignalFD {
         let mut data = SignalfdSiginfo::default();
 
         // Read signal info from signalfd
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 0, // SYS_READ
-                in("rdi") self.fd,
-                in("rsi") &mut data as *mut SignalfdSiginfo,
-                in("rdx") 128,
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
+        let ret = unsafe { syscall::read(self.fd, &mut data as *mut SignalfdSiginfo as *mut u8, 128) };
 
         assert!(!ret.is_negative());
 
@@ -108,16 +79,7 @@ impl SignalFD {
 
 impl Drop for SignalFD {
     fn drop(&mut self) {
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 3, // SYS_CLOSE
-                in("rdi") self.fd,
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
+        let ret = syscall::close(self.fd);
 
         assert!(!ret.is_negative());
     }
@@ -131,7 +93,9 @@ pub union epoll_data_t {
     uint64_t: u64,
 }
 
+// struct epoll_event is packed on x86_64 only
 #[repr(C)]
+#[cfg_attr(target_arch = "x86_64", repr(packed))]
 pub struct EpollEvent {
     pub events: u32,            /* Epoll events */
     pub data: epoll_data_t      /* User data variable */
@@ -143,17 +107,8 @@ pub struct Epoll {
 
 impl Epoll {
     pub fn new() -> Self {
-        // Create epoll fd
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 291, // SYS_EPOLL_CREATE1
-                in("rdi") 0, // Flags
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
+        // Create epoll fd, no flags
+        let ret = syscall::epoll_create1(0);
 
         assert!(!ret.is_negative());
 
@@ -171,19 +126,8 @@ impl Epoll {
         };
 
         // Add fd to the epoll interest list
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 233, // SYS_EPOLL_CTL
-                in("rdi") self.fd, // int epfd
-                in("rsi") 1, // EPOLL_CTL_ADD == 1
-                in("rdx") fd, // FD to monitor
-                in("r10") &event as *const EpollEvent, // struct epoll_event __user * event
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
+        // EPOLL_CTL_ADD == 1
+        let ret = unsafe { syscall::epoll_ctl(self.fd, 1, fd, &event as *const EpollEvent as *const c_void) };
 
         assert!(!ret.is_negative());
     }
@@ -197,18 +141,11 @@ impl Epoll {
         };
 
         // Wait for a epoll event
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 232, // SYS_EPOLL_WAIT
-                in("rdi") self.fd, // epoll fd
-                in("rsi") &mut event as *mut EpollEvent, // epoll event
-                in("rdx") 1, // maxevents
-                in("r10") -1, // timeout, -1 == forever
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret, // Numbers of events
-            );
+        // maxevents == 1, timeout -1 == forever. Returns the number of events
+        // It's interrupted when the process is stopped and continued, like after SIGCONT. -4 == EINTR
+        let mut ret = -4;
+        while ret == -4 {
+            ret = unsafe { syscall::epoll_wait(self.fd, &mut event as *mut EpollEvent as *mut c_void, 1, -1) };
         }
 
         assert!(!ret.is_negative());
@@ -220,16 +157,7 @@ impl Epoll {
 impl Drop for Epoll {
     fn drop(&mut self) {
         // Close epoll fd
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 3, // SYS_CLOSE
-                in("rdi") self.fd,
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
+        let ret = syscall::close(self.fd);
 
         assert!(!ret.is_negative());
     }
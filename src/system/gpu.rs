@@ -1,4 +1,7 @@
-use std::sync::{Arc, Mutex, mpsc};
+use anyhow::Result;
+
+use super::{ collector::Collector, bus::Source };
+use crate::snapshot::GpuSnapshot;
 
 #[derive(Default)]
 pub struct Gpu {
@@ -7,57 +10,71 @@ pub struct Gpu {
     pub mem_load: u32,
     pub mem_used: f32,
 
+    // Set when the first update is done, stays false if there's no GPU
+    pub available: bool,
+
+    // None if NVML can't be loaded or there's no GPU
+    nvml: Option::<nvml_wrapper::Nvml>,
+}
+
+impl Gpu {
+    // Setup device
+    // NVML can start threads of its own so this has to be done after the Event thread is started
+    pub fn init(&mut self) {
+        self.nvml = nvml_wrapper::Nvml::init().ok()
+            .filter(|nvml| nvml.device_by_index(0).is_ok());
+    }
+
+    pub fn snapshot(&self) -> Option::<GpuSnapshot> {
+        self.available.then_some(GpuSnapshot {
+            temperature: self.temp,
+            gpu_load: self.gpu_load,
+            mem_load: self.mem_load,
+            mem_used: self.mem_used,
+        })
+    }
+
+    // Show 'snapshot' instead of what was read, see System::restore()
+    pub fn restore(&mut self, snapshot: Option::<&GpuSnapshot>) {
+        let gpu = snapshot.cloned().unwrap_or_default();
+
+        self.temp = gpu.temperature;
+        self.gpu_load = gpu.gpu_load;
+        self.mem_load = gpu.mem_load;
+        self.mem_used = gpu.mem_used;
+        self.available = snapshot.is_some();
+    }
 }
 
-pub fn start_thread(internal: Arc<Mutex<Gpu>>, tx: mpsc::Sender::<u8>, exit: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>, sleepy: std::time::Duration) -> std::thread::JoinHandle<()> {
-    std::thread::Builder::new().name("Gpu".to_string()).spawn(move || {
-        // Setup device
-        if let Ok(nvml) = nvml_wrapper::Nvml::init() {
-            if let Ok(device) = nvml.device_by_index(0) {
-                let (lock, cvar) = &*exit;
-                'outer: loop {
-                    match internal.lock() {
-                        Ok(mut val) => {
-                            if let Ok(temp) = device.temperature(nvml_wrapper::enum_wrappers::device::TemperatureSensor::Gpu) {
-                                val.temp = temp;
-                            }
-                            if let Ok(util) = device.utilization_rates() {
-                                val.gpu_load = util.gpu;
-                                val.mem_load = util.memory;
-                            }
-                            if let Ok(mem) = device.memory_info() {
-                                val.mem_used = (mem.used as f32 / mem.total as f32) * 100.0;
-                            }
-                        },
-                        Err(_) => break,
-                    }
-
-                    match tx.send(9) {
-                        Ok(_) => (),
-                        Err(_) => break,
-                    }
-
-                    if let Ok(mut exitvar) = lock.lock() {
-                        loop {
-                            if let Ok(result) = cvar.wait_timeout(exitvar, sleepy) {
-                                exitvar = result.0;
-
-                                if *exitvar {
-                                    break 'outer;
-                                }
-
-                                if result.1.timed_out() {
-                                    break;
-                                }
-                            } else {
-                                break 'outer;
-                            }
-                        }
-                    } else {
-                        break;
-                    }
-                }
-            }
+impl Collector for Gpu {
+    const NAME: &'static str = "Gpu";
+    const SOURCE: Source = Source::Gpu;
+
+    fn update(&mut self) -> Result<()> {
+        let device = if let Some(device) = self.nvml.as_ref().and_then(|nvml| nvml.device_by_index(0).ok()) {
+            device
+        } else {
+            return Ok(());
+        };
+
+        if let Ok(temp) = device.temperature(nvml_wrapper::enum_wrappers::device::TemperatureSensor::Gpu) {
+            self.temp = temp;
+        }
+        if let Ok(util) = device.utilization_rates() {
+            self.gpu_load = util.gpu;
+            self.mem_load = util.memory;
+        }
+        if let Ok(mem) = device.memory_info() {
+            self.mem_used = (mem.used as f32 / mem.total as f32) * 100.0;
         }
-    }).expect("Couldn't spawn Processes thread")
+
+        self.available = true;
+
+        Ok(())
+    }
+
+    // No thread without a GPU
+    fn enabled(&self) -> bool {
+        self.nvml.is_some()
+    }
 }
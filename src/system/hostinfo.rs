@@ -1,11 +1,14 @@
+use super::Config;
+
 pub struct Hostinfo {
+    pub hostname: String,
     pub distname: String,
     pub kernel: String,
     pub ansi_color: String,
 }
 
-impl Default for Hostinfo {
-    fn default() -> Self {
+impl Hostinfo {
+    pub fn new(config: &Config) -> Self {
         let mut distname = String::from("Not Found");
         let mut ansi_color = String::from("\x1b[97m");
 
@@ -29,12 +32,18 @@ impl Default for Hostinfo {
             }
         }
 
-        let kernel = std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        let kernel = std::fs::read_to_string(format!("{}/sys/kernel/osrelease", config.proc_root.trim_end_matches('/')))
             .unwrap_or_else(|_| "Not Found".to_string())
             .trim()
             .to_string();
 
+        let hostname = std::fs::read_to_string(format!("{}/sys/kernel/hostname", config.proc_root.trim_end_matches('/')))
+            .unwrap_or_else(|_| "localhost".to_string())
+            .trim()
+            .to_string();
+
         Hostinfo {
+            hostname,
             distname,
             kernel,
             ansi_color,
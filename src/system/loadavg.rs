@@ -1,9 +1,10 @@
-use std::arch::asm;
-
 use anyhow::{ Context, Result };
-use std::sync::{ Arc, Mutex, mpsc };
+use std::ffi::CString;
+use std::fmt::Write;
 
-use super::{ read_fd, open_file };
+use super::{ read_fd, open_file, root_path, Config, collector::Collector, bus::Source };
+use crate::syscall;
+use crate::snapshot::LoadavgSnapshot;
 
 pub struct Loadavg {
     pub min1: String,
@@ -11,37 +12,41 @@ pub struct Loadavg {
     pub min15: String,
     buffer: String,
     fd: i32,
+    path: CString,
 }
 
 impl Loadavg {
-    pub fn new() -> Result<Self> {
-        let fd = open_file(c"/proc/loadavg".as_ptr()).context("Can't open /proc/loadavg")?;
-
-        Ok(Self {
+    // The file is opened by update() so a failure can be retried
+    pub fn new(config: &Config) -> Self {
+        Self {
             min1: String::new(),
             min5: String::new(),
             min15: String::new(),
             buffer: String::with_capacity(100),
-            fd
-        })
+            fd: -1,
+            path: root_path(&config.proc_root, "/loadavg"),
+        }
     }
 
-    pub fn update(&mut self) -> Result<()> {
-        unsafe {
-            read_fd(self.fd, self.buffer.as_mut_vec()).context("Can't read /proc/loadavg")?;
+    pub fn snapshot(&self) -> LoadavgSnapshot {
+        // They are 0.0 until the first update
+        LoadavgSnapshot {
+            min1: self.min1.parse().unwrap_or_default(),
+            min5: self.min5.parse().unwrap_or_default(),
+            min15: self.min15.parse().unwrap_or_default(),
         }
+    }
 
+    // Show 'snapshot' instead of what was read, see System::restore()
+    // /proc/loadavg has two decimals
+    pub fn restore(&mut self, snapshot: &LoadavgSnapshot) {
         self.min1.clear();
         self.min5.clear();
         self.min15.clear();
 
-        let mut split = self.buffer.split_ascii_whitespace();
-
-        self.min1.push_str(split.next().context("Can't parse /proc/loadavg: 1")?);
-        self.min5.push_str(split.next().context("Can't parse /proc/loadavg: 2")?);
-        self.min15.push_str(split.next().context("Can't parse /proc/loadavg: 3")?);
-
-        Ok(())
+        let _ = write!(self.min1, "{:.2}", snapshot.min1);
+        let _ = write!(self.min5, "{:.2}", snapshot.min5);
+        let _ = write!(self.min15, "{:.2}", snapshot.min15);
     }
 }
 
@@ -49,61 +54,34 @@ impl Drop for Loadavg {
     fn drop(&mut self) {
         // Close file
         if self.fd > 0 {
-            unsafe {
-                asm!("syscall",
-                    in("rax") 3, // SYS_CLOSE
-                    in("rdi") self.fd,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") _,
-                );
-            }
+            syscall::close(self.fd);
         }
     }
 }
 
-pub fn start_thread(internal: Arc<Mutex<Loadavg>>, tx: mpsc::Sender::<u8>, exit: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>, error: Arc<Mutex<Vec::<anyhow::Error>>>, sleepy: std::time::Duration) -> std::thread::JoinHandle<()> {
-    std::thread::Builder::new().name("Load Average".to_string()).spawn(move || {
-        let (lock, cvar) = &*exit;
-        'outer: loop {
-            match internal.lock() {
-                Ok(mut val) => {
-                    if let Err(err) = val.update() {
-                        let mut errvec = error.lock().expect("Error lock couldn't be aquired!");
-                        errvec.push(err);
+impl Collector for Loadavg {
+    const NAME: &'static str = "Load Average";
+    const SOURCE: Source = Source::Loadavg;
 
-                        let _ = tx.send(99);
-                        break;
-                    }
-                },
-                Err(_) => break,
-            }
+    fn update(&mut self) -> Result<()> {
+        if self.fd < 0 {
+            self.fd = open_file(self.path.as_ptr()).with_context(|| format!("Can't open {}", self.path.to_string_lossy()))?;
+        }
 
-            match tx.send(2) {
-                Ok(_) => (),
-                Err(_) => break,
-            }
+        unsafe {
+            read_fd(self.fd, self.buffer.as_mut_vec()).with_context(|| format!("Can't read {}", self.path.to_string_lossy()))?;
+        }
 
-            if let Ok(mut exitvar) = lock.lock() {
-                loop {
-                    if let Ok(result) = cvar.wait_timeout(exitvar, sleepy) {
-                        exitvar = result.0;
+        self.min1.clear();
+        self.min5.clear();
+        self.min15.clear();
 
-                        if *exitvar {
-                            break 'outer;
-                        }
+        let mut split = self.buffer.split_ascii_whitespace();
 
-                        if result.1.timed_out() {
-                            break;
-                        }
-                    } else {
-                        break 'outer;
-                    }
-                }
-            } else {
-                break;
-            }
-        }
-    }).expect("Couldn't spawn Load Average thread")
+        self.min1.push_str(split.next().context("Can't parse /proc/loadavg: 1")?);
+        self.min5.push_str(split.next().context("Can't parse /proc/loadavg: 2")?);
+        self.min15.push_str(split.next().context("Can't parse /proc/loadavg: 3")?);
 
+        Ok(())
+    }
 }
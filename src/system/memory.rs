@@ -1,9 +1,10 @@
-use std::arch::asm;
-
 use anyhow::{ Context, Result };
-use std::sync::{ Arc, Mutex, mpsc, atomic, atomic::Ordering };
+use std::sync::{ atomic, atomic::Ordering };
+use std::ffi::CString;
 
-use super::{ read_fd, open_file };
+use super::{ read_fd, open_file, root_path, Config, collector::Collector, bus::Source };
+use crate::syscall;
+use crate::snapshot::MemorySnapshot;
 
 pub struct Memory {
     // pub mem_total: u64,
@@ -14,14 +15,14 @@ pub struct Memory {
     pub swap_free: u64,
     pub swap_used: u64,
     buffer: String,
-    fd: i32
+    fd: i32,
+    path: CString,
 }
 
 impl Memory {
-    pub fn new() -> Result<Self> {
-        let fd = open_file(c"/proc/meminfo".as_ptr()).context("Can't open /proc/meminfo")?;
-
-        Ok(Self {
+    // The file is opened by update() so a failure can be retried
+    pub fn new(config: &Config) -> Self {
+        Self {
             mem_total: atomic::AtomicU64::new(0),
             mem_free: 0,
             mem_used: 0,
@@ -29,18 +30,59 @@ impl Memory {
             swap_free: 0,
             swap_used: 0,
             buffer: String::with_capacity(2000),
-            fd
-        })
+            fd: -1,
+            path: root_path(&config.proc_root, "/meminfo"),
+        }
+    }
+
+    pub fn snapshot(&self) -> MemorySnapshot {
+        MemorySnapshot {
+            total: self.mem_total.load(Ordering::Relaxed),
+            free: self.mem_free,
+            used: self.mem_used,
+            swap_total: self.swap_total,
+            swap_free: self.swap_free,
+            swap_used: self.swap_used,
+        }
+    }
+
+    // Show 'snapshot' instead of what was read, see System::restore()
+    pub fn restore(&mut self, snapshot: &MemorySnapshot) {
+        self.mem_total.store(snapshot.total, Ordering::Relaxed);
+        self.mem_free = snapshot.free;
+        self.mem_used = snapshot.used;
+        self.swap_total = snapshot.swap_total;
+        self.swap_free = snapshot.swap_free;
+        self.swap_used = snapshot.swap_used;
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        // Close any open FDs when it's dropped
+        if self.fd > 0 {
+            syscall::close(self.fd);
+        }
     }
-    pub fn update(&mut self) -> Result<()> {
+}
+
+impl Collector for Memory {
+    const NAME: &'static str = "Memory";
+    const SOURCE: Source = Source::Memory;
+
+    fn update(&mut self) -> Result<()> {
         /*self.buffer.clear();
         std::fs::File::open("/proc/meminfo")
             .context("Can't open /proc/meminfo")?
             .read_to_string(&mut self.buffer)
-            .context("Can't read /proc/meminfo")?;*/
+            .with_context(|| format!("Can't read {}", self.path.to_string_lossy()))?;*/
+
+        if self.fd < 0 {
+            self.fd = open_file(self.path.as_ptr()).with_context(|| format!("Can't open {}", self.path.to_string_lossy()))?;
+        }
 
         unsafe {
-            read_fd(self.fd, self.buffer.as_mut_vec()).context("Can't read /proc/meminfo")?;
+            read_fd(self.fd, self.buffer.as_mut_vec()).with_context(|| format!("Can't read {}", self.path.to_string_lossy()))?;
         }
 
         let mut lines = self.buffer.lines();
@@ -88,65 +130,3 @@ impl Memory {
         Ok(())
     }
 }
-
-impl Drop for Memory {
-    fn drop(&mut self) {
-        // Close any open FDs when it's dropped
-        if self.fd > 0 {
-            unsafe {
-                asm!("syscall",
-                    in("rax") 3, // SYS_CLOSE
-                    in("rdi") self.fd,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") _,
-                );
-            }
-        }
-    }
-}
-
-pub fn start_thread(internal: Arc<Mutex<Memory>>, tx: mpsc::Sender::<u8>, exit: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>, error: Arc<Mutex<Vec::<anyhow::Error>>>, sleepy: std::time::Duration) -> std::thread::JoinHandle<()> {
-    std::thread::Builder::new().name("Memory".to_string()).spawn(move || {
-        let (lock, cvar) = &*exit;
-        'outer: loop {
-            match internal.lock() {
-                Ok(mut val) => {
-                    if let Err(err) = val.update() {
-                        let mut errvec = error.lock().expect("Error lock couldn't be aquired!");
-                        errvec.push(err);
-
-                        let _ = tx.send(99);
-                        break;
-                    }
-                },
-                Err(_) => break,
-            }
-
-            match tx.send(4) {
-                Ok(_) => (),
-                Err(_) => break,
-            }
-
-            if let Ok(mut exitvar) = lock.lock() {
-                loop {
-                    if let Ok(result) = cvar.wait_timeout(exitvar, sleepy) {
-                        exitvar = result.0;
-
-                        if *exitvar {
-                            break 'outer;
-                        }
-
-                        if result.1.timed_out() {
-                            break;
-                        }
-                    } else {
-                        break 'outer;
-                    }
-                }
-            } else {
-                break;
-            }
-        }
-    }).expect("Couldn't spawn Memory thread")
-}
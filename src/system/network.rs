@@ -1,9 +1,10 @@
-use std::arch::asm;
-
 use anyhow::{ Context, Result };
-use std::sync::{ Arc, Mutex, mpsc };
+use std::ffi::CString;
+use std::time::{ Duration, Instant };
 
-use super::{ read_fd, open_file };
+use super::{ read_fd, open_file, root_path, Config, Filter, collector::Collector, bus::Source };
+use crate::syscall;
+use crate::snapshot::{ NetworkSnapshot, InterfaceSnapshot };
 
 #[derive(Default)]
 pub struct Bandwidth {
@@ -15,26 +16,88 @@ pub struct Bandwidth {
 
 pub struct Network {
     pub stats: std::collections::BTreeMap<String, Bandwidth>,
+
+    // Time between the last two updates. recv and sent are over this long
+    pub elapsed: Duration,
+    last_update: Option::<Instant>,
+
     buffer: String,
-    fd: i32
+    fd: i32,
+    path: CString,
+    filter: Filter,
 }
 
 impl Network {
-    pub fn new() -> Result<Self> {
-        let fd = open_file(c"/proc/net/dev".as_ptr()).context("Can't open /proc/net/dev")?;
-
-        Ok(Self {
+    // The file is opened by update() so a failure can be retried
+    pub fn new(config: &Config) -> Self {
+        Self {
             stats: std::collections::BTreeMap::new(),
+            elapsed: Duration::ZERO,
+            last_update: None,
             buffer: String::with_capacity(1500),
-            fd
-        })
+            fd: -1,
+            path: root_path(&config.proc_root, "/net/dev"),
+            filter: config.interfaces.clone(),
+        }
+    }
+
+    pub fn snapshot(&self) -> NetworkSnapshot {
+        NetworkSnapshot {
+            interfaces: self.stats.iter()
+                .map(|(name, bw)| InterfaceSnapshot {
+                    name: name.clone(),
+                    recv: bw.recv,
+                    sent: bw.sent,
+                    total_recv: bw.total_recv,
+                    total_sent: bw.total_sent,
+                })
+                .collect(),
+            elapsed: self.elapsed,
+        }
+    }
+
+    // Show 'snapshot' instead of what was read, see System::restore()
+    pub fn restore(&mut self, snapshot: &NetworkSnapshot) {
+        self.stats = snapshot.interfaces.iter()
+            .map(|interface| (interface.name.clone(), Bandwidth {
+                recv: interface.recv,
+                sent: interface.sent,
+                total_recv: interface.total_recv,
+                total_sent: interface.total_sent,
+            }))
+            .collect();
+
+        self.elapsed = snapshot.elapsed;
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        // Close file
+        if self.fd > 0 {
+            syscall::close(self.fd);
+        }
     }
+}
+
+impl Collector for Network {
+    const NAME: &'static str = "Network";
+    const SOURCE: Source = Source::Network;
+
+    fn update(&mut self) -> Result<()> {
+        if self.fd < 0 {
+            self.fd = open_file(self.path.as_ptr()).with_context(|| format!("Can't open {}", self.path.to_string_lossy()))?;
+        }
 
-    pub fn update(&mut self) -> Result<()> {
         unsafe {
-            read_fd(self.fd, self.buffer.as_mut_vec()).context("Can't read /proc/net/dev")?;
+            read_fd(self.fd, self.buffer.as_mut_vec()).with_context(|| format!("Can't read {}", self.path.to_string_lossy()))?;
         }
 
+        // The interval can change while running so the rates use the time that actually passed
+        let now = Instant::now();
+        self.elapsed = self.last_update.map_or(Duration::ZERO, |last| now.duration_since(last));
+        self.last_update = Some(now);
+
         for line in self.buffer.lines().skip(2) {
             let mut bandwidth = Bandwidth::default();
 
@@ -53,7 +116,7 @@ impl Network {
                 .context("Can't parse total_sent from /proc/net/dev")?;
 
             // If it hasn't sent and recieved anything it's probably off so don't add it.
-            if bandwidth.total_recv + bandwidth.total_sent != 0 {
+            if bandwidth.total_recv + bandwidth.total_sent != 0 && self.filter.matches(name.trim_end_matches(':')) {
                 self.stats.entry(name)
                     .and_modify(|bw|
                         {
@@ -70,66 +133,3 @@ impl Network {
         Ok(())
     }
 }
-
-impl Drop for Network {
-    fn drop(&mut self) {
-        // Close file
-        if self.fd > 0 {
-            unsafe {
-                asm!("syscall",
-                    in("rax") 3, // SYS_CLOSE
-                    in("rdi") self.fd,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") _,
-                );
-            }
-        }
-    }
-}
-
-pub fn start_thread(internal: Arc<Mutex<Network>>, tx: mpsc::Sender::<u8>, exit: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>, error: Arc<Mutex<Vec::<anyhow::Error>>>, sleepy: std::time::Duration) -> std::thread::JoinHandle<()> {
-    std::thread::Builder::new().name("Network".to_string()).spawn(move || {
-        let (lock, cvar) = &*exit;
-        'outer: loop {
-            match internal.lock() {
-                Ok(mut val) => {
-                    if let Err(err) = val.update() {
-                        let mut errvec = error.lock().expect("Error lock couldn't be aquired!");
-                        errvec.push(err);
-
-                        let _ = tx.send(99);
-
-                        break;
-                    }
-                },
-                Err(_) => break,
-            }
-
-            match tx.send(7) {
-                Ok(_) => (),
-                Err(_) => break,
-            }
-
-            if let Ok(mut exitvar) = lock.lock() {
-                loop {
-                    if let Ok(result) = cvar.wait_timeout(exitvar, sleepy) {
-                        exitvar = result.0;
-
-                        if *exitvar {
-                            break 'outer;
-                        }
-
-                        if result.1.timed_out() {
-                            break;
-                        }
-                    } else {
-                        break 'outer;
-                    }
-                }
-            } else {
-                break;
-            }
-        }
-    }).expect("Couldn't spawn Network thread")
-}
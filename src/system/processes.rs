@@ -1,18 +1,23 @@
-use std::arch::asm;
-
 use anyhow::{ bail, ensure, Context, Result };
-use std::sync::{ Arc, Mutex, mpsc, atomic };
+use std::sync::{ Arc, Mutex, atomic };
 use std::io::Read;
 use std::fmt::Write as fmtWrite;
 use ahash::{ AHashMap, AHashSet };
 use std::collections::hash_map::Entry;
+use std::time::{ Duration, Instant };
 
 pub mod process;
-use super::{ cpu, Config, uring::{ Uring, UringError, IOOPS::*, SMAPS_BIT } };
+use process::NO_SLOT;
+use super::{ cpu, read_fd, root_path, Config, CLK_TCK, IoBackend, Sort, uring::{ Uring, UringError, IOOPS::*, SMAPS_BIT }, collector::Collector, bus::Source };
+use crate::syscall;
+use crate::snapshot::{ ProcessesSnapshot, ProcessSnapshot };
 
 // Size of 'Processes.buffer_directories' used for getdents64()
 const BUF_SIZE: usize = 1024 * 1024;
 
+// Amount of fds kept free for everything that isn't a process
+const FD_RESERVE: usize = 128;
+
 #[repr(C)]
 struct LinuxDirent64T {
     /// 64-bit inode number.
@@ -31,6 +36,17 @@ struct LinuxDirent64T {
     pub d_name: [u8; 4096],
 }
 
+// How the stat and smaps_rollup files are read
+// There's only one of these so the size of Uring doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum Reader {
+    // Everything is submitted to a io_uring in one go
+    Uring(Uring),
+
+    // One SYS_PREAD64 per file. Used when io_uring isn't available
+    Pread,
+}
+
 //#[derive(Default)]
 pub struct Processes {
     // List of all processes
@@ -54,74 +70,153 @@ pub struct Processes {
     // If all_processes isn't enabled, ignore the PIDs in this list
     ignored: AHashSet<u32>,
 
-    // io_uring
-    uring: Uring,
+    // io_uring or pread
+    reader: Reader,
+
+    // Free slots in the io_uring registered file table
+    slots: Vec::<u32>,
+
+    // Amount of fds the processes are allowed to keep open and how many they use
+    fd_budget: usize,
+    pub open_fds: usize,
+
+    // Used to find the least active processes in balance_fds(). (work, total, pid)
+    idle: Vec::<(u64, u64, u32)>,
 
     // Buffer for use with find_all. It's here to save memory
-    index: Vec::<usize>
+    index: Vec::<usize>,
+
+    // When /proc was last searched for new processes
+    last_scan: Option::<Instant>,
+
+    // Time between the last two updates, CPU usage is calculated from it
+    last_update: Option::<Instant>,
+    elapsed: Duration,
+
+    // For the amount of cores
+    cpuinfo: Arc<Mutex<cpu::Cpuinfo>>,
+    config: Arc<Config>,
 }
 
 impl Processes {
-    pub fn new() -> Result<Self> {
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 2, // SYS_OPEN
-                in("rdi") c"/proc".as_ptr(),
-                in("rsi") 16, // O_DIRECTORY
-                //in("rdx") 0, // This is the mode. It is not used in this case
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
-
-        // Check if there's an error
-        ensure!(!ret.is_negative(), "SYS_OPEN return code: {}", ret);
+    // Only fails if io_uring was asked for and can't be set up
+    // /proc is opened by update() so a failure can be retried
+    pub fn new(cpuinfo: Arc<Mutex<cpu::Cpuinfo>>, config: Arc<Config>, fd_limit: u64) -> Result<Self> {
+        // io_uring is often disabled in containers, by seccomp or kernel.io_uring_disabled
+        // Create io_uring with default size (500)
+        let reader = match config.io_backend {
+            IoBackend::Auto => Uring::new(0).map_or(Reader::Pread, Reader::Uring),
+            IoBackend::Uring => Reader::Uring(Uring::new(0).context("Can't make a io_uring")?),
+            IoBackend::Pread => Reader::Pread,
+        };
 
-        Ok(Self {
+        let mut processes = Self {
             processes: AHashMap::default(),
             maxpidlen: 0,
             rebuild: false,
             smaps: false,
-            fd: ret,
+            fd: -1,
             buffer: String::new(),
             buffer_vector_dirs: Vec::with_capacity(BUF_SIZE),
             ignored: AHashSet::default(),
             sorted: Vec::new(),
             index: Vec::<usize>::with_capacity(128),
+            reader,
+            slots: Vec::new(),
+            fd_budget: (fd_limit as usize).saturating_sub(FD_RESERVE),
+            open_fds: 0,
+            idle: Vec::new(),
+            last_scan: None,
+            last_update: None,
+            elapsed: Duration::ZERO,
+            cpuinfo,
+            config,
+        };
+
+        processes.register_files();
+
+        Ok(processes)
+    }
+
+    // Register the stat fds of all processes with io_uring and leave the rest of the table
+    // empty for new processes. If it fails everything is read with plain IORING_OP_READ
+    fn register_files(&mut self) {
+        let uring = if let Reader::Uring(uring) = &mut self.reader {
+            uring
+        } else {
+            return;
+        };
+
+        // The kernel won't register more files than RLIMIT_NOFILE allows
+        let mut fds = vec![-1; uring.entries.min(self.fd_budget)];
+        let mut used = 0;
+
+        for process in self.processes.values_mut() {
+            // Processes with a closed stat_fd are read with read_cold()
+            if used < fds.len() && process.stat_fd > 0 {
+                fds[used] = process.stat_fd;
+                process.slot = used as u32;
+                used += 1;
+            } else {
+                process.slot = NO_SLOT;
+            }
+        }
 
-            // Create io_uring with default size (500)
-            uring: Uring::new(0).expect("Can't make a io_uring"),
-        })
+        self.slots.clear();
+
+        if uring.register(&fds).is_ok() {
+            // Pop from the lowest slot first
+            self.slots.extend((used as u32..fds.len() as u32).rev());
+        } else {
+            for process in self.processes.values_mut() {
+                process.slot = NO_SLOT;
+            }
+        }
     }
 
-    pub fn update(&mut self, cpuinfo: &Arc<Mutex<cpu::Cpuinfo>>, config: &Arc<Config>) -> Result<()> {
+    fn update_all(&mut self, cpuinfo: &Arc<Mutex<cpu::Cpuinfo>>, config: &Arc<Config>) -> Result<()> {
         //let now = std::time::Instant::now();
 
         let all_processes = config.all.load(atomic::Ordering::Relaxed);
 
+        let proc_root = config.proc_root.trim_end_matches('/');
+
         // Trigger rebuild if 'show all processes' option is changed
         if all_processes != self.rebuild {
             self.rebuild = all_processes;
             self.processes.clear();
             self.ignored.clear();
+
+            // All the slots are free now
+            self.register_files();
+
+            self.last_scan = None;
         }
 
-        // Seek to beginning of directory fd
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 8, // SYS_SEEK
-                in("rdi") self.fd,
-                in("rsi") 0, // offset
-                in("rdx") 0, // SET_SEEK
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
+        // Listing /proc and reading cmdlines is the slow part
+        // Between scans only the processes that are already known are updated
+        let scan_interval = Duration::from_millis(config.scan_interval.load(atomic::Ordering::Relaxed));
+        let now = Instant::now();
+
+        if self.last_scan.is_some_and(|last| now.duration_since(last) < scan_interval) {
+            return self.update_processes(cpuinfo, config);
         }
 
+        self.last_scan = Some(now);
+
+        if self.fd < 0 {
+            let proc_dir = root_path(proc_root, "");
+            let ret = unsafe { syscall::open(proc_dir.as_ptr(), 16) }; // O_DIRECTORY
+
+            // Check if there's an error
+            ensure!(!ret.is_negative(), "Can't open {}, SYS_OPEN return code: {}", proc_root, ret);
+
+            self.fd = ret;
+        }
+
+        // Seek to beginning of directory fd
+        let ret = syscall::lseek(self.fd, 0, 0); // SEEK_SET
+
         ensure!(!ret.is_negative(), "SYS_SEEK return code: {}", ret);
 
         loop {
@@ -129,18 +224,7 @@ impl Processes {
             // and adapted to suit my needs
 
             // getdents64 system call
-            let nread: i32;
-            unsafe {
-                asm!("syscall",
-                    in("rax") 217, // SYS_GETDENTS64
-                    in("rdi") self.fd,
-                    in("rsi") self.buffer_vector_dirs.as_mut_ptr(),
-                    in("rdx") BUF_SIZE,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") nread,
-                );
-            }
+            let nread = unsafe { syscall::getdents64(self.fd, self.buffer_vector_dirs.as_mut_ptr(), BUF_SIZE) };
 
             // Make sure there is no error
             ensure!(!nread.is_negative(), "SYS_GETDENTS64 return code: {}", nread);
@@ -179,7 +263,7 @@ impl Processes {
                         if let Entry::Vacant(process_entry) = self.processes.entry(pid) {
                             // Avoiding allocations is cool kids!
                             self.buffer.clear();
-                            let _ = write!(&mut self.buffer, "/proc/{}/cmdline", pid);
+                            let _ = write!(&mut self.buffer, "{}/{}/cmdline", proc_root, pid);
 
                             // If cmdline can't be opened it probably means that the process has terminated, skip it.
                             if let Ok(mut f) = std::fs::File::open(&self.buffer) {
@@ -198,7 +282,8 @@ impl Processes {
                             if !self.buffer.is_empty() & (pid != 1) {
                                 // Cancer code that is very hacky and don't work for all cases
                                 // For instance, if a directory name has spaces or slashes in it, it breaks.
-                                let mut split = self.buffer.split(&['\0', ' '][..]);
+                                // The arguments end with a null too, which would be an empty one at the end
+                                let mut split = self.buffer.trim_end_matches('\0').split(&['\0', ' '][..]);
                                 let executable = split.next()
                                     .context("Parsing error in /proc/[pid]/cmdline")?
                                     .rsplit('/')
@@ -206,18 +291,22 @@ impl Processes {
                                     .context("Parsing error in /proc/[pid]/cmdline")?
                                     .to_string();
 
-                                let cmdline: String = split.intersperse(" ").collect();
+                                let cmdline = split.collect::<Vec::<&str>>().join(" ");
 
                                 // If it's not Ok() then the stat_file couldn't be opened
                                 // which means the process has terminated
-                                if let Ok(process) =
+                                if let Ok(mut process) =
                                     process::Process::new(
+                                        proc_root,
                                         pid,
                                         executable,
                                         cmdline,
-                                        false
+                                        false,
+                                        self.open_fds < self.fd_budget
                                     )
                                 {
+                                    self.open_fds += process.open_fds();
+                                    assign_slot(&mut self.reader, &mut self.slots, &mut process);
                                     process_entry.insert(process);
                                 }
                             } else {
@@ -225,11 +314,11 @@ impl Processes {
                                 if all_processes {
                                     // If stat can't be opened it means the process has terminated, skip it.
                                     self.buffer.clear();
-                                    let _ = write!(&mut self.buffer, "/proc/{}/stat", pid);
+                                    let _ = write!(&mut self.buffer, "{}/{}/stat", proc_root, pid);
 
                                     let executable = if let Ok(mut f) = std::fs::File::open(&self.buffer) {
                                         self.buffer.clear();
-                                        f.read_to_string(&mut self.buffer).with_context(|| format!("/proc/{}/stat", pid))?;
+                                        f.read_to_string(&mut self.buffer).with_context(|| format!("{}/{}/stat", proc_root, pid))?;
                                         self.buffer[
                                             self.buffer.find('(')
                                             .context("Can't parse /proc/[pid]/stat for exetuable name")?
@@ -242,14 +331,18 @@ impl Processes {
 
                                     // If it's not Ok() then the stat_file couldn't be opened
                                     // which means the process has terminated
-                                    if let Ok(process) =
+                                    if let Ok(mut process) =
                                         process::Process::new(
+                                            proc_root,
                                             pid,
                                             executable,
                                             String::new(),
-                                            true
+                                            true,
+                                            self.open_fds < self.fd_budget
                                         )
                                     {
+                                        self.open_fds += process.open_fds();
+                                        assign_slot(&mut self.reader, &mut self.slots, &mut process);
                                         process_entry.insert(process);
                                     }
                                 } else {
@@ -277,6 +370,10 @@ impl Processes {
     }
 
     fn update_processes(&mut self, cpuinfo: &Arc<Mutex<cpu::Cpuinfo>>, config: &Arc<Config>) -> Result<()> {
+        let now = Instant::now();
+        self.elapsed = self.last_update.map_or(Duration::ZERO, |last| now.duration_since(last));
+        self.last_update = Some(now);
+
         let smaps = config.smaps.load(atomic::Ordering::Relaxed);
 
         // If smaps option is toggled off, close the smaps files
@@ -290,61 +387,130 @@ impl Processes {
             }
         }
 
+        let topmode = config.topmode.load(atomic::Ordering::Relaxed);
+
+        match self.reader {
+            Reader::Uring(_) => self.read_uring(smaps, topmode, cpuinfo)?,
+            Reader::Pread => self.read_pread(smaps, topmode, cpuinfo, false)?,
+        }
+
+        self.balance_fds();
+
+        Ok(())
+    }
+
+    // Keep the amount of open fds below fd_budget by closing the fds of the least active processes.
+    // They are read with read_cold() until they become active again and there's room for them
+    fn balance_fds(&mut self) {
+        self.open_fds = self.processes.values().map(|process| process.open_fds()).sum();
+
+        // Stay at 90% of the budget so it doesn't open and close the same processes every update
+        let target = self.fd_budget / 10 * 9;
+
+        if self.open_fds > self.fd_budget {
+            self.idle.clear();
+            self.idle.extend(
+                self.processes.values()
+                    .filter(|process| process.open_fds() != 0)
+                    .map(|process| (process.work, process.total, process.pid))
+            );
+
+            // Least active first
+            self.idle.sort_unstable();
+
+            for (_, _, pid) in self.idle.iter() {
+                if self.open_fds <= target {
+                    break;
+                }
+
+                if let Some(process) = self.processes.get_mut(pid) {
+                    self.open_fds -= process.open_fds();
+
+                    release_slot(&mut self.reader, &mut self.slots, process);
+                    process.close_fds();
+                }
+            }
+        } else if self.open_fds < target {
+            // Open the processes that are doing something again
+            for process in self.processes.values_mut() {
+                if self.open_fds >= target {
+                    break;
+                }
+
+                if process.stat_fd < 0 && process.work != 0 && process.open_stat() {
+                    assign_slot(&mut self.reader, &mut self.slots, process);
+                    self.open_fds += 1;
+                }
+            }
+        }
+    }
+
+    fn read_uring(&mut self, smaps: bool, topmode: bool, cpuinfo: &Arc<Mutex<cpu::Cpuinfo>>) -> Result<()> {
         //let now = std::time::Instant::now();
 
-        // Reset counting variables
-        self.uring.reset();
+        let entries = if let Reader::Uring(uring) = &self.reader {
+            uring.entries
+        } else {
+            bail!("read_uring() called without a io_uring!");
+        };
 
-        // Slightly different behaviour if smaps is enabled
+        // Adjust io_uring ringbuffer according to how many processes are running
+        // Double the size if smaps are enabled
+        // Add 50 to allow for growth, 100 if smaps are enabled
+        // If we have more than 100 free entries shrink the buffer
         if smaps {
-            // Adjust io_uring ringbuffer according to how many processes are running
-            // Double the size if smaps are enabled
-            // Add 50 to allow for growth, 100 if smaps are enabled
-            if (self.processes.len() * 2) > self.uring.entries || (self.processes.len() * 2 + 200) < self.uring.entries {
-                self.uring = Uring::new((self.processes.len() * 2) + 100)?;
+            if (self.processes.len() * 2) > entries || (self.processes.len() * 2 + 200) < entries {
+                self.reader = Reader::Uring(Uring::new((self.processes.len() * 2) + 100)?);
+                self.register_files();
             }
+        } else if self.processes.len() > entries || (self.processes.len() + 100) < entries {
+            self.reader = Reader::Uring(Uring::new(self.processes.len() + 50)?);
+            self.register_files();
+        }
 
-            // Add files to io_uring queue
-            for process in self.processes.values_mut() {
-                self.uring.add_to_queue(process.pid as u64, &mut process.buffer_stat, process.stat_fd, IORING_OP_READ);
+        // Before the io_uring is borrowed
+        let (cpu_count, totald) = self.totals(cpuinfo)?;
+
+        let uring = if let Reader::Uring(uring) = &mut self.reader {
+            uring
+        } else {
+            bail!("read_uring() called without a io_uring!");
+        };
+
+        // Reset counting variables
+        uring.reset();
+
+        // Add files to io_uring queue
+        for process in self.processes.values_mut() {
+            // Closed processes are read after everything else
+            if process.stat_fd < 0 {
+                continue;
+            }
+
+            if process.slot != NO_SLOT {
+                uring.add_fixed_to_queue(process.pid as u64, process.slot);
+            } else {
+                uring.add_to_queue(process.pid as u64, &mut process.buffer_stat, process.stat_fd, IORING_OP_READ);
+            }
 
+            // Slightly different behaviour if smaps is enabled
+            if smaps {
                 let fd = process.get_smaps_fd();
 
                 // If fd is negative it means we either couldn't open the smaps file
                 // or we've tried to read it in the past but failed
                 if !fd.is_negative() {
-                    self.uring.add_to_queue(process.pid as u64 | SMAPS_BIT, &mut process.buffer_smaps, fd, IORING_OP_READ);
+                    uring.add_to_queue(process.pid as u64 | SMAPS_BIT, &mut process.buffer_smaps, fd, IORING_OP_READ);
                 }
             }
-        } else {
-            // Adjust io_uring ringbuffer according to how many processes are running
-            // Double the size if smaps are enabled
-            // Add 50 to allow for growth, 100 if smaps are enabled
-            // If we have more than 100 free entries shrink the buffer
-            if self.processes.len() > self.uring.entries || (self.processes.len() + 100) < self.uring.entries {
-                self.uring = Uring::new(self.processes.len() + 50)?;
-            }
-
-            // Add files to io_uring queue
-            for process in self.processes.values_mut() {
-                self.uring.add_to_queue(process.pid as u64, &mut process.buffer_stat, process.stat_fd, IORING_OP_READ);
-            }
         }
 
         // Submit queue to kernel
-        self.uring.submit_all().context("Can't submit io_uring jobs to the kernel!")?;
-
-        let (cpu_count, totald) = if let Ok(val) = cpuinfo.lock() {
-            (val.cpu_count as f32, val.totald)
-        } else {
-            bail!("Cpuinfo lock is poisoned!");
-        };
-
-        let topmode = config.topmode.load(atomic::Ordering::Relaxed);
+        uring.submit_all().context("Can't submit io_uring jobs to the kernel!")?;
 
         loop {
             // Get next completed IO, returns Err() when all have been read
-            let completion = self.uring.get_next();
+            let completion = uring.get_next();
 
             if let Ok((res, user_data)) = completion {
                 if let Entry::Occupied(entry) = self.processes.entry(user_data as u32) {
@@ -374,28 +540,32 @@ impl Processes {
                     if !res.is_negative() {
                         let process = entry.into_mut();
 
-                        unsafe {
-                            process.buffer_stat.set_len(res as usize);
-                        }
-
-                        unsafe {
-                            process.update_stat(&mut self.index).context("process.update_stat() returned with a failure state!")?;
-                        }
+                        if process.slot != NO_SLOT {
+                            // The data is in the fixed buffer belonging to the slot
+                            let buffer = uring.fixed_buffer(process.slot, res as usize);
 
-                        // Calculate CPU % usage
-                        if topmode {
-                            if process.work > totald {
-                                process.cpu_avg = 100.0 * cpu_count;
-                            } else {
-                                process.cpu_avg = (process.work as f32 / totald as f32) * 100.0 *  cpu_count;
+                            unsafe {
+                                process.update_stat_fixed(buffer, &mut self.index).context("process.update_stat_fixed() returned with a failure state!")?;
                             }
-                        } else if process.work > totald {
-                            process.cpu_avg = 100.0;
                         } else {
-                            process.cpu_avg = (process.work as f32 / totald as f32) * 100.0;
+                            unsafe {
+                                process.buffer_stat.set_len(res as usize);
+                            }
+
+                            unsafe {
+                                process.update_stat(&mut self.index).context("process.update_stat() returned with a failure state!")?;
+                            }
                         }
+
+                        process.update_cpu_avg(totald, cpu_count, topmode);
                     } else {
-                        entry.remove_entry();
+                        let (_, process) = entry.remove_entry();
+
+                        // Give the slot back so a new process can use it
+                        if process.slot != NO_SLOT {
+                            let _ = uring.update_file(process.slot, -1);
+                            self.slots.push(process.slot);
+                        }
                     }
                 }
             } else if let Err(UringError::SubmitToSqResult(_)) = completion {
@@ -406,12 +576,93 @@ impl Processes {
             }
         }
 
+        // Read the processes without an open stat_fd
+        self.read_pread(smaps, topmode, cpuinfo, true)?;
+
         Ok(())
     }
 
-    // Make a list of all processes and sort by amount of Work done
+    // One SYS_PREAD64 per file, for when io_uring isn't available
+    // Processes with a closed stat_fd are opened, read and closed again
+    // If 'cold_only' is true only those are read
+    fn read_pread(&mut self, smaps: bool, topmode: bool, cpuinfo: &Arc<Mutex<cpu::Cpuinfo>>, cold_only: bool) -> Result<()> {
+        let (cpu_count, totald) = self.totals(cpuinfo)?;
+
+        let index = &mut self.index;
+        let mut result = Ok(());
+
+        self.processes.retain(|_, process| {
+            // Stop doing work if something has already failed
+            if result.is_err() {
+                return true;
+            }
+
+            let cold = process.stat_fd < 0;
+
+            if cold_only && !cold {
+                return true;
+            }
+
+            // If it can't be read the process has most likely terminated
+            if cold {
+                if process.read_cold(smaps).is_err() {
+                    return false;
+                }
+            } else if read_fd(process.stat_fd, &mut process.buffer_stat).is_err() {
+                return false;
+            }
+
+            if let Err(err) = unsafe { process.update_stat(index) }.context("process.update_stat() returned with a failure state!") {
+                result = Err(err);
+                return true;
+            }
+
+            process.update_cpu_avg(totald, cpu_count, topmode);
+
+            // read_cold() takes care of smaps
+            if smaps && !cold {
+                let fd = process.get_smaps_fd();
+
+                // If fd is negative it means we either couldn't open the smaps file
+                // or we've tried to read it in the past but failed
+                if !fd.is_negative() {
+                    if read_fd(fd, &mut process.buffer_smaps).is_ok() {
+                        if let Err(err) = process.update_smaps().context("process.update_smaps() returned with a failure state!") {
+                            result = Err(err);
+                        }
+                    } else {
+                        // Most likely because we lack permissions
+                        // Disable smaps for this process and set fd to -1 so it's not opened again
+                        process.disable_smaps();
+                        process.smaps_fd = -1;
+                    }
+                }
+            }
+
+            true
+        });
+
+        result
+    }
+
+    // The amount of cores and how many clock ticks they had together since the last update
+    // It's from the time that passed and not cpuinfo.totald since Cpu can have another interval
+    fn totals(&self, cpuinfo: &Arc<Mutex<cpu::Cpuinfo>>) -> Result<(f32, u64)> {
+        let cpu_count = if let Ok(val) = cpuinfo.lock() {
+            val.cpu_count as f32
+        } else {
+            bail!("Cpuinfo lock is poisoned!");
+        };
+
+        // At least 1 so the first update is 0% and not NaN
+        let totald = ((self.elapsed.as_secs_f64() * CLK_TCK * cpu_count as f64) as u64).max(1);
+
+        Ok((cpu_count, totald))
+    }
+
+    // Make a list of all processes sorted by Config.sort
     // For use with displaying it in the terminal
-    pub fn cpu_sort(&mut self) -> (usize, &Vec::<usize>) {
+    pub fn sort(&mut self) -> (usize, &Vec::<usize>) {
         // This pointer cancer is because I don't want to allocate
         // a new vector every single time this function is called
         self.sorted.clear();
@@ -420,13 +671,7 @@ impl Processes {
             self.sorted.push(val as *const process::Process as usize);
         }
 
-        // Sort by amount of Work, if equal sort by Total Work
-        self.sorted.sort_by(|a, b| {
-            let a = unsafe { &*(*a as *const process::Process) };
-            let b = unsafe { &*(*b as *const process::Process) };
-            b.work.cmp(&a.work)
-                .then(b.total.cmp(&a.total))
-        });
+        self.sort_list();
 
         (self.maxpidlen, &self.sorted)
     }
@@ -444,77 +689,118 @@ impl Processes {
             }
         }
 
-        // Sort by amount of Work, if equal sort by Total Work
+        self.sort_list();
+
+        (self.maxpidlen, &self.sorted)
+    }
+
+    fn sort_list(&mut self) {
+        let sort = self.config.sort();
+        let smaps = self.smaps;
+
+        // Same as what the UI shows
+        let memory = |process: &process::Process| if smaps && process.pss != -1 { process.pss } else { process.rss };
+
         self.sorted.sort_by(|a, b| {
             let a = unsafe { &*(*a as *const process::Process) };
             let b = unsafe { &*(*b as *const process::Process) };
-            b.work.cmp(&a.work)
-                .then(b.total.cmp(&a.total))
+
+            match sort {
+                // Sort by amount of Work, if equal sort by Total Work
+                Sort::Cpu => b.work.cmp(&a.work)
+                    .then(b.total.cmp(&a.total)),
+                Sort::Memory => memory(b).cmp(&memory(a)),
+                Sort::Pid => a.pid.cmp(&b.pid),
+                Sort::Name => a.executable.cmp(&b.executable)
+                    .then(a.pid.cmp(&b.pid)),
+            }
         });
+    }
 
-        (self.maxpidlen, &self.sorted)
+    pub fn snapshot(&self) -> ProcessesSnapshot {
+        let mut processes: Vec::<ProcessSnapshot> = self.processes.values()
+            .map(|process| ProcessSnapshot {
+                pid: process.pid,
+                executable: process.executable.clone(),
+                cmdline: process.cmdline.clone(),
+                kernel_thread: process.not_executable,
+                cpu_usage: process.cpu_avg,
+                cpu_time: process.total,
+                cpu_time_delta: process.work,
+                rss: process.rss.max(0) as u64,
+                pss: (self.smaps && process.pss >= 0).then_some(process.pss as u64),
+            })
+            .collect();
+
+        // Same order as Sort::Cpu
+        processes.sort_by(|a, b| {
+            b.cpu_time_delta.cmp(&a.cpu_time_delta)
+                .then(b.cpu_time.cmp(&a.cpu_time))
+        });
+
+        ProcessesSnapshot { processes }
+    }
+
+    // Show 'snapshot' instead of what was read, see System::restore()
+    pub fn restore(&mut self, snapshot: &ProcessesSnapshot) {
+        self.processes = snapshot.processes.iter()
+            .map(|process| (process.pid, process::Process::from_snapshot(process)))
+            .collect();
+
+        self.maxpidlen = self.processes.keys()
+            .map(|pid| pid.to_string().len())
+            .max()
+            .unwrap_or(0);
+
+        // Used by the memory sort
+        self.smaps = self.config.smaps.load(atomic::Ordering::Relaxed);
     }
 }
 
-impl Drop for Processes {
-    fn drop(&mut self) {
-        // Close file
-        if self.fd != 0 {
-            unsafe {
-                asm!("syscall",
-                    in("rax") 3, // SYS_CLOSE
-                    in("rdi") self.fd,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") _,
-                );
-            }
+// Put the stat fd of 'process' in the registered file table if there's a free slot
+fn assign_slot(reader: &mut Reader, slots: &mut Vec::<u32>, process: &mut process::Process) {
+    if process.stat_fd < 0 {
+        return;
+    }
+
+    if let (Reader::Uring(uring), Some(slot)) = (reader, slots.pop()) {
+        if uring.update_file(slot, process.stat_fd).is_ok() {
+            process.slot = slot;
+        } else {
+            slots.push(slot);
         }
     }
 }
 
-pub fn start_thread(internal: Arc<Mutex<Processes>>, cpuinfo: Arc<Mutex<cpu::Cpuinfo>>, config: Arc<Config>, tx: mpsc::Sender::<u8>, exit: Arc<(Mutex<bool>, std::sync::Condvar)>, error: Arc<Mutex<Vec::<anyhow::Error>>>, sleepy: std::time::Duration) -> std::thread::JoinHandle<()> {
-    std::thread::Builder::new().name("Processes".to_string()).spawn(move || {
-        let (lock, cvar) = &*exit;
-        'outer: loop {
-            match internal.lock() {
-                Ok(mut val) => {
-                    if let Err(err) = val.update(&cpuinfo, &config) {
-                        let mut errvec = error.lock().expect("Error lock couldn't be aquired!");
-
-                        errvec.push(err);
-                        let _ = tx.send(99);
+// Take 'process' out of the registered file table
+fn release_slot(reader: &mut Reader, slots: &mut Vec::<u32>, process: &mut process::Process) {
+    if let Reader::Uring(uring) = reader {
+        if process.slot != NO_SLOT {
+            let _ = uring.update_file(process.slot, -1);
+            slots.push(process.slot);
+        }
+    }
 
-                        break;
-                    }
-                },
-                Err(_) => break,
-            }
+    process.slot = NO_SLOT;
+}
 
-            match tx.send(8) {
-                Ok(_) => (),
-                Err(_) => break,
-            }
+impl Drop for Processes {
+    fn drop(&mut self) {
+        // Close file
+        if self.fd > 0 {
+            syscall::close(self.fd);
+        }
+    }
+}
 
-            if let Ok(mut exitvar) = lock.lock() {
-                loop {
-                    if let Ok(result) = cvar.wait_timeout(exitvar, sleepy) {
-                        exitvar = result.0;
+impl Collector for Processes {
+    const NAME: &'static str = "Processes";
+    const SOURCE: Source = Source::Processes;
 
-                        if *exitvar {
-                            break 'outer;
-                        }
+    fn update(&mut self) -> Result<()> {
+        let cpuinfo = Arc::clone(&self.cpuinfo);
+        let config = Arc::clone(&self.config);
 
-                        if result.1.timed_out() {
-                            break;
-                        }
-                    } else {
-                        break 'outer;
-                    }
-                }
-            } else {
-                break;
-            }
-        }
-    }).expect("Couldn't spawn Processes thread")
+        self.update_all(&cpuinfo, &config)
+    }
 }
@@ -1,10 +1,16 @@
-use std::arch::asm;
-
 use anyhow::{ ensure, Context, Result };
 use std::ffi::CString;
+#[cfg(target_arch = "x86_64")]
 use core::arch::x86_64::*;
 use std::alloc;
 
+use super::super::{ open_file, read_fd, close_fd };
+use crate::syscall;
+use crate::snapshot::ProcessSnapshot;
+
+// Process.slot value for processes that aren't in the registered file table
+pub const NO_SLOT: u32 = u32::MAX;
+
 #[derive(Default)]
 pub struct Process {
     pub cpu_avg: f32,
@@ -12,7 +18,7 @@ pub struct Process {
     pub cmdline: String,
     pub executable: String,
 
-    //stat_file: CString,
+    stat_file: CString,
     smaps_file: CString,
 
     // /proc/stat
@@ -33,33 +39,34 @@ pub struct Process {
 
     pub not_executable: bool,
 
+    // -1 if it's closed to save fds, see Processes.balance_fds()
     pub stat_fd: i32,
     pub smaps_fd: i32,
 
+    // Index into the io_uring registered file table, NO_SLOT if it doesn't have one
+    pub slot: u32,
+
     pub buffer_stat: Vec::<u8>,
     pub buffer_smaps: Vec::<u8>,
 
 }
 
 impl Process {
-    pub fn new(pid: u32, executable: String, cmdline: String, not_executable: bool) -> Result<Self> {
-        let stat_file = unsafe { CString::from_vec_unchecked(format!("/proc/{}/stat", pid).into_bytes()) };
-
-        // Open file
-        let fd: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 2, // SYS_OPEN
-                in("rdi") stat_file.as_ptr(),
-                in("rsi") 0, // O_RDONLY
-                //in("rdx") 0, // This is the mode. It is not used in this case
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") fd,
-            );
-        }
+    // 'proc_root' is Config.proc_root without a trailing slash
+    // 'open' is false when Processes is over its fd budget, it starts cold then
+    pub fn new(proc_root: &str, pid: u32, executable: String, cmdline: String, not_executable: bool, open: bool) -> Result<Self> {
+        let stat_file = unsafe { CString::from_vec_unchecked(format!("{}/{}/stat", proc_root, pid).into_bytes()) };
+
+        // Open file, O_RDONLY
+        // If it's cold read_cold() finds out if it has terminated
+        let fd = match open {
+            true => unsafe { syscall::open(stat_file.as_ptr(), 0) },
+            false => -1,
+        };
 
-        ensure!(!fd.is_negative());
+        // If we are out of fds (EMFILE or ENFILE) keep it closed and read it with read_cold()
+        // Anything else means the process has terminated
+        ensure!(!fd.is_negative() || fd == -24 || fd == -23);
 
         // This is to ensure that the vector is aligned to 32 bytes for my asm
         let layout = alloc::Layout::from_size_align(512, 32).expect("Can't create aligned layout!");
@@ -72,73 +79,74 @@ impl Process {
             pid,
             executable,
             cmdline,
-            //stat_file,
-            smaps_file: unsafe { CString::from_vec_unchecked(format!("/proc/{}/smaps_rollup", pid).into_bytes()) },
+            stat_file,
+            smaps_file: unsafe { CString::from_vec_unchecked(format!("{}/{}/smaps_rollup", proc_root, pid).into_bytes()) },
             not_executable,
             buffer_stat,
             buffer_smaps: Vec::<u8>::with_capacity(1024),
             pss: -1,
-            stat_fd: fd,
+            stat_fd: fd.max(-1),
+            slot: NO_SLOT,
             ..Default::default()
         })
     }
 
+    // A process that only has the values from 'snapshot'. It has no files and can't be updated
+    pub fn from_snapshot(snapshot: &ProcessSnapshot) -> Self {
+        // It has a Drop so ..Default::default() can't be used
+        let mut process = Self::default();
+
+        process.pid = snapshot.pid;
+        process.executable.clone_from(&snapshot.executable);
+        process.cmdline.clone_from(&snapshot.cmdline);
+        process.not_executable = snapshot.kernel_thread;
+        process.cpu_avg = snapshot.cpu_usage;
+        process.total = snapshot.cpu_time;
+        process.work = snapshot.cpu_time_delta;
+        process.rss = snapshot.rss as i64;
+        process.pss = snapshot.pss.map_or(-1, |pss| pss as i64);
+        process.stat_fd = -1;
+        process.smaps_fd = -1;
+        process.slot = NO_SLOT;
+
+        process
+    }
+
     pub fn get_smaps_fd(&mut self) -> i32 {
         // Only need to open it once
         if self.smaps_fd == 0 {
-            // Open file
-            let fd: i32;
-            unsafe {
-                asm!("syscall",
-                    in("rax") 2, // SYS_OPEN
-                    in("rdi") self.smaps_file.as_ptr(),
-                    in("rsi") 0, // O_RDONLY
-                    //in("rdx") 0, // This is the mode. It is not used in this case
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") fd,
-                );
-            }
-
-            self.smaps_fd = fd;
+            // Open file, O_RDONLY
+            self.smaps_fd = unsafe { syscall::open(self.smaps_file.as_ptr(), 0) };
         }
 
         self.smaps_fd
     }
 
-    pub unsafe fn update_stat(&mut self, index: &mut Vec::<usize>) -> Result<()> {
-        //let now = std::time::Instant::now();
+    // Parse buffer_stat
+    pub(crate) unsafe fn update_stat(&mut self, index: &mut Vec::<usize>) -> Result<()> {
+        let (total, rss) = parse_stat(self.buffer_stat.as_slice(), index, self.pid)?;
 
-        // Need to keep the old total so we have something to compare to
-        let old_total = self.total;
-
-        // Find all instances of [space] and record their index in index
-        find_all(index, self.buffer_stat.as_slice());
-
-        // Adjust the indexes so they are always the same
-        let idx = index.split_at(index.len().checked_sub(51).context("Index is too small!")?).1;
-
-        let utime: u64 = btoi::btou(&self.buffer_stat[*idx.get_unchecked(11)+1..*idx.get_unchecked(12)]).context("Can't convert utime to a number!").with_context(||format!("pid: {}", self.pid))?;
-
-//             eprintln!("utime: {:?}", self.utime);
+        self.set_stat(total, rss);
 
-        let stime: u64 = btoi::btou(&self.buffer_stat[*idx.get_unchecked(12)+1..*idx.get_unchecked(13)]).context("Can't convert stime to a number!").with_context(||format!("pid: {}", self.pid))?;
-
-//             eprintln!("stime: {:?}", self.stime);
-
-        let cutime: u64 = btoi::btou(&self.buffer_stat[*idx.get_unchecked(13)+1..*idx.get_unchecked(14)]).context("Can't convert cutime to a number!").with_context(||format!("pid: {}", self.pid))?;
-
-//             eprintln!("cutime: {:?}", self.cutime);
+        Ok(())
+    }
 
-        let cstime: u64 = btoi::btou(&self.buffer_stat[*idx.get_unchecked(14)+1..*idx.get_unchecked(15)]).context("Can't convert cstime to a number!").with_context(||format!("pid: {}", self.pid))?;
+    // Parse 'buffer' instead of buffer_stat, used with the io_uring fixed buffers
+    // 'buffer' needs to be aligned to 32 bytes
+    pub(crate) unsafe fn update_stat_fixed(&mut self, buffer: &[u8], index: &mut Vec::<usize>) -> Result<()> {
+        let (total, rss) = parse_stat(buffer, index, self.pid)?;
 
-//             eprintln!("cstime: {:?}", self.cstime);
+        self.set_stat(total, rss);
 
-        self.rss = btoi::btou::<i64>(&self.buffer_stat[*idx.get_unchecked(22)+1..*idx.get_unchecked(23)]).context("Can't convert rss to a number!").with_context(||format!("pid: {}", self.pid))? * 4096;
+        Ok(())
+    }
 
-//             eprintln!("rss: {:?}", self.rss);
+    fn set_stat(&mut self, total: u64, rss: i64) {
+        // Need to keep the old total so we have something to compare to
+        let old_total = self.total;
 
-        self.total = utime + stime + cutime + cstime;
+        self.rss = rss;
+        self.total = total;
 
         //eprintln!("total: {:?}, old_total: {:?}", self.total, old_total);
 
@@ -148,25 +156,96 @@ impl Process {
         } else {
             0
         };
+    }
 
-        Ok(())
-
-        //eprintln!("{}", now.elapsed().as_nanos());
+    // Calculate CPU % usage
+    pub fn update_cpu_avg(&mut self, totald: u64, cpu_count: f32, topmode: bool) {
+        if topmode {
+            if self.work > totald {
+                self.cpu_avg = 100.0 * cpu_count;
+            } else {
+                self.cpu_avg = (self.work as f32 / totald as f32) * 100.0 *  cpu_count;
+            }
+        } else if self.work > totald {
+            self.cpu_avg = 100.0;
+        } else {
+            self.cpu_avg = (self.work as f32 / totald as f32) * 100.0;
+        }
     }
 
     pub fn update_smaps(&mut self) -> Result<()> {
          // If smaps_fd isn't above 0 it means we couldn't open/read it so set pss == -1
         if self.smaps_fd > 0 {
-            // Should maybe skip converting to str. I'll have to benchmark it
-            let data = unsafe { std::str::from_utf8_unchecked(&self.buffer_smaps) };
-            self.pss = btoi::btou::<i64>(data.lines()
-                .nth(2)
-                .context("Can't parse 'pss' from /proc/[pid]/smaps_rollup, before whitespace")?
-                .split_ascii_whitespace()
-                .nth(1)
-                .context("Can't parse 'pss' from /proc/[pid]/smaps_rollup, after whitespace")?.as_bytes())
-                .context("Can't convert 'pss' to a number")?
-                * 1024;
+            self.parse_pss()?;
+        } else {
+            self.pss = -1;
+        }
+
+        Ok(())
+    }
+
+    fn parse_pss(&mut self) -> Result<()> {
+        // Should maybe skip converting to str. I'll have to benchmark it
+        let data = unsafe { std::str::from_utf8_unchecked(&self.buffer_smaps) };
+        self.pss = btoi::btou::<i64>(data.lines()
+            .nth(2)
+            .context("Can't parse 'pss' from /proc/[pid]/smaps_rollup, before whitespace")?
+            .split_ascii_whitespace()
+            .nth(1)
+            .context("Can't parse 'pss' from /proc/[pid]/smaps_rollup, after whitespace")?.as_bytes())
+            .context("Can't convert 'pss' to a number")?
+            * 1024;
+
+        Ok(())
+    }
+
+    // Amount of fds this process has open
+    pub fn open_fds(&self) -> usize {
+        (self.stat_fd > 0) as usize + (self.smaps_fd > 0) as usize
+    }
+
+    // Close all fds. The process is read with read_cold() until open_stat() is called
+    pub fn close_fds(&mut self) {
+        if self.stat_fd > 0 {
+            close_fd(self.stat_fd);
+            self.stat_fd = -1;
+        }
+
+        self.disable_smaps();
+    }
+
+    // Returns false if it couldn't be opened
+    pub fn open_stat(&mut self) -> bool {
+        if let Ok(fd) = open_file(self.stat_file.as_ptr()) {
+            self.stat_fd = fd;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Open, read and close stat_file (and smaps_file if 'smaps' is true) into the buffers
+    // Returns an error if the process has terminated
+    pub fn read_cold(&mut self, smaps: bool) -> Result<()> {
+        let fd = open_file(self.stat_file.as_ptr())?;
+        let ret = read_fd(fd, &mut self.buffer_stat);
+        close_fd(fd);
+        ret?;
+
+        // smaps_fd == -1 means we can't read it, don't try again
+        if smaps && self.smaps_fd == 0 {
+            let data = open_file(self.smaps_file.as_ptr()).and_then(|fd| {
+                let ret = read_fd(fd, &mut self.buffer_smaps);
+                close_fd(fd);
+                ret
+            });
+
+            if data.is_ok() {
+                self.parse_pss()?;
+            } else {
+                self.smaps_fd = -1;
+                self.pss = -1;
+            }
         } else {
             self.pss = -1;
         }
@@ -177,15 +256,7 @@ impl Process {
     pub fn disable_smaps(&mut self) {
         // If smaps is turned On and then Off we should close the file
         if self.smaps_fd > 0 {
-            unsafe {
-                asm!("syscall",
-                    in("rax") 3, // SYS_CLOSE
-                    in("rdi") self.smaps_fd,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") _,
-                );
-            }
+            syscall::close(self.smaps_fd);
 
             self.smaps_fd = 0;
         }
@@ -196,33 +267,72 @@ impl Drop for Process {
     fn drop(&mut self) {
         // Close any open FDs when it's dropped
         if self.stat_fd > 0 {
-            unsafe {
-                asm!("syscall",
-                    in("rax") 3, // SYS_CLOSE
-                    in("rdi") self.stat_fd,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") _,
-                );
-            }
+            syscall::close(self.stat_fd);
         }
 
         if self.smaps_fd > 0 {
-            unsafe {
-                asm!("syscall",
-                    in("rax") 3, // SYS_CLOSE
-                    in("rdi") self.smaps_fd,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") _,
-                );
-            }
+            syscall::close(self.smaps_fd);
         }
     }
 }
 
+// Returns (utime + stime + cutime + cstime, rss in bytes) from the contents of /proc/[pid]/stat
+unsafe fn parse_stat(buffer: &[u8], index: &mut Vec::<usize>, pid: u32) -> Result<(u64, i64)> {
+    //let now = std::time::Instant::now();
+
+    // Find all instances of [space] and record their index in index
+    find_all(index, buffer);
+
+    // Adjust the indexes so they are always the same
+    let idx = index.split_at(index.len().checked_sub(51).context("Index is too small!")?).1;
+
+    let utime: u64 = btoi::btou(&buffer[*idx.get_unchecked(11)+1..*idx.get_unchecked(12)]).context("Can't convert utime to a number!").with_context(||format!("pid: {}", pid))?;
+
+//         eprintln!("utime: {:?}", utime);
+
+    let stime: u64 = btoi::btou(&buffer[*idx.get_unchecked(12)+1..*idx.get_unchecked(13)]).context("Can't convert stime to a number!").with_context(||format!("pid: {}", pid))?;
+
+//         eprintln!("stime: {:?}", stime);
+
+    let cutime: u64 = btoi::btou(&buffer[*idx.get_unchecked(13)+1..*idx.get_unchecked(14)]).context("Can't convert cutime to a number!").with_context(||format!("pid: {}", pid))?;
+
+//         eprintln!("cutime: {:?}", cutime);
+
+    let cstime: u64 = btoi::btou(&buffer[*idx.get_unchecked(14)+1..*idx.get_unchecked(15)]).context("Can't convert cstime to a number!").with_context(||format!("pid: {}", pid))?;
+
+//         eprintln!("cstime: {:?}", cstime);
+
+    let rss = btoi::btou::<i64>(&buffer[*idx.get_unchecked(22)+1..*idx.get_unchecked(23)]).context("Can't convert rss to a number!").with_context(||format!("pid: {}", pid))? * 4096;
+
+//         eprintln!("rss: {:?}", rss);
+
+    //eprintln!("{}", now.elapsed().as_nanos());
+
+    Ok((utime + stime + cutime + cstime, rss))
+}
+
 // Find all instances of [space] and put their index in positions vector
+// Picks the fastest version the CPU supports. 'haystack' needs to be aligned to 32 bytes
+// and the memory after it has to be readable up to the next 32 byte boundary
 unsafe fn find_all(positions: &mut Vec::<usize>, haystack: &[u8]) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        // The result of is_x86_feature_detected!() is cached so this is cheap
+        if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("bmi1") {
+            find_all_avx2(positions, haystack);
+        } else {
+            // SSE2 is always available on x86_64
+            find_all_sse2(positions, haystack);
+        }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    find_all_scalar(positions, haystack);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,bmi1")]
+unsafe fn find_all_avx2(positions: &mut Vec::<usize>, haystack: &[u8]) {
     // Set buffer length to Capacity
     positions.set_len(128);
     let slice = positions.as_mut_slice();
@@ -284,3 +394,204 @@ unsafe fn find_all(positions: &mut Vec::<usize>, haystack: &[u8]) {
 
     positions.set_len(idx);
 }
+
+// Same as find_all_avx2() but 16 bytes at a time
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn find_all_sse2(positions: &mut Vec::<usize>, haystack: &[u8]) {
+    // Set buffer length to Capacity
+    positions.set_len(128);
+    let slice = positions.as_mut_slice();
+
+    let start_ptr = haystack.as_ptr();
+    let end_ptr = start_ptr.add(haystack.len());
+    let mut ptr = start_ptr;
+
+    // Set all bytes in register to 32, aka [space]
+    let vn1 = _mm_set1_epi8(32);
+
+    // Load 16 bytes from buffer
+    let data = _mm_load_si128(ptr as *const __m128i);
+
+    // Compare against vn1 and save the mask
+    let mut mask = _mm_movemask_epi8(_mm_cmpeq_epi8(vn1, data)) as u32;
+
+    // The index of the current item in positions
+    let mut idx = 0;
+
+    let mut idx_ptr = 0;
+
+    while ptr.add(16) < end_ptr {
+        // If mask is zero it means there are no matches
+        while mask != 0 {
+            // Saved index of match in buffer in positions
+            *slice.get_unchecked_mut(idx) = idx_ptr as usize + mask.trailing_zeros() as usize;
+
+            idx += 1;
+
+            // Zero lowest set bit in the mask
+            mask &= mask - 1;
+        }
+
+        ptr = ptr.add(16);
+
+        idx_ptr = ptr.offset_from(start_ptr);
+
+        // Load the next 16 bytes from buffer
+        let data = _mm_load_si128(ptr as *const __m128i);
+
+        // Compare and save mask
+        mask = _mm_movemask_epi8(_mm_cmpeq_epi8(vn1, data)) as u32;
+    }
+
+    // Deal with any remaining bytes
+    while mask != 0 {
+        let position = idx_ptr as usize + mask.trailing_zeros() as usize;
+
+        if position >= haystack.len() {
+            break;
+        }
+
+        *slice.get_unchecked_mut(idx) = position;
+        idx += 1;
+
+        mask &= mask - 1;
+    }
+
+    assert!(idx < positions.capacity(), "Index too large");
+
+    positions.set_len(idx);
+}
+
+// Same as find_all_avx2() but one byte at a time
+#[cfg_attr(target_arch = "x86_64", allow(dead_code))]
+fn find_all_scalar(positions: &mut Vec::<usize>, haystack: &[u8]) {
+    positions.clear();
+
+    for (idx, byte) in haystack.iter().enumerate() {
+        if *byte == b' ' {
+            positions.push(idx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Aligned to 32 bytes like Process.buffer_stat
+    #[repr(align(32))]
+    struct Buffer([u8; 512]);
+
+    // xorshift64, good enough to make up stat lines
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, max: u64) -> u64 {
+            self.next() % max
+        }
+    }
+
+    // Something that looks like /proc/[pid]/stat, the executable name can contain spaces
+    fn stat_line(rng: &mut Rng) -> String {
+        let mut line = format!("{} (", rng.below(4194304) + 1);
+
+        for _ in 0..rng.below(16) {
+            if rng.below(4) == 0 {
+                line.push(' ');
+            } else {
+                line.push((b'a' + rng.below(26) as u8) as char);
+            }
+        }
+
+        line.push_str(") S");
+
+        for _ in 0..49 {
+            line.push(' ');
+            line.push_str(&(rng.next() >> (32 + rng.below(32))).to_string());
+        }
+
+        line.push('\n');
+
+        line
+    }
+
+    // Copy 'line' into 'buffer' and fill whatever is left with spaces
+    // so anything that reads past the end would be caught
+    fn fill(buffer: &mut Buffer, line: &str) -> usize {
+        buffer.0.fill(b' ');
+        let len = line.len().min(buffer.0.len());
+        buffer.0[..len].copy_from_slice(&line.as_bytes()[..len]);
+
+        len
+    }
+
+    #[test]
+    fn find_all_versions_are_identical() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut buffer = Buffer([0; 512]);
+
+        let mut scalar = Vec::<usize>::with_capacity(128);
+        #[cfg(target_arch = "x86_64")]
+        let mut sse2 = Vec::<usize>::with_capacity(128);
+        #[cfg(target_arch = "x86_64")]
+        let mut avx2 = Vec::<usize>::with_capacity(128);
+
+        for _ in 0..10000 {
+            let line = stat_line(&mut rng);
+            let len = fill(&mut buffer, &line);
+            let haystack = &buffer.0[..len];
+
+            find_all_scalar(&mut scalar, haystack);
+
+            #[cfg(target_arch = "x86_64")]
+            {
+                unsafe { find_all_sse2(&mut sse2, haystack) };
+                assert_eq!(scalar, sse2, "SSE2 differs for {:?}", line);
+
+                if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("bmi1") {
+                    unsafe { find_all_avx2(&mut avx2, haystack) };
+                    assert_eq!(scalar, avx2, "AVX2 differs for {:?}", line);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn find_all_handles_every_length() {
+        let mut buffer = Buffer([0; 512]);
+        let mut scalar = Vec::<usize>::with_capacity(128);
+        #[cfg(target_arch = "x86_64")]
+        let mut sse2 = Vec::<usize>::with_capacity(128);
+        #[cfg(target_arch = "x86_64")]
+        let mut avx2 = Vec::<usize>::with_capacity(128);
+
+        let line = "1 (a b) S 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49 50\n";
+
+        // Every length, including exact multiples of 16 and 32
+        for len in 0..=line.len() {
+            fill(&mut buffer, &line[..len]);
+            let haystack = &buffer.0[..len];
+
+            find_all_scalar(&mut scalar, haystack);
+
+            #[cfg(target_arch = "x86_64")]
+            {
+                unsafe { find_all_sse2(&mut sse2, haystack) };
+                assert_eq!(scalar, sse2, "SSE2 differs at length {}", len);
+
+                if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("bmi1") {
+                    unsafe { find_all_avx2(&mut avx2, haystack) };
+                    assert_eq!(scalar, avx2, "AVX2 differs at length {}", len);
+                }
+            }
+        }
+    }
+}
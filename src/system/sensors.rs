@@ -1,21 +1,56 @@
-use std::sync::{Arc, Mutex, mpsc};
+use anyhow::Result;
+
+use super::{ Config, Filter, collector::Collector, bus::Source };
+use crate::snapshot::{ SensorsSnapshot, SensorSnapshot };
 
 pub struct Sensors {
     pub chips: std::collections::BTreeMap<String, u8>,
+
+    // Critical temperature of the sensors in 'chips' that have one
+    pub crit: std::collections::BTreeMap<String, u8>,
     pub sensors: sensors::Sensors,
+    filter: Filter,
 }
 
-impl Default for Sensors {
-    fn default() -> Self {
+impl Sensors {
+    pub fn new(config: &Config) -> Self {
         Sensors {
             chips: std::collections::BTreeMap::new(),
+            crit: std::collections::BTreeMap::new(),
             sensors: sensors::Sensors::new(),
+            filter: config.sensors.clone(),
+        }
+    }
+
+    pub fn snapshot(&self) -> SensorsSnapshot {
+        SensorsSnapshot {
+            sensors: self.chips.iter()
+                .map(|(label, temperature)| SensorSnapshot {
+                    label: label.clone(),
+                    temperature: *temperature,
+                    crit: self.crit.get(label).copied(),
+                })
+                .collect(),
         }
     }
+
+    // Show 'snapshot' instead of what was read, see System::restore()
+    pub fn restore(&mut self, snapshot: &SensorsSnapshot) {
+        self.chips = snapshot.sensors.iter()
+            .map(|sensor| (sensor.label.clone(), sensor.temperature))
+            .collect();
+
+        self.crit = snapshot.sensors.iter()
+            .filter_map(|sensor| Some((sensor.label.clone(), sensor.crit?)))
+            .collect();
+    }
 }
 
-impl Sensors {
-    pub fn update(&mut self) {
+impl Collector for Sensors {
+    const NAME: &'static str = "Sensors";
+    const SOURCE: Source = Source::Sensors;
+
+    fn update(&mut self) -> Result<()> {
         // Don't handle any errors, just skip that sensor.
         for chip in self.sensors {
             if let Ok(mut c_name) = chip.get_name() {
@@ -25,56 +60,33 @@ impl Sensors {
                             if let Ok(val) = subfeature.get_value() {
                                 // If the name is temp1 that means it doesn't have a f_name
                                 // So use the chip name instead
-                                if f_name == "temp1" {
+                                let label = if f_name == "temp1" {
                                     c_name.truncate(14);
-                                    self.chips.insert(c_name.clone(), val.round() as u8);
+                                    c_name.clone()
                                 }
                                 else {
                                     f_name.truncate(14);
-                                    self.chips.insert(f_name, val.round() as u8);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-}
-
-pub fn start_thread(internal: Arc<Mutex<Sensors>>, tx: mpsc::Sender::<u8>, exit: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>, sleepy: std::time::Duration) -> std::thread::JoinHandle<()> {
-    std::thread::Builder::new().name("Sensors".to_string()).spawn(move || {
-        let (lock, cvar) = &*exit;
-        'outer: loop {
-            match internal.lock() {
-                Ok(mut val) => val.update(),
-                Err(_) => break,
-            }
+                                    f_name
+                                };
 
-            match tx.send(6) {
-                Ok(_) => (),
-                Err(_) => break,
-            }
-
-            if let Ok(mut exitvar) = lock.lock() {
-                loop {
-                    if let Ok(result) = cvar.wait_timeout(exitvar, sleepy) {
-                        exitvar = result.0;
+                                if self.filter.matches(&label) {
+                                    let crit = feature.get_subfeature(sensors::SubfeatureType::SENSORS_SUBFEATURE_TEMP_CRIT)
+                                        .and_then(|crit| crit.get_value().ok())
+                                        .filter(|crit| *crit > 0.0);
 
-                        if *exitvar {
-                            break 'outer;
-                        }
+                                    if let Some(crit) = crit {
+                                        self.crit.insert(label.clone(), crit.round() as u8);
+                                    }
 
-                        if result.1.timed_out() {
-                            break;
+                                    self.chips.insert(label, val.round() as u8);
+                                }
+                            }
                         }
-                    } else {
-                        break 'outer;
                     }
                 }
-            } else {
-                break;
             }
         }
-    }).expect("Couldn't spawn Sensors thread")
+
+        Ok(())
+    }
 }
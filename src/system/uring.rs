@@ -1,8 +1,9 @@
-use std::arch::asm;
-
-
 use thiserror::Error;
 use std::sync::atomic::*;
+use std::alloc;
+use std::ffi::c_void;
+
+use crate::syscall;
 
 #[allow(dead_code,non_camel_case_types)]
 pub enum IOOPS {
@@ -168,9 +169,38 @@ const MAP_POPULATE: u32 = 0x008000;
 const IORING_OFF_SQES: u64 = 0x10000000;
 const IORING_OFF_SQ_RING: u64 = 0;
 
+// io_uring_register opcodes
+const IORING_REGISTER_BUFFERS: u32 = 0;
+const IORING_UNREGISTER_BUFFERS: u32 = 1;
+const IORING_REGISTER_FILES: u32 = 2;
+const IORING_UNREGISTER_FILES: u32 = 3;
+const IORING_REGISTER_FILES_UPDATE: u32 = 6;
+
+// sqe->flags
+const IOSQE_FIXED_FILE: u8 = 1 << 0;
+
+// Size of each fixed buffer slot. Same size as Process.buffer_stat
+pub const FIXED_BUF_SIZE: usize = 512;
 
 pub const SMAPS_BIT: u64 = 1<<63;
 
+// Used with IORING_REGISTER_FILES_UPDATE
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct io_uring_files_update {
+    offset: u32,
+    resv: u32,
+    fds: u64,    /* __aligned_u64 pointer to the fd array */
+}
+
+// Used with IORING_REGISTER_BUFFERS
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct iovec {
+    iov_base: u64,
+    iov_len: u64,
+}
+
 
 #[derive(Error, Debug)]
 pub enum UringError {
@@ -195,6 +225,11 @@ pub enum UringError {
     #[error("All jobs have been processed")]
     JobComplete,
 
+    #[error("SYS_IO_URING_REGISTER returned an error: {0}")]
+    SysIoUringRegister(i32),
+
+    #[error("Can't allocate memory for the fixed buffers")]
+    FixedBufferAlloc,
 }
 
 
@@ -241,6 +276,15 @@ pub struct Uring {
     cring_mask: *const u32,
 
     ring_fd: i32,
+
+    /** Registered files and buffers **/
+
+    // Amount of slots in the registered file table, 0 if nothing is registered
+    pub slots: u32,
+
+    // One FIXED_BUF_SIZE buffer per file slot, registered as a single fixed buffer
+    fixed_buffers: *mut u8,
+    fixed_layout: Option<alloc::Layout>,
 }
 
 // Since this is only used in a single thread just treat it as safe.
@@ -254,17 +298,7 @@ impl Uring {
             queue_depth = QUEUE_DEPTH
         }
 
-        let ring_fd: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 425, //SYS_IO_URING_SETUP
-                in("rdi") queue_depth,
-                in("rsi") &mut io_params as *mut io_uring_params,
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ring_fd,
-            );
-        }
+        let ring_fd = unsafe { syscall::io_uring_setup(queue_depth, &mut io_params as *mut io_uring_params as *mut c_void) };
 
         checkerr!(ring_fd.is_negative(), UringError::SysIoUringSetup(ring_fd));
 
@@ -279,21 +313,16 @@ impl Uring {
         sring_sz = sring_sz.max(cring_sz);
 
         // Ringbuffer mmap
-        let ret: i64;
-        unsafe {
-            asm!("syscall",
-                in("rax") 9, // SYS_MMAP
-                in("rdi") 0, // address
-                in("rsi") sring_sz, // length
-                in("rdx") PROT_READ | PROT_WRITE, // prot
-                in("r10") MAP_SHARED | MAP_POPULATE, // flags
-                in("r8") ring_fd, // fd
-                in("r9") IORING_OFF_SQ_RING, // offset
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
+        let ret = unsafe {
+            syscall::mmap(
+                0, // address
+                sring_sz as usize, // length
+                PROT_READ | PROT_WRITE, // prot
+                MAP_SHARED | MAP_POPULATE, // flags
+                ring_fd, // fd
+                IORING_OFF_SQ_RING, // offset
+            )
+        };
 
         checkerr!(ret.is_negative(), UringError::SysMmapSqPtr(ret as i32));
 
@@ -308,21 +337,16 @@ impl Uring {
 
 
         // SQE array mmap
-        let ret: i64;
-        unsafe {
-            asm!("syscall",
-                in("rax") 9, // SYS_MMAP
-                in("rdi") 0, // address
-                in("rsi") io_params.sq_entries * std::mem::size_of::<io_uring_sqe>() as u32, // length
-                in("rdx") PROT_READ | PROT_WRITE, // prot
-                in("r10") MAP_SHARED | MAP_POPULATE, // flags
-                in("r8") ring_fd, // fd
-                in("r9") IORING_OFF_SQES, // offset
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
+        let ret = unsafe {
+            syscall::mmap(
+                0, // address
+                io_params.sq_entries as usize * std::mem::size_of::<io_uring_sqe>(), // length
+                PROT_READ | PROT_WRITE, // prot
+                MAP_SHARED | MAP_POPULATE, // flags
+                ring_fd, // fd
+                IORING_OFF_SQES, // offset
+            )
+        };
 
         checkerr!(ret.is_negative(), UringError::SysMmapSqes(ret as i32));
 
@@ -359,9 +383,91 @@ impl Uring {
 
             ring_fd,
 
+            slots: 0,
+            fixed_buffers: std::ptr::null_mut(),
+            fixed_layout: None,
         })
     }
 
+    fn register_syscall(&self, opcode: u32, arg: u64, nr_args: u32) -> Result<(), UringError> {
+        let ret = unsafe { syscall::io_uring_register(self.ring_fd, opcode, arg as *const c_void, nr_args) };
+
+        checkerr!(ret.is_negative(), UringError::SysIoUringRegister(ret));
+
+        Ok(())
+    }
+
+    // Register a file table and a fixed buffer with one slot per entry in 'fds'
+    // -1 in 'fds' means the slot is empty and can be filled in later with update_file()
+    // Any previous registration is dropped first
+    pub fn register(&mut self, fds: &[i32]) -> Result<(), UringError> {
+        self.unregister();
+
+        let layout = alloc::Layout::from_size_align(fds.len().max(1) * FIXED_BUF_SIZE, 4096)
+            .map_err(|_| UringError::FixedBufferAlloc)?;
+
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+
+        checkerr!(ptr.is_null(), UringError::FixedBufferAlloc);
+
+        self.fixed_buffers = ptr;
+        self.fixed_layout = Some(layout);
+
+        let iov = iovec {
+            iov_base: ptr as u64,
+            iov_len: layout.size() as u64,
+        };
+
+        // This fails if the buffer is bigger than 'ulimit -l' allows
+        if let Err(err) = self.register_syscall(IORING_REGISTER_BUFFERS, &iov as *const iovec as u64, 1) {
+            self.unregister();
+            return Err(err);
+        }
+
+        if let Err(err) = self.register_syscall(IORING_REGISTER_FILES, fds.as_ptr() as u64, fds.len() as u32) {
+            self.unregister();
+            return Err(err);
+        }
+
+        self.slots = fds.len() as u32;
+
+        Ok(())
+    }
+
+    // Put 'fd' in the registered file table at 'slot'. -1 empties the slot
+    pub fn update_file(&mut self, slot: u32, fd: i32) -> Result<(), UringError> {
+        let update = io_uring_files_update {
+            offset: slot,
+            resv: 0,
+            fds: &fd as *const i32 as u64,
+        };
+
+        self.register_syscall(IORING_REGISTER_FILES_UPDATE, &update as *const io_uring_files_update as u64, 1)
+    }
+
+    // Drop the registered files and buffers
+    pub fn unregister(&mut self) {
+        if self.slots != 0 {
+            let _ = self.register_syscall(IORING_UNREGISTER_FILES, 0, 0);
+            self.slots = 0;
+        }
+
+        if let Some(layout) = self.fixed_layout.take() {
+            let _ = self.register_syscall(IORING_UNREGISTER_BUFFERS, 0, 0);
+
+            unsafe { alloc::dealloc(self.fixed_buffers, layout) };
+
+            self.fixed_buffers = std::ptr::null_mut();
+        }
+    }
+
+    // The fixed buffer belonging to 'slot'. It's aligned to 32 bytes
+    pub fn fixed_buffer(&self, slot: u32, len: usize) -> &[u8] {
+        debug_assert!(slot < self.slots && len <= FIXED_BUF_SIZE);
+
+        unsafe { std::slice::from_raw_parts(self.fixed_buffers.add(slot as usize * FIXED_BUF_SIZE), len) }
+    }
+
     // Read next item from the completion queue
     pub fn read_from_cq(&mut self) -> Result<(i32, u64), UringError> {
         // Load current head
@@ -403,10 +509,47 @@ impl Uring {
 
         // Set the options for our request
         sqe.opcode = op as u8;
+        sqe.flags = 0;
         sqe.fd = fd;
         sqe.addr = buffer.as_mut_ptr() as u64;
         sqe.len = buffer.capacity() as u32;
         sqe.user_data = user_data;
+        sqe.union2[0] = 0;
+
+        // Update array
+        unsafe { *self.sring_array.offset(index as isize) = index};
+
+        // Increase tail and save it
+        tail += 1;
+        unsafe { &*self.sring_tail }.store(tail, Ordering::Release);
+
+        // Add to the number to submit on the next system call
+        self.submit += 1;
+    }
+
+    // Add a IORING_OP_READ_FIXED of the registered file in 'slot' into the fixed buffer of 'slot'
+    // *** THERE ARE NO CHECKS! ***
+    pub fn add_fixed_to_queue(&mut self, user_data: u64, slot: u32) {
+        // Load current tail
+        let mut tail: u32 = unsafe { &*self.sring_tail }.load(Ordering::Acquire);
+
+        // Index of SQE entry
+        let index: u32 = unsafe { tail & (*self.sring_mask) };
+
+        // Get SQE entry
+        let sqe = unsafe { &mut *self.sqes.offset(index as isize) };
+
+        // Set the options for our request
+        // fd is the index into the registered file table
+        sqe.opcode = IOOPS::IORING_OP_READ_FIXED as u8;
+        sqe.flags = IOSQE_FIXED_FILE;
+        sqe.fd = slot as i32;
+        sqe.addr = unsafe { self.fixed_buffers.add(slot as usize * FIXED_BUF_SIZE) } as u64;
+        sqe.len = FIXED_BUF_SIZE as u32;
+        sqe.user_data = user_data;
+
+        // buf_index, there's only one registered buffer
+        sqe.union2[0] = 0;
 
         // Update array
         unsafe { *self.sring_array.offset(index as isize) = index};
@@ -438,20 +581,8 @@ impl Uring {
 
     // Submit all queued operations to the kernel
     pub fn submit_all(&mut self) -> Result<(), UringError> {
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 426, // SYS_IO_URING_ENTER
-                in("rdi") self.ring_fd, // io_uring fd
-                in("rsi") self.submit, // to_submit
-                in("rdx") 0, // min_complete
-                in("r10") 0, // flags
-                in("r8") 0, // sigset_t
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
+        // to_submit, min_complete, flags
+        let ret = unsafe { syscall::io_uring_enter(self.ring_fd, self.submit, 0, 0) };
 
         // If it's negative something has gone horrible wrong
         checkerr!(ret.is_negative(), UringError::SubmitToSqResult(ret));
@@ -470,20 +601,8 @@ impl Uring {
 
     // Wait for all operations to complete
     pub fn wait_for_x(&mut self, amount: u64) -> Result<i32, UringError> {
-        let ret: i32;
-        unsafe {
-            asm!("syscall",
-                in("rax") 426, // SYS_IO_URING_ENTER
-                in("rdi") self.ring_fd, // io_uring fd
-                in("rsi") 0, // to_submit
-                in("rdx") amount, // min_complete
-                in("r10") 1u32 << 0, // flags, 1u32 << 0 == IORING_ENTER_GETEVENTS
-                in("r8") 0, // sigset_t
-                out("rcx") _,
-                out("r11") _,
-                lateout("rax") ret,
-            );
-        }
+        // flags, 1u32 << 0 == IORING_ENTER_GETEVENTS
+        let ret = unsafe { syscall::io_uring_enter(self.ring_fd, 0, amount as u32, 1u32 << 0) };
 
         checkerr!(ret.is_negative(), UringError::SubmitToSqResult(ret));
 
@@ -502,16 +621,11 @@ impl Uring {
 // The memory mmap is freed automatically
 impl Drop for Uring {
     fn drop(&mut self) {
+        // The buffer memory isn't freed automatically
+        self.unregister();
+
         if self.ring_fd > 0 {
-            unsafe {
-                asm!("syscall",
-                    in("rax") 3, // SYS_CLOSE
-                    in("rdi") self.ring_fd,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") _,
-                );
-            }
+            syscall::close(self.ring_fd);
         }
     }
 }
@@ -1,5 +1,7 @@
 #![allow(dead_code)]
-use std::arch::asm;
+use std::ffi::c_void;
+
+use crate::syscall;
 
 
 // ioctl stuff
@@ -86,19 +88,8 @@ macro_rules! check_statics {
 
 // Initialize the statics
 fn init() {
-    // Open tty fd
-    let fd: i32;
-    unsafe {
-        asm!("syscall",
-            in("rax") 2, // SYS_OPEN
-            in("rdi") c"/dev/tty".as_ptr(),
-            in("rsi") 2, // O_RDWR
-            //in("rdx") 0, // This is the mode. It is not used in this case
-            out("rcx") _,
-            out("r11") _,
-            lateout("rax") fd,
-        );
-    }
+    // Open tty fd, 2 == O_RDWR
+    let fd = unsafe { syscall::open(c"/dev/tty".as_ptr(), 2) };
 
     assert!(!fd.is_negative(), "SYS_OPEN returned an error: {}", fd);
 
@@ -108,18 +99,7 @@ fn init() {
     }
 
     // Save original tty settings to TTYTERMIOS
-    let ret: i32;
-    unsafe {
-        asm!("syscall",
-            in("rax") 16, // SYS_IOCTL
-            in("rdi") TTYFD,
-            in("rsi") TCGETS,
-            in("rdx") &raw const TTYTERMIOS,
-            out("rcx") _,
-            out("r11") _,
-            lateout("rax") ret,
-        );
-    }
+    let ret = unsafe { syscall::ioctl(TTYFD, TCGETS, &raw const TTYTERMIOS as *mut c_void) };
 
     assert!(!ret.is_negative(), "SYS_IOCTL TCGETS returned an error: {}", ret);
 }
@@ -142,81 +122,31 @@ pub fn enable_custom_mode() {
     };
 
     // Set tty with our new settings
-    let ret: i32;
-    unsafe {
-        asm!("syscall",
-            in("rax") 16, // SYS_IOCTL
-            in("rdi") TTYFD,
-            in("rsi") TCSETS,
-            in("rdx") &termios as *const Termios,
-            out("rcx") _,
-            out("r11") _,
-            lateout("rax") ret,
-        );
-    }
+    let ret = unsafe { syscall::ioctl(TTYFD, TCSETS, &termios as *const Termios as *mut c_void) };
 
     assert!(!ret.is_negative(), "SYS_IOCTL TCSETS returned an error: {}", ret);
 
     let data = "\x1b[?1049h\x1b[2J\x1b[?25l";
-    unsafe {
-        asm!("syscall",
-            in("rax") 1, // SYS_WRITE
-            in("rdi") 1,
-            in("rsi") data.as_ptr(),
-            in("rdx") data.len(),
-            out("rcx") _,
-            out("r11") _,
-            lateout("rax") _,
-        );
-    }
+    unsafe { syscall::write(1, data.as_ptr(), data.len()) };
 }
 
 // Reset tty settings to original settings and close tty fd
+// Does nothing if enable_custom_mode() wasn't called, like when running headless
 pub fn disable_custom_mode() {
-    let data = "\x1b[2J\x1b[?1049l\x1b[?25h";
-    unsafe {
-        asm!("syscall",
-            in("rax") 1, // SYS_WRITE
-            in("rdi") 1,
-            in("rsi") data.as_ptr(),
-            in("rdx") data.len(),
-            out("rcx") _,
-            out("r11") _,
-            lateout("rax") _,
-        );
-    }
-
     if unsafe { TTYFD } == 0 {
         return;
     }
 
+    let data = "\x1b[2J\x1b[?1049l\x1b[?25h";
+    unsafe { syscall::write(1, data.as_ptr(), data.len()) };
+
     // Set tty settings to our saved original values
-    let ret: i32;
-    unsafe {
-        asm!("syscall",
-            in("rax") 16, // SYS_IOCTL
-            in("rdi") TTYFD,
-            in("rsi") TCSETS,
-            in("rdx") &raw const TTYTERMIOS,
-            out("rcx") _,
-            out("r11") _,
-            lateout("rax") ret,
-        );
-    }
+    let ret = unsafe { syscall::ioctl(TTYFD, TCSETS, &raw const TTYTERMIOS as *mut c_void) };
 
     assert!(!ret.is_negative(), "SYS_IOCTL TCSETS returned an error: {}", ret);
 
     // Close the tty fd
-    let ret: i32;
-    unsafe {
-        asm!("syscall",
-            in("rax") 3, // SYS_CLOSE
-            in("rdi") TTYFD,
-            out("rcx") _,
-            out("r11") _,
-            lateout("rax") ret,
-        );
-    }
+    let ret = syscall::close(unsafe { TTYFD });
 
     assert!(!ret.is_negative(), "SYS_CLOSE returned an error: {}", ret);
 
@@ -229,18 +159,7 @@ pub fn disable_custom_mode() {
 pub fn send_char(c: &str) {
     check_statics!();
 
-    let ret: i32;
-    unsafe {
-        asm!("syscall",
-            in("rax") 16, // SYS_IOCTL
-            in("rdi") TTYFD,
-            in("rsi") TIOCSTI,
-            in("rdx") c.as_ptr(),
-            out("rcx") _,
-            out("r11") _,
-            lateout("rax") ret,
-        );
-    }
+    let ret = unsafe { syscall::ioctl(TTYFD, TIOCSTI, c.as_ptr() as *mut c_void) };
 
     assert!(!ret.is_negative(), "SYS_IOCTL TIOCSTI returned an error: {}", ret);
 }
@@ -251,18 +170,7 @@ pub fn gettermsize() -> (u16, u16) {
 
     let mut winsize = Winsize::default();
 
-    let ret: i32;
-    unsafe {
-        asm!("syscall",
-            in("rax") 16, // SYS_IOCTL
-            in("rdi") TTYFD,
-            in("rsi") TIOCGWINSZ,
-            in("rdx") &mut winsize as *mut Winsize,
-            out("rcx") _,
-            out("r11") _,
-            lateout("rax") ret,
-        );
-    }
+    let ret = unsafe { syscall::ioctl(TTYFD, TIOCGWINSZ, &mut winsize as *mut Winsize as *mut c_void) };
 
     assert!(!ret.is_negative(), "SYS_IOCTL TIOCGWINSZ returned an error: {}", ret);
 
@@ -1,7 +1,7 @@
-use std::arch::asm;
-
 use std::io::Write as ioWrite;
 use std::fmt::Write as fmtWrite;
+use std::sync::Arc;
+use std::path::PathBuf;
 use anyhow::{ ensure, Context, Result };
 
 mod time;
@@ -19,6 +19,9 @@ use loadavg::Loadavg;
 mod overview;
 use overview::Overview;
 
+mod cores;
+use cores::Cores;
+
 mod hostinfo;
 use hostinfo::Hostinfo;
 
@@ -35,6 +38,11 @@ mod gpu;
 use gpu::Gpu;
 
 use crate::terminal;
+use crate::syscall;
+use crate::system::bus::{ Key, Source };
+use crate::system::Health;
+use crate::settings::{ Settings, Keys, Colors };
+use crate::output::alerts::Highlight;
 
 
 // These are for use with the conversion functions
@@ -51,7 +59,7 @@ const DELIMITER_LN: f64 = 6.931_471_805_599_453;
 macro_rules! write_to_stdout {
     ($data:expr) => {
         // Write to stdout
-        let mut ret: i32;
+        let mut ret: isize;
         let mut written = 0;
 
         let d_ptr_orig = $data.as_ptr() as usize;
@@ -60,17 +68,7 @@ macro_rules! write_to_stdout {
         while written < length {
             let d_ptr = (d_ptr_orig + written) as *const u8;
 
-            unsafe {
-                asm!("syscall",
-                    in("rax") 1, // SYS_WRITE
-                    in("rdi") 1,
-                    in("rsi") d_ptr,
-                    in("rdx") length - written,
-                    out("rcx") _,
-                    out("r11") _,
-                    lateout("rax") ret,
-                );
-            }
+            ret = unsafe { syscall::write(1, d_ptr, length - written) };
 
             // Check if there's an error
             ensure!(!ret.is_negative(), "SYS_WRITE return code: {}", ret);
@@ -84,6 +82,12 @@ macro_rules! write_to_stdout {
     };
 }
 
+// A host with --connect
+pub struct Tab {
+    pub name: String,
+    pub connected: bool,
+}
+
 #[derive(Default)]
 pub struct XY {
     pub x: u16,
@@ -92,15 +96,35 @@ pub struct XY {
 
 pub struct Ui <'ui> {
     pub terminal_size: XY,
-    error: Option<anyhow::Error>,
+    error: Option<Arc<anyhow::Error>>,
 
     paused: bool,
 
+    // Keys go to the process filter instead of doing things
+    search: bool,
+
+    // Collectors that are failing or not supported. Their panels show a badge instead
+    unavailable: Vec::<Source>,
+
+    // The config file as it was loaded, the toggles are written back to it
+    settings: Settings,
+    settings_path: Option::<PathBuf>,
+    keys: Keys,
+
+    // Shown next to the clock, with the one at 'tab' highlighted. Empty if set_tabs() isn't used
+    tabs: Vec::<Tab>,
+    tab: usize,
+
+    // Panels with an alert that's firing, from Alerts. 'highlighted' is what's drawn
+    highlight: Option::<Highlight>,
+    highlighted: Vec::<Source>,
+
     buffer: Vec::<u8>,
     system: &'ui super::system::System,
 
     time: Time <'ui>,
     overview: Overview <'ui>,
+    cores: Cores <'ui>,
     memory: Memory <'ui>,
     swap: Swap <'ui>,
     loadavg: Loadavg <'ui>,
@@ -112,28 +136,41 @@ pub struct Ui <'ui> {
 }
 
 impl <'ui> Ui <'ui> {
-    pub fn new(system: &'ui super::system::System, terminal_size: (u16, u16)) -> Result<Self> {
+    pub fn new(system: &'ui super::system::System, terminal_size: (u16, u16), settings: Settings, settings_path: Option::<PathBuf>) -> Result<Self> {
         terminal::enable_custom_mode();
 
         let (tsizex, tsizey) = (terminal_size.0, terminal_size.1);
 
+        // Every panel gets its own copy
+        let colors = settings.colors.clone();
+
         let mut ui = Self {
             paused: false,
+            search: false,
+            unavailable: Vec::new(),
+            keys: settings.keys,
+            tabs: Vec::new(),
+            tab: 0,
+            highlight: None,
+            highlighted: Vec::new(),
+            settings,
+            settings_path,
             buffer: Vec::new(),
             system,
             terminal_size: XY { x: tsizex, y: tsizey },
             error: None,
 
             time: Time::new(system, XY { x: 1, y: tsizey }),
-            overview: Overview::new(system, XY { x: 1, y: 1 }),
-            memory: Memory::new(system, XY { x: 18, y: 1 }),
-            swap: Swap::new(system, XY { x: 38, y: 1 }),
-            loadavg: Loadavg::new(system, XY { x: 58, y: 1 }),
-            hostinfo: Hostinfo::new(system),
-            processes: Processes::new(system, XY { x: 27, y: 6 }),
-            network: Network::new(system, XY { x: 1, y: 6 }),
-            sensors: Sensors::new(system, XY { x: 1, y: 12 }),
-            gpu: Gpu::new(system, XY { x: 1, y: 22 }),
+            overview: Overview::new(system, XY { x: 1, y: 1 }, &colors),
+            cores: Cores::new(system, XY { x: 1, y: 6 }, &colors),
+            memory: Memory::new(system, XY { x: 18, y: 1 }, &colors),
+            swap: Swap::new(system, XY { x: 38, y: 1 }, &colors),
+            loadavg: Loadavg::new(system, XY { x: 58, y: 1 }, &colors),
+            hostinfo: Hostinfo::new(system, &colors),
+            processes: Processes::new(system, XY { x: 27, y: 6 }, &colors),
+            network: Network::new(system, XY { x: 1, y: 6 }, &colors),
+            sensors: Sensors::new(system, XY { x: 1, y: 12 }, &colors),
+            gpu: Gpu::new(system, XY { x: 1, y: 22 }, &colors),
         };
 
         ui.rebuild().context("Error occured while building UI")?;
@@ -141,10 +178,63 @@ impl <'ui> Ui <'ui> {
         Ok(ui)
     }
 
+    // Show another System, like the next host with --connect
+    pub fn switch(&mut self, system: &'ui super::system::System) -> Result<()> {
+        let tsizey = self.terminal_size.y;
+
+        self.system = system;
+        self.unavailable.clear();
+
+        self.time = Time::new(system, XY { x: 1, y: tsizey });
+        let colors = &self.settings.colors;
+        self.overview = Overview::new(system, XY { x: 1, y: 1 }, colors);
+        self.cores = Cores::new(system, XY { x: 1, y: 6 }, colors);
+        self.memory = Memory::new(system, XY { x: 18, y: 1 }, colors);
+        self.swap = Swap::new(system, XY { x: 38, y: 1 }, colors);
+        self.loadavg = Loadavg::new(system, XY { x: 58, y: 1 }, colors);
+        self.hostinfo = Hostinfo::new(system, colors);
+        self.processes = Processes::new(system, XY { x: 27, y: 6 }, colors);
+        self.network = Network::new(system, XY { x: 1, y: 6 }, colors);
+        self.sensors = Sensors::new(system, XY { x: 1, y: 12 }, colors);
+        self.gpu = Gpu::new(system, XY { x: 1, y: 22 }, colors);
+
+        self.rebuild()
+    }
+
+    pub fn set_tabs(&mut self, tabs: Vec::<Tab>, current: usize) -> Result<()> {
+        self.tabs = tabs;
+        self.tab = current;
+
+        self.draw_tabs();
+
+        self.flush()
+    }
+
+    pub fn set_highlight(&mut self, highlight: Highlight) {
+        self.highlight = Some(highlight);
+    }
+
     pub fn toggle_pause(&mut self) {
         self.paused = !self.paused;
     }
 
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn keys(&self) -> Keys {
+        self.keys
+    }
+
+    // True while keys go to the process filter
+    pub fn searching(&self) -> bool {
+        self.search
+    }
+
     pub fn rebuild_cache(&mut self) -> Result<()> {
         self.time.pos.y = self.terminal_size.y;
         self.time.rebuild_cache();
@@ -155,10 +245,16 @@ impl <'ui> Ui <'ui> {
         self.overview.rebuild_cache();
         self.memory.rebuild_cache();
         self.swap.rebuild_cache();
-        self.processes.rebuild_cache(&self.terminal_size);
 
         // The following objects rely on the position of the previous ones
         // So don't do anything silly.
+        let cores = self.settings.panels.cores && !self.system.config().disabled.contains(&Source::Cpu);
+        self.cores.rebuild_cache(&self.terminal_size, cores)?;
+
+        self.processes.pos.y = self.cores.pos.y + self.cores.size.y;
+        self.processes.rebuild_cache(&self.terminal_size);
+
+        self.network.pos.y = self.cores.pos.y + self.cores.size.y;
         self.network.rebuild_cache()?;
 
         self.sensors.pos.y = self.network.size.y + self.network.pos.y;
@@ -170,38 +266,152 @@ impl <'ui> Ui <'ui> {
         Ok(())
     }
 
-    pub fn update (&mut self, item: u8) -> Result<()> {
-        if !self.paused {
-            match item {
+    // Returns true if the user wants to exit
+    pub fn key(&mut self, key: Key) -> Result<bool> {
+        if self.search {
+            if let Ok(mut in_buf_lock) = self.system.inputbuffer().lock() {
+                match key {
+                    // Disable Search
+                    Key::Enter | Key::Escape => {
+                        self.search = false;
+
+                        in_buf_lock.clear();
+                    },
+
+                    // Delete last char if you press backspace
+                    Key::Backspace => {
+                        let _ = in_buf_lock.pop();
+                    },
+
+                    Key::Char(c) => in_buf_lock.push(c),
+                }
+            }
+
+            // Show the new filter
+            self.rebuild()?;
+
+            return Ok(false);
+        }
+
+        let config = self.system.config();
+        let keys = self.keys;
+
+        match key {
+            // Enable Search
+            Key::Char(c) if c == keys.filter => self.search = true,
+
+            // Quit
+            Key::Char(c) if c == keys.quit => return Ok(true),
+
+            // Pause UI
+            Key::Char(c) if c == keys.pause => self.toggle_pause(),
+
+            // Toggle Topmode
+            Key::Char(c) if c == keys.topmode => {
+                config.topmode.fetch_xor(true, std::sync::atomic::Ordering::AcqRel);
+                self.draw_toggles()?;
+            },
+
+            // Toggle smaps
+            Key::Char(c) if c == keys.smaps => {
+                config.smaps.fetch_xor(true, std::sync::atomic::Ordering::AcqRel);
+                self.draw_toggles()?;
+            },
+
+            // Toggle All Processes
+            Key::Char(c) if c == keys.all => {
+                config.all.fetch_xor(true, std::sync::atomic::Ordering::AcqRel);
+                self.draw_toggles()?;
+            },
+
+            // Next sort order
+            Key::Char(c) if c == keys.sort => {
+                let sort = config.sort().next();
+                config.set_sort(sort);
+
+                self.update(Source::Processes)?;
+                self.draw_status(&format!("sort: {}", sort.name()))?;
+            },
+
+            // Sample more or less often
+            Key::Char(c) if c == keys.faster || c == keys.slower => {
+                self.system.scale_intervals(if c == keys.faster { 0.5 } else { 2.0 });
+
+                let frequency = config.frequency.load(std::sync::atomic::Ordering::Relaxed);
+                self.draw_status(&format!("{} ms", frequency))?;
+            },
+
+            // Write the toggles, sort order and intervals to the config file
+            Key::Char(c) if c == keys.save => {
+                let result = match &self.settings_path {
+                    Some(path) => self.settings.save(path, config),
+                    None => Err(anyhow::anyhow!("There's no config path")),
+                };
+
+                // Not being able to save isn't a reason to stop
+                match result {
+                    Ok(()) => self.draw_status("saved")?,
+                    Err(_) => self.write_status("can't save config", true)?,
+                }
+            },
+
+            // Rebuild UI cache
+            Key::Char(c) if c == keys.rebuild => self.rebuild()?,
+
+            _ => (),
+        }
+
+        Ok(false)
+    }
+
+    pub fn update (&mut self, source: Source) -> Result<()> {
+        // An alert fired or was resolved, redraw everything so the titles are right
+        if let Some(highlight) = &self.highlight {
+            let highlighted = highlight.lock().map(|highlight| highlight.clone()).unwrap_or_default();
+
+            if !self.paused && highlighted != self.highlighted {
+                self.highlighted = highlighted;
+
+                return self.rebuild();
+            }
+        }
+
+        if !self.paused && self.available(source)? {
+            match source {
                 // Time
-                1 => {
+                Source::Time => {
                     if self.terminal_size.x > self.time.size.x {
                         self.time.draw(&mut self.buffer);
                     }
                 },
 
                 // Load average
-                2 => {
+                Source::Loadavg => {
                     if self.terminal_size.x > (self.loadavg.size.x + self.loadavg.pos.x) && self.terminal_size.y > self.loadavg.size.y  {
                         self.loadavg.draw(&mut self.buffer)?;
                     }
                 },
 
                 // Overview
-                3 => {
+                Source::Cpu => {
                     if self.terminal_size.x > (self.overview.size.x + self.overview.pos.x) && self.terminal_size.y > (self.overview.size.y + self.overview.pos.y) {
                         self.overview.draw(&mut self.buffer)?;
                     }
 
+                    if self.terminal_size.x > (self.cores.pos.x + self.cores.size.x) && self.terminal_size.y > (self.cores.pos.y + self.cores.size.y)
+                        && self.cores.draw(&mut self.buffer)? {
+                        self.rebuild()?;
+                    }
+
                     if self.terminal_size.x > (self.processes.pos.x + 22) && self.terminal_size.y > (self.processes.pos.y + 3) {
-                        if let Ok(cpuinfo) = self.system.cpuinfo.lock() {
-                            write!(self.buffer, "\x1b[6;41H\x1b[0K\x1b[38;5;244m{}\x1b[0m", &cpuinfo.governor)?;
+                        if let Ok(cpuinfo) = self.system.cpuinfo().lock() {
+                            write!(self.buffer, "\x1b[{};{}H\x1b[0K\x1b[{}m{}\x1b[0m", self.processes.pos.y, self.processes.pos.x + 14, self.settings.colors.dim, cpuinfo.governor)?;
                         }
                     }
                 },
 
                 // Memory
-                4 => {
+                Source::Memory => {
                     if self.terminal_size.x > (self.memory.pos.x + self.memory.size.x) && self.terminal_size.y > (self.memory.pos.y + self.memory.size.y) {
                         self.memory.draw(&mut self.buffer)?;
                     }
@@ -219,7 +429,7 @@ impl <'ui> Ui <'ui> {
                 },*/
 
                 // Sensors
-                6 => {
+                Source::Sensors => {
                     if self.terminal_size.x > self.sensors.size.x && self.terminal_size.y > (self.network.size.y + self.sensors.size.y + self.overview.size.y) {
                         if self.sensors.draw(&mut self.buffer)? {
                             self.rebuild()?;
@@ -228,7 +438,7 @@ impl <'ui> Ui <'ui> {
                 },
 
                 // Network
-                7 => {
+                Source::Network => {
                     if self.terminal_size.x > self.network.size.x  && self.terminal_size.y > (self.network.size.y + self.overview.size.y) {
                         if self.network.draw(&mut self.buffer)? {
                             self.rebuild()?;
@@ -237,7 +447,7 @@ impl <'ui> Ui <'ui> {
                 },
 
                 // Process list
-                8 => {
+                Source::Processes => {
                     //let now = std::time::Instant::now();
                     if self.terminal_size.x > (self.processes.pos.x + 22) && self.terminal_size.y > (self.processes.pos.y + 3) {
                         self.processes.draw(&mut self.buffer, &self.terminal_size)?;
@@ -247,7 +457,7 @@ impl <'ui> Ui <'ui> {
                 },
 
                 // Gpu
-                9 => {
+                Source::Gpu => {
                     if self.terminal_size.x > (self.gpu.pos.x + self.gpu.size.x) && self.terminal_size.y > (self.gpu.pos.y + self.gpu.size.y)  {
                         //let now = std::time::Instant::now();
                         self.gpu.draw(&mut self.buffer)?;
@@ -256,33 +466,155 @@ impl <'ui> Ui <'ui> {
                     }
                 },
 
-                // Topmode
-                10 => {
-                    if self.terminal_size.x > (self.processes.pos.x + 22) && self.terminal_size.y > (self.processes.pos.y + 3) {
-                        self.toggle_topmode();
-                    }
-                },
+                Source::Input => (),
+            }
 
-                // smaps
-                11 => {
-                    if self.terminal_size.x > (self.processes.pos.x + 22) && self.terminal_size.y > (self.processes.pos.y + 3) {
-                        self.toggle_smaps();
-                    }
-                },
+            if self.highlighted.contains(&source) {
+                self.draw_highlight(source);
+            }
 
-                // all_processes
-                12 => {
-                    if self.terminal_size.x > (self.processes.pos.x + 22) && self.terminal_size.y > (self.processes.pos.y + 3) {
-                        self.toggle_all_processes();
-                    }
-                },
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // Write the buffer to stdout
+    fn flush(&mut self) -> Result<()> {
+        write_to_stdout!(self.buffer);
+
+        self.buffer.clear();
 
-                _ => (),
+        Ok(())
+    }
+
+    // Short message next to the toggles. It's replaced by the governor on the next Cpu update
+    pub fn draw_status(&mut self, msg: &str) -> Result<()> {
+        self.write_status(msg, false)
+    }
+
+    // 'failed' draws it in the accent color
+    fn write_status(&mut self, msg: &str, failed: bool) -> Result<()> {
+        if self.terminal_size.x > (self.processes.pos.x + 22) && self.terminal_size.y > (self.processes.pos.y + 3) {
+            let color = if failed { &self.settings.colors.accent } else { &self.settings.colors.dim };
+
+            let _ = write!(self.buffer, "\x1b[{};{}H\x1b[0K\x1b[{}m{}\x1b[0m", self.processes.pos.y, self.processes.pos.x + 14, color, msg);
+        }
+
+        self.flush()
+    }
+
+    // Returns false if the panels of 'source' shouldn't be drawn
+    // Failing and unsupported collectors get a badge instead
+    fn available(&mut self, source: Source) -> Result<bool> {
+        // Turned off in the config file, there's nothing to show
+        if self.system.config().disabled.contains(&source) {
+            return Ok(false);
+        }
+
+        let health = self.system.health(source);
+
+        if !health.is_ok() {
+            if !self.unavailable.contains(&source) {
+                self.unavailable.push(source);
             }
 
-            write_to_stdout!(self.buffer);
+            self.draw_unavailable(source, &health);
+
+            self.flush()?;
+
+            return Ok(false);
+        }
+
+        if let Some(idx) = self.unavailable.iter().position(|s| *s == source) {
+            // It's working again, the panel sizes might have changed so redraw everything
+            self.unavailable.swap_remove(idx);
+
+            self.rebuild()?;
 
-            self.buffer.clear();
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    // Replace the panels belonging to 'source' with 'n/a' or an error badge
+    fn draw_unavailable(&mut self, source: Source, health: &Health) {
+        let Colors { title, accent, dim, .. } = &self.settings.colors;
+
+        let badge = match health {
+            Health::Failed { .. } => format!("\x1b[{accent}m[ error ]\x1b[0m"),
+            _ => format!("\x1b[{dim}mn/a\x1b[0m"),
+        };
+
+        let panels = match source {
+            Source::Time => vec![(&self.time.pos, &self.time.size)],
+            Source::Loadavg => vec![(&self.loadavg.pos, &self.loadavg.size)],
+            Source::Cpu => vec![(&self.overview.pos, &self.overview.size), (&self.cores.pos, &self.cores.size)],
+            Source::Memory => vec![(&self.memory.pos, &self.memory.size), (&self.swap.pos, &self.swap.size)],
+            Source::Sensors => vec![(&self.sensors.pos, &self.sensors.size)],
+            Source::Network => vec![(&self.network.pos, &self.network.size)],
+            Source::Processes => vec![(&self.processes.pos, &self.processes.size)],
+            Source::Gpu => vec![(&self.gpu.pos, &self.gpu.size)],
+            Source::Input => Vec::new(),
+        };
+
+        for (pos, size) in panels {
+            // Hidden, like Cores with one core
+            if size.y == 0 {
+                continue;
+            }
+
+            // Don't draw outside the terminal
+            if pos.x + size.x.max(11) > self.terminal_size.x || pos.y + size.y.max(2) > self.terminal_size.y {
+                continue;
+            }
+
+            // Clear the panel
+            for y in 0..size.y.max(2) {
+                let _ = write!(self.buffer, "\x1b[{};{}H{:width$}", pos.y + y, pos.x, "", width = size.x as usize);
+            }
+
+            let _ = write!(self.buffer,
+                "\x1b[{};{}H\x1b[{title}m{}\x1b[0m\x1b[{};{}H{}",
+                pos.y, pos.x, source.name(),
+                pos.y + 1, pos.x, badge
+            );
+        }
+    }
+
+    // Draw the titles of the panels belonging to 'source' over the ones that were just drawn
+    fn draw_highlight(&mut self, source: Source) {
+        let titles = match source {
+            Source::Loadavg => vec![(&self.loadavg.pos, &self.loadavg.size, "Load")],
+            Source::Cpu => vec![(&self.overview.pos, &self.overview.size, "Overview"), (&self.cores.pos, &self.cores.size, "Cores")],
+            Source::Memory => vec![(&self.memory.pos, &self.memory.size, "Memory"), (&self.swap.pos, &self.swap.size, "Swap")],
+            Source::Sensors => vec![(&self.sensors.pos, &self.sensors.size, "Sensors")],
+            Source::Processes => vec![(&self.processes.pos, &self.processes.size, "Processes")],
+            Source::Gpu => vec![(&self.gpu.pos, &self.gpu.size, "Gpu")],
+            Source::Time | Source::Network | Source::Input => Vec::new(),
+        };
+
+        for (pos, size, title) in titles {
+            // Hidden or outside the terminal
+            if size.y == 0 || pos.x + size.x > self.terminal_size.x || pos.y + size.y > self.terminal_size.y {
+                continue;
+            }
+
+            let _ = write!(self.buffer, "\x1b[{};{}H\x1b[{}m{}\x1b[0m", pos.y, pos.x, self.settings.colors.highlight, title);
+        }
+    }
+
+    // Show which of topmode, smaps and all_processes are enabled
+    fn draw_toggles(&mut self) -> Result<()> {
+        if !self.paused {
+            if self.terminal_size.x > (self.processes.pos.x + 22) && self.terminal_size.y > (self.processes.pos.y + 3) {
+                self.toggle_topmode();
+                self.toggle_smaps();
+                self.toggle_all_processes();
+            }
+
+            self.flush()?;
         }
 
         Ok(())
@@ -298,40 +630,81 @@ impl <'ui> Ui <'ui> {
             self.hostinfo.draw(&mut self.buffer);
         }
 
-        for i in 1..=12 {
-            self.update(i)?;
+        for source in Source::COLLECTORS {
+            self.update(source)?;
         }
 
-        Ok(())
+        self.draw_toggles()?;
+
+        self.draw_tabs();
+
+        self.flush()
+    }
+
+    // Between the clock and the hostinfo. Only the one that's shown if they don't all fit
+    fn draw_tabs(&mut self) {
+        if self.tabs.is_empty() {
+            return;
+        }
+
+        let x = self.time.size.x + 3;
+        let width = self.terminal_size.x.saturating_sub(x + self.hostinfo.size.x + 1) as usize;
+
+        // The brackets and spaces keep every tab where it is when another one is picked
+        let length: usize = self.tabs.iter().map(|tab| tab.name.chars().count() + 2).sum();
+
+        let Colors { title, accent, dim, .. } = &self.settings.colors;
+
+        let _ = write!(self.buffer, "\x1b[{};{}H", self.terminal_size.y, x);
+
+        if length <= width {
+            for (idx, tab) in self.tabs.iter().enumerate() {
+                let color = match (tab.connected, idx == self.tab) {
+                    (false, _) => accent,
+                    (true, true) => title,
+                    (true, false) => dim,
+                };
+
+                let (open, close) = if idx == self.tab { ('[', ']') } else { (' ', ' ') };
+
+                let _ = write!(self.buffer, "\x1b[{}m{}{}{}\x1b[0m", color, open, tab.name, close);
+            }
+        } else if let Some(tab) = self.tabs.get(self.tab) {
+            let text = format!("[{}/{} {}]", self.tab + 1, self.tabs.len(), tab.name);
+
+            if text.chars().count() <= width {
+                let _ = write!(self.buffer, "\x1b[{}m{}\x1b[0m", if tab.connected { title } else { accent }, text);
+            }
+        }
     }
 
     fn toggle_topmode(&mut self) {
-        if self.system.config.topmode.load(std::sync::atomic::Ordering::Relaxed) {
-            let _ = write!(self.buffer, "\x1b[6;37H\x1b[38;5;244mt\x1b[0m");
+        if self.system.config().topmode.load(std::sync::atomic::Ordering::Relaxed) {
+            let _ = write!(self.buffer, "\x1b[{};{}H\x1b[{}mt\x1b[0m", self.processes.pos.y, self.processes.pos.x + 10, self.settings.colors.dim);
         } else {
-            let _ = write!(self.buffer, "\x1b[6;37H ");
+            let _ = write!(self.buffer, "\x1b[{};{}H ", self.processes.pos.y, self.processes.pos.x + 10);
         }
     }
 
     fn toggle_smaps(&mut self) {
-        if self.system.config.smaps.load(std::sync::atomic::Ordering::Relaxed) {
-            let _ = write!(self.buffer, "\x1b[6;38H\x1b[38;5;244ms\x1b[0m");
+        if self.system.config().smaps.load(std::sync::atomic::Ordering::Relaxed) {
+            let _ = write!(self.buffer, "\x1b[{};{}H\x1b[{}ms\x1b[0m", self.processes.pos.y, self.processes.pos.x + 11, self.settings.colors.dim);
         } else {
-            let _ = write!(self.buffer, "\x1b[6;38H ");
+            let _ = write!(self.buffer, "\x1b[{};{}H ", self.processes.pos.y, self.processes.pos.x + 11);
         }
     }
 
     fn toggle_all_processes(&mut self) {
-        if self.system.config.all.load(std::sync::atomic::Ordering::Relaxed) {
-            let _ = write!(self.buffer, "\x1b[6;39H\x1b[38;5;244ma\x1b[0m");
+        if self.system.config().all.load(std::sync::atomic::Ordering::Relaxed) {
+            let _ = write!(self.buffer, "\x1b[{};{}H\x1b[{}ma\x1b[0m", self.processes.pos.y, self.processes.pos.x + 12, self.settings.colors.dim);
         } else {
-            let _ = write!(self.buffer, "\x1b[6;39H ");
+            let _ = write!(self.buffer, "\x1b[{};{}H ", self.processes.pos.y, self.processes.pos.x + 12);
         }
     }
 
     // This is used to print an error *after* resetting the terminal
-    pub fn set_error(&mut self, err: anyhow::Error) {
-        self.error = Some(err);
+    pub fn set_error(&mut self, err: impl Into<Arc<anyhow::Error>>) {
+        self.error = Some(err.into());
     }
 }
 
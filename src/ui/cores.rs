@@ -0,0 +1,618 @@
+use std::io::Write as ioWrite;
+use std::fmt::Write as fmtWrite;
+use anyhow::{ bail, Result };
+use std::ops::Range;
+
+use crate::system::{ System, Kind };
+use crate::settings::Colors;
+use super::XY;
+
+// A bar per core is used while they fit in this many rows, then one character per core
+const MAX_BAR_ROWS: u16 = 4;
+
+// Characters inside the brackets of a bar, without the percentage
+const MIN_BAR: usize = 4;
+const MAX_BAR: usize = 20;
+
+// In the compact grid, cores are in groups of this many with a space between
+const GROUP: usize = 8;
+
+// Eighths of a core, the first one is an idle core
+const BLOCKS: [char; 9] = ['▁', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// A core at or above this is drawn in the accent color
+const PEGGED: f32 = 90.0;
+
+// The stacked bar of where the time went, the legend is dropped from the end if it doesn't fit
+const MIN_STACK: usize = 10;
+const MAX_STACK: usize = 60;
+
+// 'us  6.0' and a space before it, the same letters as top. The ones that matter most go first
+const LEGEND: usize = 8;
+const LEGEND_NAMES: [&str; 8] = ["us", "sy", "wa", "st", "ni", "hi", "si", "gu"];
+
+enum Layout {
+    // '  3 [||||      52%]'
+    Bars { bar: usize },
+
+    // '  0 ▁▂█▁▁▁▁▁ ▁▁▁▁▁▁▁▁', labeled with the first core of the row
+    Compact,
+}
+
+// Cores that are together, like a socket or the efficiency cores
+struct Section {
+    // Like 's1 n2 E', only the parts that aren't the same for every core
+    label: String,
+
+    // In 'Cores.order'
+    cores: Range<usize>,
+}
+
+// Where the time went and the usage of every core in a grid over the whole width, between the top row and the process list
+pub struct Cores <'a> {
+    pub system: &'a System,
+    pub pos: XY,
+    pub size: XY,
+
+    colors: Colors,
+
+    // Amount of cores the layout is for
+    count: usize,
+
+    // Width of the biggest core id
+    idlen: usize,
+
+    layout: Layout,
+
+    // Indexes of the cores in the order they're shown. By socket, NUMA node and kind
+    // with SMT siblings next to each other
+    order: Vec::<usize>,
+    sections: Vec::<Section>,
+
+    // What's in each row, in 'order'. A section always starts a new row
+    rows: Vec::<Range<usize>>,
+
+    // Width of the stacked bar and how many of LEGEND_NAMES fit after it
+    stack: usize,
+    legend: usize,
+
+    // Columns there are for everything
+    width: usize,
+
+    // The frequency row is only there with cpufreq
+    frequencies: bool,
+
+    // Title, the stacked bar, the frequencies, then the start of every row of cores
+    cache: Vec::<String>,
+}
+
+impl <'a> Cores <'a> {
+    pub fn new(system: &'a System, pos: XY, colors: &Colors) -> Self {
+        Self {
+            system,
+            colors: colors.clone(),
+            pos,
+            size: XY { x: 0, y: 0 },
+            count: 0,
+            idlen: 1,
+            layout: Layout::Compact,
+            order: Vec::new(),
+            sections: Vec::new(),
+            rows: Vec::new(),
+            stack: MIN_STACK,
+            legend: 0,
+            width: 0,
+            frequencies: false,
+            cache: Vec::new(),
+        }
+    }
+
+    // Nothing is shown if it's not 'enabled', then the size is 0. With one core there's only the stacked bar
+    pub fn rebuild_cache(&mut self, terminal_size: &XY, enabled: bool) -> Result<()> {
+        let Ok(cpuinfo) = self.system.cpuinfo().lock() else {
+            bail!("cpuinfo lock is poisoned!");
+        };
+
+        let count = cpuinfo.cores.len();
+        self.count = count;
+        self.idlen = cpuinfo.cores.last().map_or(1, |core| core.id.to_string().len());
+        self.frequencies = !cpuinfo.policies.is_empty();
+        self.cache.clear();
+        self.rows.clear();
+
+        let cores = &cpuinfo.cores;
+
+        self.order = (0..count).collect();
+        self.order.sort_by_key(|&idx| {
+            let topology = &cores[idx].topology;
+            (topology.package, topology.node, topology.kind, topology.die, topology.core, cores[idx].id)
+        });
+
+        // Only what's different between the cores goes in the labels. Nodes are left out if there's one per socket
+        let distinct = |key: &dyn Fn(usize) -> (u32, u32)| {
+            let mut keys: Vec::<(u32, u32)> = (0..count).map(key).collect();
+            keys.sort_unstable();
+            keys.dedup();
+            keys.len()
+        };
+
+        let sockets = distinct(&|idx| (cores[idx].topology.package, 0));
+        let packages = sockets > 1;
+        let nodes = distinct(&|idx| (cores[idx].topology.package, cores[idx].topology.node)) > sockets;
+        let hybrid = cores.iter().any(|core| core.topology.kind != Kind::Unknown);
+
+        self.sections.clear();
+
+        for (pos, &idx) in self.order.iter().enumerate() {
+            let topology = &cores[idx].topology;
+            let mut label = Vec::<String>::new();
+
+            if packages {
+                label.push(format!("s{}", topology.package));
+            }
+
+            if nodes {
+                label.push(format!("n{}", topology.node));
+            }
+
+            match topology.kind {
+                _ if !hybrid => (),
+                Kind::Efficiency => label.push(String::from("E")),
+                _ => label.push(String::from("P")),
+            }
+
+            let label = label.join(" ");
+
+            match self.sections.last_mut() {
+                Some(section) if section.label == label => section.cores.end = pos + 1,
+                _ => self.sections.push(Section { label, cores: pos..pos + 1 }),
+            }
+        }
+
+        let ids: Vec::<u32> = cores.iter().map(|core| core.id).collect();
+        drop(cpuinfo);
+
+        // The panels are drawn if they end before the last column
+        let width = terminal_size.x.saturating_sub(self.pos.x + 1) as usize;
+        self.width = width;
+
+        if !enabled {
+            self.size = XY { x: 0, y: 0 };
+
+            return Ok(());
+        }
+
+        // '[' + stack + ']' then the legend
+        self.legend = LEGEND_NAMES.len();
+        while self.legend > 0 && width < MIN_STACK + 2 + self.legend * LEGEND {
+            self.legend -= 1;
+        }
+
+        self.stack = width.saturating_sub(2 + self.legend * LEGEND).clamp(MIN_STACK, MAX_STACK);
+        self.size.x = (self.stack + 2 + self.legend * LEGEND) as u16;
+
+        // With more than one section every row starts with its label, or spaces under it
+        let labeled = self.sections.len() > 1;
+        let labels = match labeled {
+            true => self.sections.iter().map(|section| section.label.len()).max().unwrap_or(0) + 1,
+            false => 0,
+        };
+
+        let sizes: Vec::<usize> = self.sections.iter().map(|section| section.cores.len()).collect();
+        let idlen = self.idlen;
+
+        // '{id} [' + bar + '100%]' and the space before the next one
+        let cell = idlen + 8;
+        let most = ((width + 1).saturating_sub(labels) / (cell + MIN_BAR)).clamp(1, count.max(1));
+
+        // Space before the cores in every row
+        let mut label_width = 0;
+
+        let per_row = if count < 2 {
+            // The cores would just be the same as the stacked bar
+            0
+        } else if rows(&sizes, most) <= MAX_BAR_ROWS as usize {
+            // Just as many in every row, with the bars as long as they can be
+            let columns = balance(&sizes, most, 1);
+            let bar = ((width + 1).saturating_sub(labels) / columns).saturating_sub(cell).clamp(MIN_BAR, MAX_BAR);
+
+            self.layout = Layout::Bars { bar };
+            self.size.x = self.size.x.max((labels + columns * (cell + bar) - 1) as u16);
+            label_width = labels;
+
+            columns
+        } else {
+            // The label is the first core of the row if there's only one section. Every group has a space before it
+            label_width = if labeled { labels - 1 } else { idlen };
+            let groups = (width.saturating_sub(label_width) / (GROUP + 1)).max(1);
+
+            // Just as many in every row, in whole groups
+            let per_row = balance(&sizes, groups * GROUP, GROUP);
+
+            self.layout = Layout::Compact;
+            self.size.x = self.size.x.max((label_width + per_row + per_row / GROUP) as u16);
+
+            per_row
+        };
+
+        // Title, the stacked bar, the frequencies, the rows and a line between it and the panels below
+        let first = 2 + self.frequencies as usize;
+
+        self.cache.push(format!(
+            "\x1b[{};{}H\x1b[{}mCores\x1b[0m\x1b[0K",
+            self.pos.y, self.pos.x, self.colors.title,
+        ));
+
+        for row in 1..first {
+            self.cache.push(format!("\x1b[{};{}H\x1b[0K", self.pos.y + row as u16, self.pos.x));
+        }
+
+        if per_row > 0 {
+            for section in self.sections.iter() {
+                let mut start = section.cores.start;
+
+                while start < section.cores.end {
+                    let end = (start + per_row).min(section.cores.end);
+                    let y = self.pos.y + (first + self.rows.len()) as u16;
+
+                    // The label only goes on the first row of a section
+                    let label = match labeled {
+                        true if start == section.cores.start => section.label.clone(),
+                        true => String::new(),
+                        false if matches!(self.layout, Layout::Compact) => ids[self.order[start]].to_string(),
+                        false => String::new(),
+                    };
+
+                    // Bars don't have a space before the first one
+                    let space = if label_width > 0 && matches!(self.layout, Layout::Bars { .. }) { " " } else { "" };
+
+                    self.cache.push(format!("\x1b[{};{}H\x1b[0K\x1b[{}m{:>width$}{}\x1b[0m",
+                        y, self.pos.x, self.colors.text, label, space,
+                        width = label_width - space.len(),
+                    ));
+                    self.rows.push(start..end);
+
+                    start = end;
+                }
+            }
+        }
+
+        self.size.y = (first + self.rows.len() + 1) as u16;
+
+        Ok(())
+    }
+
+    // Returns true if the amount of cores changed and everything has to be rebuilt
+    pub fn draw(&mut self, buffer: &mut Vec::<u8>) -> Result<bool> {
+        let Ok(cpuinfo) = self.system.cpuinfo().lock() else {
+            bail!("cpuinfo lock is poisoned!");
+        };
+
+        // A CPU was taken offline or brought online
+        if cpuinfo.cores.len() != self.count || cpuinfo.policies.is_empty() == self.frequencies {
+            return Ok(true);
+        }
+
+        if self.cache.is_empty() {
+            return Ok(false);
+        }
+
+        let mut line = String::with_capacity(256);
+        let idlen = self.idlen;
+        let Colors { text, value, accent, dim, breakdown, .. } = &self.colors;
+
+        // In the order of LEGEND_NAMES
+        let b = &cpuinfo.breakdown;
+        let times = [b.user, b.system, b.iowait, b.steal, b.nice, b.irq, b.softirq, b.guest + b.guest_nice];
+
+        // nice, user, system, irq, softirq, steal and guest. iowait goes last since the CPU wasn't actually busy
+        line.push_str(&self.cache[1]);
+        let _ = write!(line, "\x1b[{accent}m[");
+
+        let mut sum = 0.0;
+        let mut filled = 0;
+
+        for idx in [4, 0, 1, 5, 6, 3, 7, 2] {
+            sum += times[idx];
+            let end = ((sum / 100.0 * self.stack as f32).round() as usize).clamp(filled, self.stack);
+
+            let _ = write!(line, "\x1b[{}m{:|<width$}", breakdown[idx], "", width = end - filled);
+            filled = end;
+        }
+
+        let _ = write!(line, "{:width$}\x1b[{accent}m]", "", width = self.stack - filled);
+
+        for (idx, name) in LEGEND_NAMES.iter().take(self.legend).enumerate() {
+            if times[idx] < 99.95 {
+                let _ = write!(line, " \x1b[{}m{} \x1b[{text}m{:4.1}", breakdown[idx], name, times[idx]);
+            } else {
+                let _ = write!(line, " \x1b[{}m{} \x1b[{text}m{:4.0}", breakdown[idx], name, times[idx]);
+            }
+        }
+
+        line.push_str("\x1b[0m");
+        let _ = buffer.write_all(line.as_bytes());
+
+        // The cores come after the frequencies
+        let first = if self.frequencies { 3 } else { 2 };
+
+        if self.frequencies {
+            line.clear();
+            line.push_str(&self.cache[2]);
+
+            // What all the cores can do, so ones that are slower than the others stand out
+            let low = cpuinfo.policies.iter().map(|policy| policy.hardware_min).filter(|&min| min > 0).min().unwrap_or(0);
+            let high = cpuinfo.policies.iter().map(|policy| policy.hardware_max).max().unwrap_or(0).max(low + 1);
+
+            // How many cores are at each frequency, a policy is all of its cores
+            let mut bins = vec![0; self.stack];
+            let mut cores = 0;
+            let mut sum = 0;
+            let mut at_min = 0;
+            let mut capped = 0;
+
+            for policy in cpuinfo.policies.iter().filter(|policy| policy.frequency > 0) {
+                let n = policy.cpus.len();
+                let bin = (policy.frequency.saturating_sub(low) as f64 / (high - low) as f64 * self.stack as f64) as usize;
+
+                bins[bin.min(self.stack - 1)] += n;
+                cores += n;
+                sum += policy.frequency * n as u64;
+
+                if policy.frequency <= policy.min_frequency {
+                    at_min += n;
+                }
+
+                if policy.max_frequency < policy.hardware_max {
+                    capped += n;
+                }
+            }
+
+            let most = bins.iter().copied().max().unwrap_or(0).max(1);
+
+            let _ = write!(line, "\x1b[{accent}m[\x1b[{value}m");
+
+            for count in bins {
+                if count == 0 {
+                    line.push(' ');
+                } else {
+                    line.push(BLOCKS[(count * 8).div_ceil(most)]);
+                }
+            }
+
+            let _ = write!(line, "\x1b[{accent}m]");
+
+            // Every governor and preference once, in the order of the policies
+            let mut governors = Vec::<String>::new();
+
+            for policy in cpuinfo.policies.iter() {
+                let governor = match policy.epp.is_empty() {
+                    true => policy.governor.clone(),
+                    false => format!("{} {}", policy.governor, policy.epp),
+                };
+
+                if !governor.trim().is_empty() && !governors.contains(&governor) {
+                    governors.push(governor);
+                }
+            }
+
+            let mut parts = vec![format!("{:.1}-{:.1} GHz", low as f64 / 1e6, high as f64 / 1e6)];
+
+            if cores > 0 {
+                parts.push(format!("avg {:.2}", sum as f64 / cores as f64 / 1e6));
+            }
+
+            if at_min > 0 {
+                parts.push(format!("at min {}", at_min));
+            }
+
+            if capped > 0 {
+                parts.push(format!("capped {}", capped));
+            }
+
+            parts.push(governors.join(", "));
+
+            // Whatever fits after the histogram
+            let mut left = self.width.saturating_sub(self.stack + 2);
+
+            for part in parts {
+                if part.is_empty() || part.chars().count() + 1 > left {
+                    break;
+                }
+
+                left -= part.chars().count() + 1;
+                let _ = write!(line, " \x1b[{text}m{}", part);
+            }
+
+            line.push_str("\x1b[0m");
+            let _ = buffer.write_all(line.as_bytes());
+        }
+
+        // Average of every section, so one socket or kind of core being busier than the rest stands out
+        line.clear();
+        line.push_str(&self.cache[0]);
+
+        if self.sections.len() > 1 {
+            let mut left = self.width.saturating_sub(5);
+
+            for section in self.sections.iter() {
+                let usage = self.order[section.cores.clone()].iter().map(|&idx| cpuinfo.cores[idx].usage).sum::<f32>() / section.cores.len() as f32;
+                let part = format!(" {} {:.0}%", section.label, usage);
+
+                if part.len() > left {
+                    break;
+                }
+
+                left -= part.len();
+                let _ = write!(line, " \x1b[{dim}m{}\x1b[{text}m{}", section.label, &part[section.label.len() + 1..]);
+            }
+        }
+
+        // There are no numbers in the grid, so the busiest one goes in the title
+        if matches!(self.layout, Layout::Compact) && !self.rows.is_empty() {
+            if let Some(busiest) = cpuinfo.cores.iter().max_by(|a, b| a.usage.total_cmp(&b.usage)) {
+                let _ = write!(line, "\x1b[{dim}m busiest: {} {:.0}%", busiest.id, busiest.usage);
+            }
+        }
+
+        line.push_str("\x1b[0m");
+        let _ = buffer.write_all(line.as_bytes());
+
+        for (row, cores) in self.rows.iter().enumerate() {
+            line.clear();
+            line.push_str(&self.cache[row + first]);
+
+            for (idx, &core) in self.order[cores.clone()].iter().enumerate() {
+                let core = &cpuinfo.cores[core];
+
+                match self.layout {
+                    Layout::Bars { bar } => {
+                        let filled = ((core.usage / 100.0 * bar as f32).round() as usize).min(bar);
+                        let color = if core.usage >= PEGGED { accent } else { value };
+
+                        let _ = write!(line, "{}\x1b[{text}m{:>idlen$} \x1b[{accent}m[\x1b[{}m{:|<filled$}{:empty$}\x1b[{text}m{:>3.0}%\x1b[{accent}m]\x1b[0m",
+                            if idx == 0 { "" } else { " " },
+                            core.id,
+                            color, "", "",
+                            core.usage,
+                            empty = bar - filled,
+                        );
+                    },
+                    Layout::Compact => {
+                        if idx % GROUP == 0 {
+                            line.push(' ');
+                        }
+
+                        let level = ((core.usage / 100.0 * 8.0).round() as usize).min(8);
+
+                        let color = match level {
+                            0 => dim,
+                            _ if core.usage >= PEGGED => accent,
+                            _ => value,
+                        };
+
+                        let _ = write!(line, "\x1b[{}m{}", color, BLOCKS[level]);
+                    },
+                }
+            }
+
+            line.push_str("\x1b[0m");
+            let _ = buffer.write_all(line.as_bytes());
+        }
+
+        Ok(false)
+    }
+}
+
+// Rows it takes with at most 'per_row' cores in each, when every section starts a new one
+fn rows(sections: &[usize], per_row: usize) -> usize {
+    sections.iter().map(|cores| cores.div_ceil(per_row)).sum()
+}
+
+// The fewest cores per row, in steps of 'step', that doesn't take more rows than 'most' per row does
+fn balance(sections: &[usize], most: usize, step: usize) -> usize {
+    let target = rows(sections, most);
+    let mut per_row = most;
+
+    while per_row > step && rows(sections, per_row - step) <= target {
+        per_row -= step;
+    }
+
+    per_row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use megamonic::{ Bus, Config, CoreSnapshot, Snapshot, Source };
+
+    // 'count' cores spread over 'sockets', with the last 'efficiency' of them efficiency cores
+    fn system(count: u32, sockets: u32, efficiency: u32) -> System {
+        let mut snapshot = Snapshot::default();
+
+        for id in 0..count {
+            let mut core = CoreSnapshot::default();
+            core.id = id;
+            core.package = id / (count / sockets);
+            core.core_id = id;
+            core.kind = match (efficiency, id >= count - efficiency) {
+                (0, _) => String::new(),
+                (_, true) => String::from("efficiency"),
+                (_, false) => String::from("performance"),
+            };
+            snapshot.cpu.per_core.push(core);
+        }
+
+        snapshot.cpu.cores = count;
+
+        let system = System::external(Config::default(), Bus::default()).unwrap();
+        system.restore(Source::Cpu, &snapshot);
+
+        system
+    }
+
+    // The cores panel on a terminal 'columns' wide
+    fn cores(system: &System, columns: u16) -> Cores<'_> {
+        let mut cores = Cores::new(system, XY { x: 1, y: 2 }, &Colors::default());
+        cores.rebuild_cache(&XY { x: columns, y: 50 }, true).unwrap();
+
+        cores
+    }
+
+    #[test]
+    fn rows_and_balance() {
+        assert_eq!(rows(&[64], 14), 5);
+        assert_eq!(rows(&[4, 4], 3), 4);
+
+        // 10 cores take two rows with 8 in a row, 5 and 5 is just as many rows
+        assert_eq!(balance(&[10], 8, 1), 5);
+        assert_eq!(balance(&[256], 64, 8), 64);
+        assert_eq!(balance(&[64], 168, 8), 64);
+
+        // Every section starts a new row
+        assert_eq!(balance(&[8, 16], 64, 8), 16);
+    }
+
+    #[test]
+    fn layout() {
+        // A bar per core while they fit in MAX_BAR_ROWS
+        let four = system(4, 1, 0);
+        let panel = cores(&four, 80);
+        assert!(matches!(panel.layout, Layout::Bars { bar: 10 }));
+        assert_eq!((panel.rows.len(), panel.rows[0].clone()), (1, 0..4));
+
+        let panel = cores(&four, 200);
+        assert!(matches!(panel.layout, Layout::Bars { bar: MAX_BAR }));
+
+        // Then one character per core, in whole groups
+        let many = system(64, 1, 0);
+        let panel = cores(&many, 80);
+        assert!(matches!(panel.layout, Layout::Compact));
+        assert_eq!((panel.rows.len(), panel.rows[0].clone()), (1, 0..64));
+
+        let many = system(256, 1, 0);
+        let panel = cores(&many, 80);
+        assert!(matches!(panel.layout, Layout::Compact));
+        assert_eq!(panel.rows, [0..64, 64..128, 128..192, 192..256]);
+        assert!(panel.size.x <= 78);
+    }
+
+    #[test]
+    fn sections() {
+        // Every socket starts a new row with its label
+        let sockets = system(8, 2, 0);
+        let panel = cores(&sockets, 80);
+        let labels: Vec::<&str> = panel.sections.iter().map(|section| section.label.as_str()).collect();
+        assert_eq!(labels, ["s0", "s1"]);
+        assert!(matches!(panel.layout, Layout::Bars { bar: 10 }));
+        assert_eq!(panel.rows, [0..4, 4..8]);
+
+        // Performance cores first, as many in each row as the biggest section needs
+        let hybrid = system(24, 1, 16);
+        let panel = cores(&hybrid, 80);
+        let labels: Vec::<&str> = panel.sections.iter().map(|section| section.label.as_str()).collect();
+        assert_eq!(labels, ["P", "E"]);
+        assert!(matches!(panel.layout, Layout::Compact));
+        assert_eq!(panel.rows, [0..8, 8..24]);
+    }
+}
@@ -3,6 +3,7 @@ use std::fmt::Write as fmtWrite;
 use anyhow::{ bail, Result };
 
 use crate::system::System;
+use crate::settings::Colors;
 use super::XY;
 
 pub struct Gpu <'a> {
@@ -10,6 +11,7 @@ pub struct Gpu <'a> {
     pub pos: XY,
     pub size: XY,
 
+    colors: Colors,
     cache1: String,
     cache2: String,
     cache3: String,
@@ -17,9 +19,10 @@ pub struct Gpu <'a> {
 }
 
 impl <'a> Gpu <'a> {
-    pub fn new(system: &'a System, pos: XY) -> Self {
+    pub fn new(system: &'a System, pos: XY, colors: &Colors) -> Self {
         Self {
             system,
+            colors: colors.clone(),
             cache1: String::new(),
             cache2: String::new(),
             cache3: String::new(),
@@ -30,13 +33,15 @@ impl <'a> Gpu <'a> {
     }
 
     pub fn rebuild_cache(&mut self) {
+        let Colors { title, text, value, accent, .. } = &self.colors;
+
         self.cache1.clear();
         self.cache2.clear();
         self.cache3.clear();
         self.cache4.clear();
 
         let _ = write!(self.cache1,
-        "\x1b[{};{}H\x1b[95mGpu\x1b[0m\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[37mTemp:         \x1b[91m[ \x1b[92m",
+        "\x1b[{};{}H\x1b[{title}mGpu\x1b[0m\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[{text}mTemp:         \x1b[{accent}m[ \x1b[{value}m",
             self.pos.y, self.pos.x,
             self.pos.y + 1, self.pos.x + 24,
             self.pos.y + 2, self.pos.x + 24,
@@ -47,17 +52,17 @@ impl <'a> Gpu <'a> {
 
 
         let _ = write!(self.cache2,
-            " C\x1b[91m ]\x1b[0m\x1b[{};{}H\x1b[37mGpu load:     \x1b[91m[ \x1b[92m",
+            " C\x1b[{accent}m ]\x1b[0m\x1b[{};{}H\x1b[{text}mGpu load:     \x1b[{accent}m[ \x1b[{value}m",
             self.pos.y + 2, self.pos.x,
         );
 
         let _ = write!(self.cache3,
-            "%\x1b[91m ]\x1b[0m\x1b[{};{}H\x1b[37mMem load:     \x1b[91m[ \x1b[92m",
+            "%\x1b[{accent}m ]\x1b[0m\x1b[{};{}H\x1b[{text}mMem load:     \x1b[{accent}m[ \x1b[{value}m",
             self.pos.y + 3, self.pos.x,
         );
 
         let _ = write!(self.cache4,
-            "%\x1b[91m ]\x1b[0m\x1b[{};{}H\x1b[37mMem use:      \x1b[91m[ \x1b[92m",
+            "%\x1b[{accent}m ]\x1b[0m\x1b[{};{}H\x1b[{text}mMem use:      \x1b[{accent}m[ \x1b[{value}m",
             self.pos.y + 4, self.pos.x,
         );
 
@@ -66,13 +71,15 @@ impl <'a> Gpu <'a> {
 
     // 2550 -> 2050
     pub fn draw(&mut self, buffer: &mut Vec::<u8>) -> Result<()> {
-        if let Ok(val) = self.system.gpuinfo.lock() {
+        let accent = &self.colors.accent;
+
+        if let Ok(val) = self.system.gpuinfo().lock() {
             // If temperature is 0 it probably means that GPU info couldn't be gathered, so don't print anything.
             if val.temp != 0 {
                 if val.mem_used < 100.0 {
-                    write!(buffer, "{}{:>3}{}{:>4}{}{:>4}{}{:>4.1}%\x1b[91m ]\x1b[0m", &self.cache1, val.temp, &self.cache2, val.gpu_load, &self.cache3, val.mem_load, &self.cache4, val.mem_used)?;
+                    write!(buffer, "{}{:>3}{}{:>4}{}{:>4}{}{:>4.1}%\x1b[{accent}m ]\x1b[0m", &self.cache1, val.temp, &self.cache2, val.gpu_load, &self.cache3, val.mem_load, &self.cache4, val.mem_used)?;
                 } else {
-                    write!(buffer, "{}{:>3}{}{:>4}{}{:>4}{}{:>4.0}%\x1b[91m ]\x1b[0m", &self.cache1, val.temp, &self.cache2, val.gpu_load, &self.cache3, val.mem_load, &self.cache4, val.mem_used)?;
+                    write!(buffer, "{}{:>3}{}{:>4}{}{:>4}{}{:>4.0}%\x1b[{accent}m ]\x1b[0m", &self.cache1, val.temp, &self.cache2, val.gpu_load, &self.cache3, val.mem_load, &self.cache4, val.mem_used)?;
                 }
             }
         } else {
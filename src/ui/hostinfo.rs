@@ -2,40 +2,44 @@ use std::io::Write as ioWrite;
 //use std::fmt::Write as fmtWrite;
 
 use crate::system::System;
+use crate::settings::Colors;
 use super::XY;
 
 pub struct Hostinfo <'a> {
     pub system: &'a System,
     pub size: XY,
 
+    colors: Colors,
     cache: String,
 }
 
 impl <'a> Hostinfo <'a> {
-    pub fn new(system: &'a System) -> Self {
-
+    pub fn new(system: &'a System, colors: &Colors) -> Self {
         Self {
             system,
+            colors: colors.clone(),
             cache: String::new(),
             size: XY { x: 0, y: 1 }
         }
     }
 
     pub fn rebuild_cache (&mut self, terminal_size: &XY) {
-        let dist_len = self.system.hostinfo.distname.len();
-        let kern_len = self.system.hostinfo.kernel.len();
+        let dist_len = self.system.hostinfo().distname.len();
+        let kern_len = self.system.hostinfo().kernel.len();
 
         // +9 is the static parts
         self.size.x = dist_len as u16 + kern_len as u16 + 9;
 
+        let accent = &self.colors.accent;
+
         self.cache.clear();
         self.cache.push_str(&format!(
-                "\x1b[{};{}H\x1b[0K\x1b[{};{}H\x1b[91m[ {}{}\x1b[91m ] [ \x1b[0m{}\x1b[91m ]\x1b[0m",
+                "\x1b[{};{}H\x1b[0K\x1b[{};{}H\x1b[{accent}m[ {}{}\x1b[{accent}m ] [ \x1b[0m{}\x1b[{accent}m ]\x1b[0m",
                 terminal_size.y, terminal_size.x.saturating_sub(self.size.x),
                 terminal_size.y, terminal_size.x.saturating_sub(self.size.x),
-                self.system.hostinfo.ansi_color,
-                self.system.hostinfo.distname,
-                self.system.hostinfo.kernel
+                self.system.hostinfo().ansi_color,
+                self.system.hostinfo().distname,
+                self.system.hostinfo().kernel
             )
         );
     }
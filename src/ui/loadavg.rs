@@ -3,6 +3,7 @@ use std::fmt::Write as fmtWrite;
 use anyhow::{ bail, Result };
 
 use crate::system::System;
+use crate::settings::Colors;
 use super::XY;
 
 pub struct Loadavg <'a> {
@@ -10,42 +11,49 @@ pub struct Loadavg <'a> {
     pub pos: XY,
     pub size: XY,
 
-    cache: (String, String, String),
+    colors: Colors,
+    cache: (String, String, String, String),
 }
 
 impl <'a> Loadavg <'a> {
-    pub fn new(system: &'a System, pos: XY) -> Self {
+    pub fn new(system: &'a System, pos: XY, colors: &Colors) -> Self {
         Self {
             system,
-            cache: (String::new(), String::new(), String::new()),
+            colors: colors.clone(),
+            cache: (String::new(), String::new(), String::new(), String::new()),
             pos,
             size: XY { x: 16, y: 4 }
         }
     }
 
     pub fn rebuild_cache (&mut self) {
+        let Colors { title, text, value, accent, .. } = &self.colors;
+
         self.cache.0.clear();
         let _ = write!(self.cache.0,
-            "\x1b[{};{}H\x1b[95mLoad\x1b[0m\x1b[{};{}H\x1b[0K\x1b[37m1 min:  \x1b[91m[ \x1b[92m",
+            "\x1b[{};{}H\x1b[{title}mLoad\x1b[0m\x1b[{};{}H\x1b[0K\x1b[{text}m1 min:  \x1b[{accent}m[ \x1b[{value}m",
             self.pos.y, self.pos.x,
             self.pos.y+1, self.pos.x,
         );
 
         self.cache.1.clear();
         let _ = write!(self.cache.1,
-            "\x1b[91m ]\x1b[0m\x1b[{};{}H\x1b[0K\x1b[37m5 min:  \x1b[91m[ \x1b[92m",
+            "\x1b[{accent}m ]\x1b[0m\x1b[{};{}H\x1b[0K\x1b[{text}m5 min:  \x1b[{accent}m[ \x1b[{value}m",
             self.pos.y+2, self.pos.x,
         );
 
         self.cache.2.clear();
         let _ = write!(self.cache.2,
-            "\x1b[91m ]\x1b[0m\x1b[{};{}H\x1b[0K\x1b[37m15 min: \x1b[91m[ \x1b[92m",
+            "\x1b[{accent}m ]\x1b[0m\x1b[{};{}H\x1b[0K\x1b[{text}m15 min: \x1b[{accent}m[ \x1b[{value}m",
             self.pos.y+3, self.pos.x,
         );
+
+        self.cache.3.clear();
+        let _ = write!(self.cache.3, "\x1b[{accent}m ]\x1b[0m");
     }
 
     pub fn draw (&mut self, buffer: &mut Vec::<u8>) -> Result<()> {
-        if let Ok(loadavg) = self.system.loadavg.lock() {
+        if let Ok(loadavg) = self.system.loadavginfo().lock() {
             let len = loadavg.min1.len().max(loadavg.min5.len().max(loadavg.min15.len()));
             self.size.x = len as u16 + 12;
 
@@ -58,16 +66,17 @@ impl <'a> Loadavg <'a> {
                     std::io::IoSlice::new(loadavg.min5.as_bytes()),
                     std::io::IoSlice::new(self.cache.2.as_bytes()),
                     std::io::IoSlice::new(loadavg.min15.as_bytes()),
-                    std::io::IoSlice::new(b"\x1b[91m ]\x1b[0m")
+                    std::io::IoSlice::new(self.cache.3.as_bytes())
                 ]);
             } else {
-                write!(buffer, "{}{:>pad$}{}{:>pad$}{}{:>pad$}\x1b[91m ]\x1b[0m",
+                write!(buffer, "{}{:>pad$}{}{:>pad$}{}{:>pad$}{}",
                     &self.cache.0,
                     &loadavg.min1,
                     &self.cache.1,
                     &loadavg.min5,
                     &self.cache.2,
                     &loadavg.min15,
+                    &self.cache.3,
                     pad=len
                 )?;
             }
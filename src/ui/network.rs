@@ -1,9 +1,10 @@
 use std::io::Write as ioWrite;
 use std::fmt::Write as fmtWrite;
 use anyhow::{ bail, Result};
-use std::sync::atomic;
+use std::time::Duration;
 
 use crate::system::System;
+use crate::settings::Colors;
 use super::{ DELIMITER_LN, DELIMITER, UNITS, XY };
 
 pub struct Network <'a> {
@@ -11,14 +12,20 @@ pub struct Network <'a> {
     pub pos: XY,
     pub size: XY,
 
+    colors: Colors,
     cache: Vec::<(String, String, String, String)>,
+
+    // What goes after the speeds
+    rx: String,
+    tx: String,
+
     buffer_speed1: String,
     buffer_speed2: String
 }
 
 impl <'a> Network <'a> {
-    pub fn new(system: &'a System, pos: XY) -> Self {
-        let y = if let Ok(networkinfo) = system.networkinfo.lock() {
+    pub fn new(system: &'a System, pos: XY, colors: &Colors) -> Self {
+        let y = if let Ok(networkinfo) = system.networkinfo().lock() {
             networkinfo.stats.len() as u16 * 2 + 2
         } else {
             2
@@ -26,7 +33,10 @@ impl <'a> Network <'a> {
 
         Self {
             system,
+            colors: colors.clone(),
             cache: Vec::new(),
+            rx: String::new(),
+            tx: String::new(),
             buffer_speed1: String::new(),
             buffer_speed2: String::new(),
             pos,
@@ -35,7 +45,12 @@ impl <'a> Network <'a> {
     }
 
     pub fn rebuild_cache (&mut self) -> Result<()> {
-        if let Ok(networkinfo) = self.system.networkinfo.lock() {
+        let Colors { title, text, value, accent, dim, .. } = &self.colors;
+
+        self.rx = format!("\x1b[{text}m Rx\x1b[0m");
+        self.tx = format!("\x1b[{text}m Tx\x1b[0m");
+
+        if let Ok(networkinfo) = self.system.networkinfo().lock() {
             self.size.y = networkinfo.stats.len() as u16 * 2 + 2;
             let mut count: u16 = 0;
             self.cache.clear();
@@ -46,26 +61,26 @@ impl <'a> Network <'a> {
                     self.cache.push(
                         (
                             format!(
-                                "\x1b[{};{}H\x1b[95mNetwork\x1b[0m\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[37m{:<8}\x1b[91m[ \x1b[92m",
+                                "\x1b[{};{}H\x1b[{title}mNetwork\x1b[0m\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[{text}m{:<8}\x1b[{accent}m[ \x1b[{value}m",
                                 self.pos.y, self.pos.x,
                                 y, self.pos.x + 25,
                                 y, self.pos.x,
                                 key
                             ),
                             format!(
-                                "\x1b[{};{}H\x1b[95mNetwork\x1b[0m\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[37m{:<8}\x1b[38;5;244m[ \x1b[37m",
+                                "\x1b[{};{}H\x1b[{title}mNetwork\x1b[0m\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[{text}m{:<8}\x1b[{dim}m[ \x1b[{text}m",
                                 self.pos.y, self.pos.x,
                                 y, self.pos.x + 25,
                                 y, self.pos.x,
                                 key
                             ),
                             format!(
-                                "\x1b[{};{}H\x1b[91m{:>10}\x1b[92m",
+                                "\x1b[{};{}H\x1b[{accent}m{:>10}\x1b[{value}m",
                                 y + 1, self.pos.x,
                                 "[ ",
                             ),
                             format!(
-                                "\x1b[{};{}H\x1b[38;5;244m{:>10}\x1b[37m",
+                                "\x1b[{};{}H\x1b[{dim}m{:>10}\x1b[{text}m",
                                 y + 1, self.pos.x,
                                 "[ "
                             )
@@ -75,7 +90,7 @@ impl <'a> Network <'a> {
                     self.cache.push(
                         (
                             format!(
-                                "\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[37m{:<8}\x1b[91m[ \x1b[92m",
+                                "\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[{text}m{:<8}\x1b[{accent}m[ \x1b[{value}m",
                                 y, self.pos.x + 25,
                                 y, self.pos.x,
                                 /*cursor::MoveTo(self.pos.x+25, self.pos.y + 1 + count ),
@@ -83,7 +98,7 @@ impl <'a> Network <'a> {
                                 key
                             ),
                             format!(
-                                "\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[37m{:<8}\x1b[38;5;244m[ \x1b[37m",
+                                "\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[{text}m{:<8}\x1b[{dim}m[ \x1b[{text}m",
                                 y, self.pos.x + 25,
                                 y, self.pos.x,
                                 /*cursor::MoveTo(self.pos.x+25, self.pos.y + 1 + count ),
@@ -91,13 +106,13 @@ impl <'a> Network <'a> {
                                 key
                             ),
                             format!(
-                                "\x1b[{};{}H\x1b[91m{:>10}\x1b[92m",
+                                "\x1b[{};{}H\x1b[{accent}m{:>10}\x1b[{value}m",
                                 y + 1, self.pos.x,
                                 //cursor::MoveTo(self.pos.x, self.pos.y + 2 + count ),
                                 "[ ",
                             ),
                             format!(
-                                "\x1b[{};{}H\x1b[38;5;244m{:>10}\x1b[37m",
+                                "\x1b[{};{}H\x1b[{dim}m{:>10}\x1b[{text}m",
                                 y + 1, self.pos.x,
                                 //cursor::MoveTo(self.pos.x, self.pos.y + 2 + count ),
                                 "[ "
@@ -117,16 +132,14 @@ impl <'a> Network <'a> {
     }
 
     pub fn draw (&mut self, buffer: &mut Vec::<u8>) -> Result<bool> {
-        if let Ok(networkinfo) = self.system.networkinfo.lock() {
+        if let Ok(networkinfo) = self.system.networkinfo().lock() {
             // Trigger cache rebuild if lengths don't match
             if self.cache.len() != networkinfo.stats.len() {
                 return Ok(true);
             }
 
-            let freq = self.system.config.frequency.load(atomic::Ordering::Relaxed);
-
             for (count, val) in networkinfo.stats.values().enumerate() {
-                convert_speed(&mut self.buffer_speed1, val.recv, freq);
+                convert_speed(&mut self.buffer_speed1, val.recv, networkinfo.elapsed, &self.colors);
 
                 let cache1 = if val.recv != 0 {
                     unsafe { self.cache.get_unchecked(count).0.as_bytes() }
@@ -134,7 +147,7 @@ impl <'a> Network <'a> {
                     unsafe { self.cache.get_unchecked(count).1.as_bytes() }
                 };
 
-                convert_speed(&mut self.buffer_speed2, val.sent, freq);
+                convert_speed(&mut self.buffer_speed2, val.sent, networkinfo.elapsed, &self.colors);
 
                 let cache2 = if val.sent != 0 {
                     unsafe { self.cache.get_unchecked(count).2.as_bytes() }
@@ -145,10 +158,10 @@ impl <'a> Network <'a> {
                 let _ = buffer.write_vectored(&[
                     std::io::IoSlice::new(cache1),
                     std::io::IoSlice::new(self.buffer_speed1.as_bytes()),
-                    std::io::IoSlice::new(b"\x1b[37m Rx\x1b[0m"),
+                    std::io::IoSlice::new(self.rx.as_bytes()),
                     std::io::IoSlice::new(cache2),
                     std::io::IoSlice::new(self.buffer_speed2.as_bytes()),
-                    std::io::IoSlice::new(b"\x1b[37m Tx\x1b[0m")
+                    std::io::IoSlice::new(self.tx.as_bytes())
                 ]);
             }
         } else {
@@ -162,28 +175,63 @@ impl <'a> Network <'a> {
 // Taken from https://github.com/banyan/rust-pretty-bytes/blob/master/src/converter.rs
 // And customized for my use
 // Convert function for network with special handling
-fn convert_speed(buffer: &mut String, num: u64, freq: u64) {
+// 'num' is bytes over 'elapsed', it's shown per second
+fn convert_speed(buffer: &mut String, num: u64, elapsed: Duration, colors: &Colors) {
+    let Colors { accent, dim, .. } = colors;
+
     buffer.clear();
 
-    if num != 0 {
+    // There's no rate before the second update
+    if num != 0 && !elapsed.is_zero() {
         // convert it to a f64 type to we can use ln() and stuff on it.
-        let num = num as f64 / (freq as f64 / 1000.0);
+        let num = num as f64 / elapsed.as_secs_f64();
 
         // Magic that makes no sense to me
-        let exponent = (num.ln() / DELIMITER_LN).floor() as i32;
+        // Less than 1 b/s is possible with long intervals, it's still shown in b/s
+        let exponent = (num.ln() / DELIMITER_LN).floor().max(0.0) as i32;
         let pretty_bytes = num / DELIMITER.powi(exponent);
 
         // Different behaviour for different units 7
         match exponent {
-            0 => { let _ = write!(buffer, "{:>5.0} b/s\x1b[91m ]", pretty_bytes); },
-            1 => { let _ = write!(buffer, "{:>4.0} Kb/s\x1b[91m ]", pretty_bytes); },
-            2 => { let _ = write!(buffer, "{:>4.0} Mb/s\x1b[91m ]", pretty_bytes); },
+            0 => { let _ = write!(buffer, "{:>5.0} b/s\x1b[{accent}m ]", pretty_bytes); },
+            1 => { let _ = write!(buffer, "{:>4.0} Kb/s\x1b[{accent}m ]", pretty_bytes); },
+            2 => { let _ = write!(buffer, "{:>4.0} Mb/s\x1b[{accent}m ]", pretty_bytes); },
             _ => {
                 let unit = UNITS[exponent as usize];
-                let _ = write!(buffer, "{:>4.1} {}/s\x1b[91m ]", pretty_bytes, unit);
+                let _ = write!(buffer, "{:>4.1} {}/s\x1b[{accent}m ]", pretty_bytes, unit);
             },
         }
     } else {
-        let _ = write!(buffer, "{:>5.0} b/s\x1b[38;5;244m ]", num);
+        let _ = write!(buffer, "{:>5} b/s\x1b[{dim}m ]", 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_is_per_second() {
+        let mut buffer = String::new();
+        let colors = Colors::default();
+
+        // 2 Kb over half a second and 8 Kb over 2 seconds are both 4 Kb/s
+        convert_speed(&mut buffer, 2048, Duration::from_millis(500), &colors);
+        assert!(buffer.starts_with("   4 Kb/s"), "{}", buffer);
+
+        convert_speed(&mut buffer, 8192, Duration::from_secs(2), &colors);
+        assert!(buffer.starts_with("   4 Kb/s"), "{}", buffer);
+
+        convert_speed(&mut buffer, 1, Duration::from_secs(60), &colors);
+        assert!(buffer.starts_with("    0 b/s"), "{}", buffer);
+
+        // Nothing to compare to yet
+        convert_speed(&mut buffer, 2048, Duration::ZERO, &colors);
+        assert!(buffer.starts_with("    0 b/s"), "{}", buffer);
+
+        // The configured colors are used as they are
+        let colors = Colors { accent: String::from("38;5;208"), ..Colors::default() };
+        convert_speed(&mut buffer, 2048, Duration::from_secs(1), &colors);
+        assert!(buffer.ends_with("\x1b[38;5;208m ]"), "{:?}", buffer);
     }
 }
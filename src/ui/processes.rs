@@ -6,6 +6,7 @@ use ahash::AHashMap;
 
 use crate::system::System;
 use crate::system::processes::process;
+use crate::settings::Colors;
 
 use super::{ DELIMITER_LN, DELIMITER, UNITS, XY };
 
@@ -14,6 +15,7 @@ pub struct Processes <'a> {
     pub pos: XY,
     pub size: XY,
 
+    colors: Colors,
     pidlen: usize,
     cache1: Vec::<String>,
     cache2: AHashMap<u32, String>,
@@ -22,9 +24,10 @@ pub struct Processes <'a> {
 }
 
 impl <'a> Processes <'a> {
-    pub fn new(system: &'a System, pos: XY) -> Self {
+    pub fn new(system: &'a System, pos: XY, colors: &Colors) -> Self {
         Self {
             system,
+            colors: colors.clone(),
             cache1: Vec::<String>::new(),
             cache2: AHashMap::new(),
             cpu_buffer: String::new(),
@@ -41,8 +44,8 @@ impl <'a> Processes <'a> {
         self.size.y = terminal_size.y.saturating_sub(self.pos.y).saturating_sub(2);
 
         self.cache1.push(format!(
-            "\x1b[{};{}H\x1b[95mProcesses\x1b[0m\x1b[{};{}H\x1b[0K\x1b[{};{}H",
-            self.pos.y, self.pos.x,
+            "\x1b[{};{}H\x1b[{}mProcesses\x1b[0m\x1b[{};{}H\x1b[0K\x1b[{};{}H",
+            self.pos.y, self.pos.x, self.colors.title,
             self.pos.y + 1, self.pos.x,
             self.pos.y + 1, self.pos.x,
         ));
@@ -57,15 +60,16 @@ impl <'a> Processes <'a> {
     }
 
     pub fn draw(&mut self, buffer: &mut Vec::<u8>, terminal_size: &XY) -> Result<()> {
-        let smaps = self.system.config.smaps.load(atomic::Ordering::Relaxed);
+        let smaps = self.system.config().smaps.load(atomic::Ordering::Relaxed);
+        let Colors { text, value, accent, dim, pss, .. } = &self.colors;
 
-        if let Ok(mut processinfo) = self.system.processinfo.lock() {
+        if let Ok(mut processinfo) = self.system.processinfo().lock() {
             // Remove processes from the cache that don't exist anymore
             self.cache2.retain(|k, _| processinfo.processes.contains_key(k) );
 
-            let (pidlen, list) = if let Ok(in_buf_lock) = self.system.inputbuffer.lock() {
+            let (pidlen, list) = if let Ok(in_buf_lock) = self.system.inputbuffer().lock() {
                 if in_buf_lock.is_empty() {
-                    processinfo.cpu_sort()
+                    processinfo.sort()
                 } else {
                     processinfo.name_filter(in_buf_lock.as_str())
                 }
@@ -104,19 +108,19 @@ impl <'a> Processes <'a> {
                     // Check if there actually is a PSS value
                     // If there isn't it probably requires root access, use RSS instead
                     if smaps & (val.pss != -1) {
-                        convert_with_padding_proc(&mut self.memory_buffer, val.pss, "\x1b[94m");
+                        convert_with_padding_proc(&mut self.memory_buffer, val.pss, pss);
                     } else {
-                        convert_with_padding_proc(&mut self.memory_buffer, val.rss, "\x1b[92m");
+                        convert_with_padding_proc(&mut self.memory_buffer, val.rss, value);
                     }
 
                     // This is needed because of rounding errors. There's probably a better way
                     self.cpu_buffer.clear();
                     if val.cpu_avg > 0.0 && val.cpu_avg < 99.5 {
-                        let _ = write!(self.cpu_buffer, "\x1b[91m[ \x1b[92m{:>4.1}%\x1b[91m ] \x1b[0m\x1b[91m[ ", val.cpu_avg);
+                        let _ = write!(self.cpu_buffer, "\x1b[{accent}m[ \x1b[{value}m{:>4.1}%\x1b[{accent}m ] \x1b[0m\x1b[{accent}m[ ", val.cpu_avg);
                     } else if val.cpu_avg >= 99.5 {
-                        let _ = write!(self.cpu_buffer, "\x1b[91m[ \x1b[92m{:>4.0}%\x1b[91m ] \x1b[0m\x1b[91m[ ", val.cpu_avg);
+                        let _ = write!(self.cpu_buffer, "\x1b[{accent}m[ \x1b[{value}m{:>4.0}%\x1b[{accent}m ] \x1b[0m\x1b[{accent}m[ ", val.cpu_avg);
                     } else {
-                        self.cpu_buffer.push_str("\x1b[38;5;244m[ \x1b[37m 0.0%\x1b[38;5;244m ] \x1b[0m\x1b[91m[ ");
+                        let _ = write!(self.cpu_buffer, "\x1b[{dim}m[ \x1b[{text}m 0.0%\x1b[{dim}m ] \x1b[0m\x1b[{accent}m[ ");
                     }
 
                     let _ = buffer.write_vectored(&[
@@ -133,7 +137,8 @@ impl <'a> Processes <'a> {
                                     val.not_executable,
                                     val.pid,
                                     pidlen,
-                                    max_length
+                                    max_length,
+                                    &self.colors
                                 )
                             ).as_bytes()
                         )
@@ -153,39 +158,42 @@ impl <'a> Processes <'a> {
     }
 }
 
-fn maxstr(exec: &str, cmd: &str, is_not_exec: bool, pid: u32, pidlen: usize, maxlen: usize) -> String {
+fn maxstr(exec: &str, cmd: &str, is_not_exec: bool, pid: u32, pidlen: usize, maxlen: usize, colors: &Colors) -> String {
     let mut e = exec.to_string();
     let mut c = cmd.to_string();
 
+    let Colors { text, accent, dim, .. } = colors;
+
     let color = if is_not_exec {
-        "\x1b[94m"
+        &colors.pss
     } else {
-        "\x1b[92m"
+        &colors.value
     };
 
     let mut p = format!("{:>pad$} ", pid, pad=pidlen);
 
     if (p.len() + 4) > maxlen {
         p.truncate(maxlen.saturating_sub(4));
-        return format!("\x1b[91m ] \x1b[37m{}\x1b[0m", p);
+        return format!("\x1b[{accent}m ] \x1b[{text}m{}\x1b[0m", p);
     }
 
     if (e.len() + p.len() + 4) > maxlen {
         e.truncate(maxlen.saturating_sub(p.len() + 4));
-        return format!("\x1b[91m ] \x1b[37m{}\x1b[0m{}{}\x1b[0m", p, color, e);
+        return format!("\x1b[{accent}m ] \x1b[{text}m{}\x1b[0m\x1b[{}m{}\x1b[0m", p, color, e);
 
     }
 
     if (c.len() + e.len() + p.len() + 4) > maxlen {
         c.truncate(maxlen.saturating_sub(e.len() + p.len() + 4));
-        return format!("\x1b[91m ] \x1b[37m{}\x1b[0m{}{}\x1b[38;5;244m {}\x1b[0m", p, color, e, c);
+        return format!("\x1b[{accent}m ] \x1b[{text}m{}\x1b[0m\x1b[{}m{}\x1b[{dim}m {}\x1b[0m", p, color, e, c);
     }
 
-    format!("\x1b[91m ] \x1b[37m{}\x1b[0m{}{}\x1b[38;5;244m {}\x1b[0m", p, color, e, c)
+    format!("\x1b[{accent}m ] \x1b[{text}m{}\x1b[0m\x1b[{}m{}\x1b[{dim}m {}\x1b[0m", p, color, e, c)
 }
 
 // Taken from https://github.com/banyan/rust-pretty-bytes/blob/master/src/converter.rs
 // And customized for my use
+// 'color' is the SGR parameters the size is written in
 #[inline(always)]
 fn convert_with_padding_proc(buffer: &mut String, num: i64, color: &str) {
     buffer.clear();
@@ -202,18 +210,18 @@ fn convert_with_padding_proc(buffer: &mut String, num: i64, color: &str) {
         // Different behaviour for different units
         // They are in order of most commonly used
         match exponent {
-            2 => { let _ = write!(buffer, "{}{:>4.0} Mb", color, pretty_bytes); },
+            2 => { let _ = write!(buffer, "\x1b[{}m{:>4.0} Mb", color, pretty_bytes); },
             3 => {
-                if pretty_bytes >= 10.0 { let _ = write!(buffer, "{}{:>4.1} Gb", color, pretty_bytes); }
-                else { let _ = write!(buffer, "{}{:>4.2} Gb", color, pretty_bytes); }
+                if pretty_bytes >= 10.0 { let _ = write!(buffer, "\x1b[{}m{:>4.1} Gb", color, pretty_bytes); }
+                else { let _ = write!(buffer, "\x1b[{}m{:>4.2} Gb", color, pretty_bytes); }
             },
-            1 => { let _ = write!(buffer, "{}{:>4.0} Kb", color, pretty_bytes); },
-            0 => { let _ = write!(buffer, "{}{:>5.0} b", color, pretty_bytes); },
+            1 => { let _ = write!(buffer, "\x1b[{}m{:>4.0} Kb", color, pretty_bytes); },
+            0 => { let _ = write!(buffer, "\x1b[{}m{:>5.0} b", color, pretty_bytes); },
             _ => {
-                let _ = write!(buffer, "{}{:>4.1} {}", color, pretty_bytes, UNITS[exponent as usize]);
+                let _ = write!(buffer, "\x1b[{}m{:>4.1} {}", color, pretty_bytes, UNITS[exponent as usize]);
             },
         };
     } else {
-        let _ = write!(buffer, "{}  {:>5}", color, "-");
+        let _ = write!(buffer, "\x1b[{}m  {:>5}", color, "-");
     }
 }
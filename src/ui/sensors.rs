@@ -2,6 +2,7 @@ use std::io::Write;
 use anyhow::{ bail, Result };
 
 use crate::system::System;
+use crate::settings::Colors;
 use super::XY;
 
 pub struct Sensors <'a> {
@@ -9,12 +10,13 @@ pub struct Sensors <'a> {
     pub pos: XY,
     pub size: XY,
 
+    colors: Colors,
     cache: Vec::<(String, u8)>,
 }
 
 impl <'a> Sensors <'a> {
-    pub fn new(system: &'a System, pos: XY) -> Self {
-        let y = if let Ok(sensorinfo) = system.sensorinfo.lock() {
+    pub fn new(system: &'a System, pos: XY, colors: &Colors) -> Self {
+        let y = if let Ok(sensorinfo) = system.sensorinfo().lock() {
             sensorinfo.chips.len() as u16 + 2
         } else {
             2
@@ -22,6 +24,7 @@ impl <'a> Sensors <'a> {
 
         Self {
             system,
+            colors: colors.clone(),
             cache: Vec::new(),
             pos,
             size: XY { x: 22, y }
@@ -29,7 +32,9 @@ impl <'a> Sensors <'a> {
     }
 
     pub fn rebuild_cache (&mut self) -> Result<()> {
-        if let Ok(sensorinfo) = self.system.sensorinfo.lock() {
+        let Colors { title, text, value, accent, .. } = &self.colors;
+
+        if let Ok(sensorinfo) = self.system.sensorinfo().lock() {
             self.size.y = sensorinfo.chips.len() as u16 + 2;
 
             self.cache.clear();
@@ -39,7 +44,7 @@ impl <'a> Sensors <'a> {
                     self.cache.push(
                         (
                             format!(
-                                "\x1b[{};{}H\x1b[95mSensors\x1b[0m\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[37m{}\x1b[{};{}H\x1b[91m[ \x1b[92m",
+                                "\x1b[{};{}H\x1b[{title}mSensors\x1b[0m\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[{text}m{}\x1b[{};{}H\x1b[{accent}m[ \x1b[{value}m",
                                 self.pos.y, self.pos.x,
                                 y, self.pos.x + 23,
                                 y, self.pos.x,
@@ -53,7 +58,7 @@ impl <'a> Sensors <'a> {
                     self.cache.push(
                         (
                             format!(
-                                "\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[37m{}\x1b[{};{}H\x1b[91m[ \x1b[92m",
+                                "\x1b[{};{}H\x1b[1K\x1b[{};{}H\x1b[{text}m{}\x1b[{};{}H\x1b[{accent}m[ \x1b[{value}m",
                                 y, self.pos.x + 23,
                                 y, self.pos.x,
                                 key,
@@ -72,7 +77,9 @@ impl <'a> Sensors <'a> {
     }
 
     pub fn draw (&mut self, buffer: &mut Vec::<u8>) -> Result<bool> {
-        if let Ok(sensorinfo) = self.system.sensorinfo.lock() {
+        let accent = &self.colors.accent;
+
+        if let Ok(sensorinfo) = self.system.sensorinfo().lock() {
             // Trigger cache rebuild if lengths aren't equal
             if self.cache.len() != sensorinfo.chips.len() {
                 return Ok(true);
@@ -83,7 +90,7 @@ impl <'a> Sensors <'a> {
 
                 // Don't update the value if it hasn't changed
                 if cache.1 != *val {
-                    let _ = write!(buffer, "{}{} C\x1b[91m ]\x1b[0m", &cache.0, val);
+                    let _ = write!(buffer, "{}{} C\x1b[{accent}m ]\x1b[0m", &cache.0, val);
                     cache.1 = *val;
                 }
             }
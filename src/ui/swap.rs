@@ -3,6 +3,7 @@ use std::fmt::Write as fmtWrite;
 use anyhow::{ bail, Result };
 
 use crate::system::System;
+use crate::settings::Colors;
 use super::{ XY, convert_with_padding };
 
 pub struct Swap <'a> {
@@ -10,7 +11,8 @@ pub struct Swap <'a> {
     pub pos: XY,
     pub size: XY,
 
-    cache: (String, String, String),
+    colors: Colors,
+    cache: (String, String, String, String),
     buffer: (String, String, String),
 
     total: u64,
@@ -18,10 +20,11 @@ pub struct Swap <'a> {
 }
 
 impl <'a> Swap <'a> {
-    pub fn new(system: &'a System, pos: XY) -> Self {
+    pub fn new(system: &'a System, pos: XY, colors: &Colors) -> Self {
         Self {
             system,
-            cache: (String::new(), String::new(), String::new()),
+            colors: colors.clone(),
+            cache: (String::new(), String::new(), String::new(), String::new()),
             buffer: (String::new(), String::new(), String::new()),
             total: 0,
             free: 0,
@@ -31,9 +34,11 @@ impl <'a> Swap <'a> {
     }
 
     pub fn rebuild_cache (&mut self) {
+        let Colors { title, text, value, accent, dim, .. } = &self.colors;
+
         self.cache.0.clear();
         let _ = write!(self.cache.0,
-            "\x1b[{};{}H\x1b[95mSwap\x1b[0m\x1b[{};{}H                  \x1b[{};{}H\x1b[37mTotal: \x1b[38;5;244m[ \x1b[37m",
+            "\x1b[{};{}H\x1b[{title}mSwap\x1b[0m\x1b[{};{}H                  \x1b[{};{}H\x1b[{text}mTotal: \x1b[{dim}m[ \x1b[{text}m",
             self.pos.y, self.pos.x,
             self.pos.y+1, self.pos.x,
             self.pos.y+1, self.pos.x,
@@ -41,22 +46,25 @@ impl <'a> Swap <'a> {
 
         self.cache.1.clear();
         let _ = write!(self.cache.1,
-            "\x1b[38;5;244m ]\x1b[0m\x1b[{};{}H                  \x1b[{};{}H\x1b[37mUsed:  \x1b[91m[ \x1b[92m",
+            "\x1b[{dim}m ]\x1b[0m\x1b[{};{}H                  \x1b[{};{}H\x1b[{text}mUsed:  \x1b[{accent}m[ \x1b[{value}m",
             self.pos.y+2, self.pos.x,
             self.pos.y+2, self.pos.x,
         );
 
         self.cache.2.clear();
         let _ = write!(self.cache.2,
-            "\x1b[91m ]\x1b[0m\x1b[{};{}H                  \x1b[{};{}H\x1b[37mFree:  \x1b[38;5;244m[ \x1b[37m",
+            "\x1b[{accent}m ]\x1b[0m\x1b[{};{}H                  \x1b[{};{}H\x1b[{text}mFree:  \x1b[{dim}m[ \x1b[{text}m",
             self.pos.y+3, self.pos.x,
             self.pos.y+3, self.pos.x,
         );
 
+        self.cache.3.clear();
+        let _ = write!(self.cache.3, "\x1b[{dim}m ]\x1b[0m");
+
     }
 
     pub fn draw (&mut self, buffer: &mut Vec::<u8>) -> Result<()> {
-        if let Ok(val) = self.system.memoryinfo.lock() {
+        if let Ok(val) = self.system.memoryinfo().lock() {
             //if self.total != val.swap_total {
                 self.total = val.swap_total;
                 convert_with_padding(&mut self.buffer.0, val.swap_total);
@@ -78,7 +86,7 @@ impl <'a> Swap <'a> {
             std::io::IoSlice::new(self.buffer.1.as_bytes()),
             std::io::IoSlice::new(self.cache.2.as_bytes()),
             std::io::IoSlice::new(self.buffer.2.as_bytes()),
-            std::io::IoSlice::new(b"\x1b[38;5;244m ]\x1b[0m")
+            std::io::IoSlice::new(self.cache.3.as_bytes())
         ]);
 
         Ok(())
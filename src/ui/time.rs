@@ -45,9 +45,9 @@ impl <'a> Time <'a> {
     }
 
     fn gettime(&mut self) -> String {
-        let current_time = self.system.time.time.load(std::sync::atomic::Ordering::Acquire);
+        let current_time = self.system.timeinfo().lock().map_or(0, |time| time.time);
 
-        let time_string = libc_strftime::strftime_local(&self.system.config.strftime_format, current_time as i64);
+        let time_string = libc_strftime::strftime_local(&self.system.config().strftime_format, current_time as i64);
         let length = time_string.len() as u16;
 
         if self.size.x != length {